@@ -1,10 +1,135 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Index, LitStr};
 
+/// Derives `From<T> for interprete_rs::blisp::interpreter::Value` for a struct, converting it to
+/// a tuple with one element per field (in declaration order). Every field type needs to implement
+/// `BLispType` and `Into<Value>`, which covers `u64, i64, f64, u8, bool, String`, `Vec<T>` of
+/// those, and other structs deriving `IntoValue`
+///
+/// # Examples
+/// ```ignore
+/// #[derive(IntoValue)]
+/// struct Point {
+///     x: i64,
+///     y: i64,
+/// }
+///
+/// // Has type `tuple<int, int>`
+/// let val = Value::from(Point { x: 1, y: -2 });
+/// ```
+#[proc_macro_derive(IntoValue)]
+pub fn derive_into_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    into_value_impl(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn into_value_impl(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = input.ident;
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            input.generics,
+            "IntoValue can't be derived for generic structs",
+        ));
+    }
+
+    let fields = match input.data {
+        Data::Struct(data) => data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "IntoValue can only be derived for structs",
+            ))
+        }
+    };
+
+    // Field accessors, `value.name` for named fields and `value.0` for tuple structs
+    let accessors: Vec<_> = match &fields {
+        Fields::Named(named) => named
+            .named
+            .iter()
+            .map(|f| {
+                let ident = f.ident.as_ref().unwrap();
+                quote!(#ident)
+            })
+            .collect(),
+        Fields::Unnamed(unnamed) => (0..unnamed.unnamed.len())
+            .map(|i| {
+                let index = Index::from(i);
+                quote!(#index)
+            })
+            .collect(),
+        Fields::Unit => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "IntoValue can't be derived for unit structs",
+            ))
+        }
+    };
+    let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+
+    Ok(quote! {
+        impl ::interprete_rs::blisp::interpreter::BLispType for #name {
+            fn blisp_type() -> ::interprete_rs::blisp::lexer::Type {
+                ::interprete_rs::blisp::lexer::Type::Tuple(::std::vec![
+                    #(<#types as ::interprete_rs::blisp::interpreter::BLispType>::blisp_type()),*
+                ])
+            }
+        }
+
+        impl ::std::convert::From<#name> for ::interprete_rs::blisp::interpreter::Value {
+            fn from(value: #name) -> Self {
+                Self::new(
+                    <#name as ::interprete_rs::blisp::interpreter::BLispType>::blisp_type().into(),
+                    ::interprete_rs::blisp::interpreter::ValueData::Tuple(::std::vec![
+                        #(::interprete_rs::blisp::interpreter::Value::from(value.#accessors)),*
+                    ]),
+                )
+            }
+        }
+    })
+}
+
+/// Checks at compile time that the brackets in a string literal of Brainfuck code are balanced,
+/// expanding to the literal itself (a `&'static str`). On a mismatch this emits a compile error
+/// giving the character position of the offending bracket
+///
+/// # Examples
+/// ```ignore
+/// let code: &str = brainfuck!("+[->+<]");
+/// ```
+#[proc_macro]
+pub fn brainfuck(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+
+    match check_brackets(&lit.value()) {
+        Ok(()) => quote!(#lit).into(),
+        Err(msg) => syn::Error::new(lit.span(), msg).into_compile_error().into(),
+    }
+}
+
+fn check_brackets(code: &str) -> Result<(), String> {
+    // Positions of the currently unmatched `[`
+    let mut stack = Vec::new();
+
+    for (i, c) in code.chars().enumerate() {
+        match c {
+            '[' => stack.push(i),
+            ']' => {
+                if stack.pop().is_none() {
+                    return Err(format!("Unmatched `]` at position {}", i));
+                }
+            }
+            _ => (),
+        }
+    }
 
-    #[test]
-    fn it_works() {
+    match stack.first() {
+        Some(i) => Err(format!("Unmatched `[` at position {}", i)),
+        None => Ok(()),
     }
 }
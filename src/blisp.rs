@@ -3,5 +3,41 @@ pub mod parser;
 pub mod macros;
 pub mod interpreter;
 pub mod functions;
+pub mod compiler;
 
+use crate::error::InterpreteResult;
 
+pub use lexer::{classify_tokens, tokenize_with_trivia, TokenKind, Trivia};
+
+/// Checks whether `src` is a valid BLisp program by lexing and parsing it, without evaluating
+/// anything. Returns the first error found, so this is a cheap check with no side effects, e.g.
+/// for an editor
+pub fn validate(src: &str) -> InterpreteResult<()> {
+    let tokens = lexer::tokenize(src.chars().collect())?;
+    parser::parse_prog(&tokens)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_test() {
+        assert!(validate("(+ 1 (- 3 2))").is_ok());
+        assert!(validate("[1 2 3]").is_ok());
+
+        // Only parsed, so type errors that would come up in evaluation are fine
+        assert!(validate("(+ 1 'c')").is_ok());
+
+        assert_eq!(
+            validate("(+ 1 (- 3 2)").unwrap_err().to_string(),
+            "Unmatched ( opened at token 0, expected ) but found EOF at token 8"
+        );
+        assert_eq!(
+            validate("(+ 1 'ab')").unwrap_err().to_string(),
+            "Did not find closing ' where expected while processing a char literal"
+        );
+    }
+}
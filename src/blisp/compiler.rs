@@ -0,0 +1,484 @@
+//! Lowers a parsed program into a flat list of instructions for a small stack machine. A program
+//! that's run many times, e.g. with different variables set in the State each time, is only
+//! walked once this way instead of on every evaluation
+
+use std::mem;
+
+use crate::{
+    blisp::{
+        functions::eval_function,
+        interpreter::{
+            flatten_args_node, infer_list_element_type, parse_fn_node, Argument, ArgumentType,
+            Closure, State, Value, ValueData,
+        },
+        macros::{leaf_node_pattern, rule_node_pattern},
+    },
+    error::InterpreteResult,
+};
+
+use super::{
+    lexer::{ReservedIdent, Type},
+    parser::{Node, ParseToken, Rule, RuleNodeData},
+};
+
+/// A single step of a compiled program. Instructions take their inputs off the top of a stack of
+/// arguments and push their result back onto it
+#[derive(Debug, PartialEq, Clone)]
+pub enum Instruction {
+    /// Pushes a value that's known when compiling, like a literal
+    Push(Value),
+    /// Pushes a type or identifier argument, e.g. the `int` in `(init x int)`
+    PushArg(Argument),
+    /// Pushes the value of a variable
+    Load(String),
+    /// Pushes a closure created with `fn`, capturing the variables it uses, see
+    /// `Closure::capture`
+    MakeClosure(Closure),
+    /// Pops `len` values and pushes a list of them. `path` is the index of the list in each
+    /// enclosing list literal, see `infer_list_element_type`
+    MakeList { len: usize, path: Vec<usize> },
+    /// Pops `argc` arguments and pushes the result of calling a reserved function with them
+    Call(ReservedIdent, usize),
+    /// Pops `argc` arguments and pushes the result of calling a registered function or a closure,
+    /// see `State::call_ident`
+    CallIdent(String, usize),
+    /// Pops a value and binds it to a variable until the matching `Unbind`, shadowing any
+    /// variable with the same name
+    Bind(String),
+    /// Undoes the most recent `Bind` that hasn't been undone yet
+    Unbind,
+}
+
+/// A program lowered by `compile`, ready to be run with `execute`
+#[derive(Debug, PartialEq, Clone)]
+pub struct CompiledProgram {
+    instructions: Vec<Instruction>,
+}
+
+impl CompiledProgram {
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+}
+
+/// Compiles a program, as returned by `parse_prog`. So far this covers literals, lists,
+/// variables, `let`, `fn`, and calls to reserved or registered functions, other special forms
+/// (`times`, `try`, and `assert`) are an error
+pub fn compile(node: &Node) -> InterpreteResult<CompiledProgram> {
+    let mut compiler = Compiler::default();
+
+    if let rule_node_pattern!(Prog; children) = node {
+        assert!(children.len() == 1);
+
+        match &children[0] {
+            node @ rule_node_pattern!(Val) => compiler.compile_val(node)?,
+            node => compiler.compile_expr(node)?,
+        }
+    } else {
+        return Err(format!("Expected Prog node, found: {:?}", node).into());
+    }
+
+    Ok(CompiledProgram {
+        instructions: compiler.instructions,
+    })
+}
+
+/// Runs a compiled program, giving the same result as `eval_with_state` on the tree it was
+/// compiled from. Tracing isn't supported, so a trace set with `State::set_trace` stays empty
+pub fn execute(program: &CompiledProgram, state: &mut State) -> InterpreteResult<Value> {
+    let mut stack = Vec::new();
+    // The variable each `Bind` shadowed, to be restored by the matching `Unbind`
+    let mut shadowed = Vec::new();
+
+    let res = run(&program.instructions, state, &mut stack, &mut shadowed);
+
+    // Only left over if the program failed part way through a `let`
+    while let Some((name, prev)) = shadowed.pop() {
+        state.unbind_var(&name, prev);
+    }
+    res?;
+
+    match stack.as_slice() {
+        [Argument::Value(val)] => Ok(val.clone().resolve_with(state.number_default())),
+        _ => Err(format!("Malformed program left {:?} on the stack", stack).into()),
+    }
+}
+
+type Shadowed = Vec<(String, Option<Option<Value>>)>;
+
+fn run(
+    instructions: &[Instruction],
+    state: &mut State,
+    stack: &mut Vec<Argument>,
+    shadowed: &mut Shadowed,
+) -> InterpreteResult<()> {
+    for inst in instructions {
+        match inst {
+            Instruction::Push(val) => stack.push(val.clone().into()),
+            Instruction::PushArg(arg) => stack.push(arg.clone()),
+            Instruction::Load(name) => stack.push(state.get_var(name)?.clone().into()),
+            Instruction::MakeClosure(closure) => stack.push(closure.capture(state).into()),
+            Instruction::MakeList { len, path } => {
+                let vals = pop_args(stack, *len)?
+                    .iter()
+                    .map(|arg| arg.try_get_val().cloned())
+                    .collect::<InterpreteResult<Vec<_>>>()?;
+                let ty = infer_list_element_type(&vals, path, state.number_default())?;
+
+                stack.push(
+                    Value::new(Type::List(Box::new(ty)).into(), ValueData::List(vals)).into(),
+                );
+            }
+            Instruction::Call(func, argc) => {
+                let args = pop_args(stack, *argc)?;
+                stack.push(eval_function(*func, args, state)?.into());
+            }
+            Instruction::CallIdent(name, argc) => {
+                let args = pop_args(stack, *argc)?;
+                stack.push(state.call_ident(name, args)?.into());
+            }
+            Instruction::Bind(name) => {
+                let val = pop_args(stack, 1)?.remove(0).try_get_val()?.clone();
+                shadowed.push((name.clone(), state.bind_var(name.clone(), val)));
+            }
+            Instruction::Unbind => {
+                let (name, prev) = shadowed.pop().ok_or("Unbind without a matching Bind")?;
+                state.unbind_var(&name, prev);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Pops the top `count` arguments, in the order they were pushed
+fn pop_args(stack: &mut Vec<Argument>, count: usize) -> InterpreteResult<Vec<Argument>> {
+    let start = stack
+        .len()
+        .checked_sub(count)
+        .ok_or("Malformed program, ran out of arguments on the stack")?;
+
+    Ok(stack.split_off(start))
+}
+
+#[derive(Default)]
+struct Compiler {
+    instructions: Vec<Instruction>,
+    /// Index of the list literal being compiled in each enclosing list literal, like
+    /// `State::list_path` is for evaluation
+    list_path: Vec<usize>,
+}
+
+impl Compiler {
+    fn compile_expr(&mut self, node: &Node) -> InterpreteResult<()> {
+        match node {
+            rule_node_pattern!(Expr; children) if children.len() == 1 => match &children[0] {
+                rule_node_pattern!(ExprBody; children) if children.len() == 1 => {
+                    match &children[0] {
+                        node @ rule_node_pattern!(Val) => self.compile_val(node),
+                        node @ rule_node_pattern!(FuncCall) => self.compile_func_call(node),
+                        node => Err(format!(
+                            "Encountered unexpected node when compiling expression body: {:?}",
+                            node
+                        )
+                        .into()),
+                    }
+                }
+                node => Err(format!("Expected ExprBody node, found: {:?}", node).into()),
+            },
+            node => Err(format!("Expected Expr node, found: {:?}", node).into()),
+        }
+    }
+
+    fn compile_val(&mut self, node: &Node) -> InterpreteResult<()> {
+        if let rule_node_pattern!(Val; children) = node {
+            assert!(children.len() == 1);
+
+            match &children[0] {
+                leaf_node_pattern!(Ident(name)) => {
+                    self.instructions.push(Instruction::Load(name.clone()))
+                }
+                Node::Leaf(
+                    tok @ (ParseToken::NumLiteral(_)
+                    | ParseToken::CharLiteral(_)
+                    | ParseToken::UCharLiteral(_)
+                    | ParseToken::UnitLiteral
+                    | ParseToken::StringLiteral(_)),
+                ) => self
+                    .instructions
+                    .push(Instruction::Push(Value::try_from(tok.clone())?)),
+                Node::Leaf(t) => {
+                    return Err(format!("Expected literal or identifier, found {:?}", t).into())
+                }
+                node @ rule_node_pattern!(List) => self.compile_list(node)?,
+                node @ rule_node_pattern!(Expr) => self.compile_expr(node)?,
+                n => {
+                    return Err(
+                        format!("Encountered invalid node when compiling Val: {:?}", n).into(),
+                    )
+                }
+            }
+
+            Ok(())
+        } else {
+            Err(format!("Expected Val node, found: {:?}", node).into())
+        }
+    }
+
+    fn compile_list(&mut self, node: &Node) -> InterpreteResult<()> {
+        let children = match node {
+            rule_node_pattern!(List; children) => children,
+            _ => return Err(format!("Expected List node, found: {:?}", node).into()),
+        };
+
+        let vals = match children.first() {
+            Some(rule_node_pattern!(ListBody; vals)) => vals,
+            Some(n) => return Err(format!("Expected ListBody node, found: {:?}", n).into()),
+            None => {
+                self.instructions
+                    .push(Instruction::Push(Value::list_from([])?));
+                return Ok(());
+            }
+        };
+
+        for (i, val) in vals.iter().enumerate() {
+            self.list_path.push(i);
+            let res = self.compile_val(val);
+            self.list_path.pop();
+
+            res?;
+        }
+
+        self.instructions.push(Instruction::MakeList {
+            len: vals.len(),
+            path: self.list_path.clone(),
+        });
+
+        Ok(())
+    }
+
+    fn compile_func_call(&mut self, node: &Node) -> InterpreteResult<()> {
+        let (func_node, args_node) = match node {
+            rule_node_pattern!(FuncCall; children) if children.len() == 2 => {
+                (&children[0], &children[1])
+            }
+            _ => return Err(format!("Expected FuncCall node, found: {:?}", node).into()),
+        };
+
+        // Lists in the arguments aren't sublists of a list the call is in, so they start over
+        let list_path = mem::take(&mut self.list_path);
+        let res = self.compile_call(func_node, args_node);
+        self.list_path = list_path;
+
+        res
+    }
+
+    fn compile_call(&mut self, func_node: &Node, args_node: &Node) -> InterpreteResult<()> {
+        let args = flatten_args_node(args_node)?;
+
+        match func_node {
+            leaf_node_pattern!(Reserved(ReservedIdent::Fn)) => {
+                let closure = parse_fn_node(args_node)?;
+                self.instructions.push(Instruction::MakeClosure(closure));
+            }
+            leaf_node_pattern!(Reserved(ReservedIdent::Let)) => {
+                let (name, val, body) = match args.as_slice() {
+                    [name, val, body] => (*name, *val, *body),
+                    _ => {
+                        return Err(format!(
+                            "{} expects 3 arguments of types {:?}, got {}",
+                            ReservedIdent::Let,
+                            ReservedIdent::Let.arg_types(),
+                            args.len()
+                        )
+                        .into())
+                    }
+                };
+                let name = match name {
+                    rule_node_pattern!(Val; children) if children.len() == 1 => {
+                        match &children[0] {
+                            leaf_node_pattern!(Ident(i)) => i,
+                            n => {
+                                return Err(
+                                    format!("Expected a variable name, found: {:?}", n).into()
+                                )
+                            }
+                        }
+                    }
+                    n => return Err(format!("Expected a variable name, found: {:?}", n).into()),
+                };
+
+                self.compile_val(val)?;
+                self.instructions.push(Instruction::Bind(name.clone()));
+                self.compile_val(body)?;
+                self.instructions.push(Instruction::Unbind);
+            }
+            leaf_node_pattern!(
+                Reserved(rsv @ (ReservedIdent::Times | ReservedIdent::Try | ReservedIdent::Assert))
+            ) => return Err(format!("{} can't be compiled yet", rsv).into()),
+            leaf_node_pattern!(Reserved(rsv)) => {
+                let arg_types = rsv.arg_types();
+
+                for (i, arg) in args.iter().enumerate() {
+                    self.compile_arg(arg, arg_types.get(i).copied())?;
+                }
+                self.instructions.push(Instruction::Call(*rsv, args.len()));
+            }
+            leaf_node_pattern!(Ident(name)) => {
+                for arg in &args {
+                    self.compile_arg(arg, None)?;
+                }
+                self.instructions
+                    .push(Instruction::CallIdent(name.clone(), args.len()));
+            }
+            n => return Err(format!("Expected function name, found {:?}", n).into()),
+        }
+
+        Ok(())
+    }
+
+    /// Like `eval_arg_node`, types and identifiers in a position that expects one are passed
+    /// through as they are
+    fn compile_arg(&mut self, node: &Node, expected: Option<ArgumentType>) -> InterpreteResult<()> {
+        if let rule_node_pattern!(Val; children) = node {
+            match children.as_slice() {
+                [leaf_node_pattern!(Type(ty))] => {
+                    self.instructions
+                        .push(Instruction::PushArg(Argument::Type(ty.clone())));
+                    return Ok(());
+                }
+                [leaf_node_pattern!(Ident(name))] if expected == Some(ArgumentType::Ident) => {
+                    self.instructions
+                        .push(Instruction::PushArg(Argument::Ident(name.clone())));
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+
+        self.compile_val(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blisp::{
+            interpreter::{eval_with_state, NumberDefault},
+            lexer::tokenize,
+            parser::parse_prog,
+        },
+        error::InterpreTestResult,
+    };
+
+    fn parse(input: &str) -> InterpreteResult<Node> {
+        let tokens = tokenize(input.chars().collect())?;
+
+        Ok(parse_prog(&tokens)?.0)
+    }
+
+    #[test]
+    fn matches_eval_test() -> InterpreTestResult {
+        let inputs = [
+            "(+ 1 (* 2 3))",
+            "(- 10 (div 9 3))",
+            "(/ 7.5 2)",
+            "[1 2u 3]",
+            "[[1 2] [] [3]]",
+            "(concat \"ab\" \"cd\")",
+            "(let x 5 (let y (+ x 1) [x y]))",
+            "(let x 1 (add (let x 10 x) x))",
+            "(fold (fn acc n (+ acc n)) 0 (range 1 5))",
+            "(let f (fn n (* n n)) (f 4))",
+            "(fold (let x 100 (fn a b (+ a x))) 0 [1])",
+            "(do (write \"hi\") 'é')",
+            "(tostringradix (shl 1 8) 16u)",
+            "nil",
+            "[]",
+        ];
+
+        for input in inputs {
+            let node = parse(input)?;
+
+            let mut out = Vec::new();
+            let mut state = State::with_writer(&mut out);
+            let exp = eval_with_state(&node, &mut state)?;
+            drop(state);
+
+            let mut compiled_out = Vec::new();
+            let mut state = State::with_writer(&mut compiled_out);
+            let val = execute(&compile(&node)?, &mut state)?;
+            drop(state);
+
+            assert_eq!(val, exp, "{}", input);
+            assert_eq!(compiled_out, out, "{}", input);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn matching_errors_test() -> InterpreTestResult {
+        let inputs = [
+            "[[1 2] [3 'a']]",
+            "(+ 1 'a')",
+            "(let x 5 y)",
+            "(div 1 0)",
+            "(undefined 1)",
+        ];
+
+        for input in inputs {
+            let node = parse(input)?;
+
+            let exp = eval_with_state(&node, &mut State::new()).unwrap_err();
+            let err = execute(&compile(&node)?, &mut State::new()).unwrap_err();
+
+            assert_eq!(err.to_string(), exp.to_string(), "{}", input);
+        }
+
+        assert_eq!(
+            compile(&parse("(times 3 (write \"x\"))")?)
+                .unwrap_err()
+                .to_string(),
+            "times can't be compiled yet"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rerun_test() -> InterpreTestResult {
+        let program = compile(&parse("(let sq (* x x) [x sq])")?)?;
+        assert_eq!(
+            program.instructions()[0],
+            Instruction::Load("x".to_string())
+        );
+
+        // The same program is run with a different input each time
+        for x in [1i64, 2, -3] {
+            let mut state = State::new();
+            state.create_var("x".to_string(), Some(x.into()))?;
+
+            assert_eq!(execute(&program, &mut state)?, Value::from(vec![x, x * x]));
+
+            // Variables bound by the program are gone afterwards, even if it fails
+            assert!(state.get_var("sq").is_err());
+        }
+
+        let program = compile(&parse("(let sq (* x x) (div sq 0))")?)?;
+        let mut state = State::new();
+        state.create_var("x".to_string(), Some(2i64.into()))?;
+        assert!(execute(&program, &mut state).is_err());
+        assert!(state.get_var("sq").is_err());
+
+        let mut state = State::new();
+        state.set_number_default(NumberDefault::UInt);
+        assert_eq!(
+            execute(&compile(&parse("(+ 1 2)")?)?, &mut state)?,
+            Value::from(3u64)
+        );
+
+        Ok(())
+    }
+}
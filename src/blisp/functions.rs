@@ -1,58 +1,117 @@
+use std::{cmp::Ordering, fmt::Display, mem};
+
 use crate::{
     blisp::{
-        interpreter::{AbstractType, ValueData},
+        interpreter::{AbstractType, ArithmeticMode, ValueData},
         lexer::Type,
     },
     error::InterpreteResult,
 };
 
 use super::{
-    interpreter::{Argument, ArgumentType, State, Value},
+    interpreter::{call_closure, infer_list_element_type, Argument, ArgumentType, State, Value},
     lexer::ReservedIdent,
 };
 
-pub fn eval_function(func: ReservedIdent, args: Vec<Argument>) -> InterpreteResult<Value> {
-    assert_eq!(
-        args.iter().map(Argument::get_type).collect::<Vec<_>>(),
-        get_arg_types(func)
-    );
+pub fn eval_function(
+    func: ReservedIdent,
+    args: Vec<Argument>,
+    state: &mut State,
+) -> InterpreteResult<Value> {
+    check_args(func, &args)?;
 
     match func {
-        ReservedIdent::Add => eval_add(args),
+        ReservedIdent::Add => eval_add(args, state.arithmetic_mode()),
+        ReservedIdent::Sub => eval_sub(args, state.arithmetic_mode()),
+        ReservedIdent::Mul => eval_mul(args, state.arithmetic_mode()),
+        ReservedIdent::Div => eval_div(args, state.arithmetic_mode()),
+        ReservedIdent::Min => eval_min(args),
+        ReservedIdent::Max => eval_max(args),
+        ReservedIdent::Abs => eval_abs(args),
+        ReservedIdent::ToInt => eval_toint(args),
+        ReservedIdent::ToUInt => eval_touint(args),
+        ReservedIdent::ToFloat => eval_tofloat(args),
+        ReservedIdent::ToFloat32 => eval_tofloat32(args),
+        ReservedIdent::Ord => eval_ord(args),
+        ReservedIdent::Chr => eval_chr(args),
+        ReservedIdent::Eq => eval_eq(args),
+        ReservedIdent::Neq => eval_neq(args),
+        ReservedIdent::Lt => eval_cmp(args, Ordering::is_lt),
+        ReservedIdent::Gt => eval_cmp(args, Ordering::is_gt),
+        ReservedIdent::Leq => eval_cmp(args, Ordering::is_le),
+        ReservedIdent::Geq => eval_cmp(args, Ordering::is_ge),
+        ReservedIdent::BAnd => eval_bitwise(args, func, (|a, b| a & b, |a, b| a & b)),
+        ReservedIdent::BOr => eval_bitwise(args, func, (|a, b| a | b, |a, b| a | b)),
+        ReservedIdent::BXor => eval_bitwise(args, func, (|a, b| a ^ b, |a, b| a ^ b)),
+        ReservedIdent::Shl | ReservedIdent::Shr => eval_shift(args, func),
+        ReservedIdent::Concat => eval_concat(args, state),
+        ReservedIdent::Prepend => eval_prepend(args, state),
+        ReservedIdent::Contains => eval_contains(args),
+        ReservedIdent::IsEmpty => eval_isempty(args),
+        ReservedIdent::Head => eval_head(args),
+        ReservedIdent::Tail => eval_tail(args),
+        ReservedIdent::Range => eval_range(args, state),
+        ReservedIdent::Zip => eval_zip(args),
+        ReservedIdent::Sort => eval_sort(args),
+        ReservedIdent::Join => eval_join(args),
+        ReservedIdent::Flatten => eval_flatten(args),
+        ReservedIdent::Write => eval_write(args, state),
+        ReservedIdent::ReadLine => eval_readline(args, state),
+        ReservedIdent::Do => eval_do(args),
+        ReservedIdent::Fold => eval_fold(args, state),
+        ReservedIdent::Filter => eval_filter(args, state),
+        ReservedIdent::Partition => eval_partition(args, state),
+        ReservedIdent::Assert => eval_assert(args, None),
+        ReservedIdent::ToStringRadix => eval_tostring_radix(args),
+        ReservedIdent::ToStringPrec => eval_tostring_prec(args),
         _ => unimplemented!(),
     }
 }
 
 pub fn get_arg_types(func: ReservedIdent) -> Vec<ArgumentType> {
-    match func {
-        ReservedIdent::Add
-        | ReservedIdent::Sub
-        | ReservedIdent::Div
-        | ReservedIdent::Mul
-        | ReservedIdent::Eq
-        | ReservedIdent::Neq
-        | ReservedIdent::Leq
-        | ReservedIdent::Geq
-        | ReservedIdent::Lt
-        | ReservedIdent::Gt
-        | ReservedIdent::And
-        | ReservedIdent::Concat
-        | ReservedIdent::While
-        | ReservedIdent::Or
-        | ReservedIdent::Take
-        | ReservedIdent::Prepend => vec![ArgumentType::Value; 2],
+    func.arg_types()
+}
 
-        ReservedIdent::Write
-        | ReservedIdent::Read
-        | ReservedIdent::Eval
-        | ReservedIdent::ToString => vec![ArgumentType::Value],
+/// Checks the number and kind of the arguments against what the function accepts, see
+/// `ReservedIdent::arg_types`
+fn check_args(func: ReservedIdent, args: &[Argument]) -> InterpreteResult<()> {
+    let mut arg_types = get_arg_types(func);
+    let actual: Vec<_> = args.iter().map(Argument::get_type).collect();
 
-        ReservedIdent::Set | ReservedIdent::Def => vec![ArgumentType::Ident, ArgumentType::Value],
+    let count_matches = if func.is_variadic() {
+        actual.len() >= arg_types.len()
+    } else {
+        actual.len() == arg_types.len()
+    };
+
+    if !count_matches {
+        return Err(format!(
+            "{} expects {}{} argument{} of types {:?}, got {}",
+            func,
+            if func.is_variadic() { "at least " } else { "" },
+            arg_types.len(),
+            if arg_types.len() == 1 { "" } else { "s" },
+            arg_types,
+            actual.len()
+        )
+        .into());
+    }
 
-        ReservedIdent::Init => vec![ArgumentType::Ident, ArgumentType::Type],
+    if func.is_variadic() {
+        // Repeat the last type to cover the extra arguments
+        let last = *arg_types.last().unwrap();
+        arg_types.resize(actual.len(), last);
+    }
 
-        ReservedIdent::If => vec![ArgumentType::Value; 3],
+    if actual != arg_types {
+        return Err(format!(
+            "{} expects arguments of types {:?}, got {:?}",
+            func, arg_types, actual
+        )
+        .into());
     }
+
+    Ok(())
 }
 
 macro_rules! result_value_helper {
@@ -70,33 +129,63 @@ macro_rules! result_value_helper {
     }};
 }
 
-pub fn eval_add(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+/// The checked, wrapping, and saturating versions of an integer operation
+type IntOps<T> = (fn(T, T) -> Option<T>, fn(T, T) -> T, fn(T, T) -> T);
+
+/// A bitwise operation for unsigned and signed integers, see `eval_bitwise`
+type BitOps = (fn(u64, u64) -> u64, fn(i64, i64) -> i64);
+
+/// Applies an integer operation according to `mode`. `symbol` is only used for the overflow
+/// error
+fn int_arith<T: Display + Copy>(
+    mode: ArithmeticMode,
+    (a, b): (T, T),
+    ops: IntOps<T>,
+    symbol: &str,
+) -> InterpreteResult<T> {
+    let (checked, wrapping, saturating) = ops;
+
+    match mode {
+        ArithmeticMode::Checked => checked(a, b)
+            .ok_or_else(|| format!("Integer overflow evaluating {} {} {}", a, symbol, b).into()),
+        ArithmeticMode::Wrapping => Ok(wrapping(a, b)),
+        ArithmeticMode::Saturating => Ok(saturating(a, b)),
+    }
+}
+
+pub fn eval_add(mut args: Vec<Argument>, mode: ArithmeticMode) -> InterpreteResult<Value> {
     assert!(args.len() == 2);
 
     let (arg1, arg2) = (args.pop().unwrap(), args.pop().unwrap());
 
     let ty = AbstractType::coerce_types(arg1.try_get_val_type()?, arg2.try_get_val_type()?)?;
 
+    // The arguments were popped off the end, so `(val2, val1)` is their order in the source
     let (val1, val2) = (arg1.try_get_val()?, arg2.try_get_val()?);
 
     use std::ops::Add;
 
+    let u64_ops: IntOps<u64> = (u64::checked_add, u64::wrapping_add, u64::saturating_add);
+    let i64_ops: IntOps<i64> = (i64::checked_add, i64::wrapping_add, i64::saturating_add);
+
     match ty {
-        AbstractType::Number => Ok(result_value_helper!(
-            Number,
-            try_as_number,
-            val1,
-            val2,
-            u64,
-            add
+        AbstractType::Number => Ok(Value::new(
+            AbstractType::Number,
+            ValueData::Number(int_arith(
+                mode,
+                (val2.try_as_number()?, val1.try_as_number()?),
+                u64_ops,
+                "+",
+            )?),
         )),
-        AbstractType::NegNumber => Ok(result_value_helper!(
-            NegNumber,
-            try_as_negnumber,
-            val1,
-            val2,
-            i64,
-            add
+        AbstractType::NegNumber => Ok(Value::new(
+            AbstractType::NegNumber,
+            ValueData::NegNumber(int_arith(
+                mode,
+                (val2.try_as_negnumber()?, val1.try_as_negnumber()?),
+                i64_ops,
+                "+",
+            )?),
         )),
         AbstractType::List => Err(format!(
             "Unexpectedly encountered AbstractType::List in eval step: {:?}",
@@ -104,85 +193,2273 @@ pub fn eval_add(mut args: Vec<Argument>) -> InterpreteResult<Value> {
         )
         .into()),
         AbstractType::ConcreteType(ct) => match ct {
-            Type::Int => Ok(result_value_helper!(ct; Int, try_as_int, val1, val2, i64, add)),
-            Type::UInt => Ok(result_value_helper!(ct; UInt, try_as_uint, val1, val2, u64, add)),
+            Type::Int => {
+                Ok(int_arith(mode, (val2.try_as_int()?, val1.try_as_int()?), i64_ops, "+")?.into())
+            }
+            Type::UInt => Ok(int_arith(
+                mode,
+                (val2.try_as_uint()?, val1.try_as_uint()?),
+                u64_ops,
+                "+",
+            )?
+            .into()),
             Type::Float => Ok(result_value_helper!(ct; Float, try_as_float, val1, val2, f64, add)),
+            Type::Float32 => {
+                Ok(result_value_helper!(ct; Float32, try_as_float32, val1, val2, f32, add))
+            }
+            // Chars stay chars, wrapping around like a `u8`
+            Type::Char => {
+                Ok(result_value_helper!(ct; Char, try_as_char, val1, val2, u8, wrapping_add))
+            }
             Type::Unit => Ok(Value::new(Type::Unit.into(), ValueData::Unit)),
-            Type::List(_) => unimplemented!(),
             _ => Err(format!("Unable to add values of type {:?}", ct).into()),
         },
     }
 }
 
-#[cfg(test)]
-mod tests {
+pub fn eval_sub(mut args: Vec<Argument>, mode: ArithmeticMode) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
 
-    use crate::{
-        blisp::{
-            interpreter::{eval, Argument, State, Value},
-            lexer::tokenize,
-            parser::parse_prog,
+    let (arg2, arg1) = (args.pop().unwrap(), args.pop().unwrap());
+
+    let ty = AbstractType::coerce_types(arg1.try_get_val_type()?, arg2.try_get_val_type()?)?;
+
+    let (val1, val2) = (arg1.try_get_val()?, arg2.try_get_val()?);
+
+    use std::ops::Sub;
+
+    let u64_ops: IntOps<u64> = (u64::checked_sub, u64::wrapping_sub, u64::saturating_sub);
+    let i64_ops: IntOps<i64> = (i64::checked_sub, i64::wrapping_sub, i64::saturating_sub);
+
+    match ty {
+        AbstractType::Number => {
+            let (n1, n2) = (val1.try_as_number()?, val2.try_as_number()?);
+
+            // Subtracting a larger Number gives a negative result, which is only valid as a
+            // NegNumber. Either Number can be too large for an `int`, so the result is found from
+            // the (unsigned) difference instead
+            if n1 >= n2 {
+                Ok(Value::new(AbstractType::Number, ValueData::Number(n1 - n2)))
+            } else {
+                let diff = n2 - n1;
+                let res = match mode {
+                    ArithmeticMode::Checked => 0i64
+                        .checked_sub_unsigned(diff)
+                        .ok_or_else(|| format!("Integer overflow evaluating {} - {}", n1, n2))?,
+                    ArithmeticMode::Wrapping => 0i64.wrapping_sub_unsigned(diff),
+                    ArithmeticMode::Saturating => 0i64.saturating_sub_unsigned(diff),
+                };
+
+                Ok(Value::new(
+                    AbstractType::NegNumber,
+                    ValueData::NegNumber(res),
+                ))
+            }
+        }
+        AbstractType::NegNumber => Ok(Value::new(
+            AbstractType::NegNumber,
+            ValueData::NegNumber(int_arith(
+                mode,
+                (val1.try_as_negnumber()?, val2.try_as_negnumber()?),
+                i64_ops,
+                "-",
+            )?),
+        )),
+        AbstractType::List => Err(format!(
+            "Unexpectedly encountered AbstractType::List in eval step: {:?}",
+            ty
+        )
+        .into()),
+        AbstractType::ConcreteType(ct) => match ct {
+            Type::Int => {
+                Ok(int_arith(mode, (val1.try_as_int()?, val2.try_as_int()?), i64_ops, "-")?.into())
+            }
+            Type::UInt => Ok(int_arith(
+                mode,
+                (val1.try_as_uint()?, val2.try_as_uint()?),
+                u64_ops,
+                "-",
+            )?
+            .into()),
+            Type::Float => Ok(result_value_helper!(ct; Float, try_as_float, val1, val2, f64, sub)),
+            Type::Float32 => {
+                Ok(result_value_helper!(ct; Float32, try_as_float32, val1, val2, f32, sub))
+            }
+            // Chars stay chars, wrapping around like a `u8`
+            Type::Char => {
+                Ok(result_value_helper!(ct; Char, try_as_char, val1, val2, u8, wrapping_sub))
+            }
+            Type::Unit => Ok(Value::new(Type::Unit.into(), ValueData::Unit)),
+            _ => Err(format!("Unable to subtract values of type {:?}", ct).into()),
         },
-        error::InterpreTestResult,
+    }
+}
+
+pub fn eval_mul(mut args: Vec<Argument>, mode: ArithmeticMode) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (arg2, arg1) = (args.pop().unwrap(), args.pop().unwrap());
+
+    let ty = AbstractType::coerce_types(arg1.try_get_val_type()?, arg2.try_get_val_type()?)?;
+
+    let (val1, val2) = (arg1.try_get_val()?, arg2.try_get_val()?);
+
+    use std::ops::Mul;
+
+    let u64_ops: IntOps<u64> = (u64::checked_mul, u64::wrapping_mul, u64::saturating_mul);
+    let i64_ops: IntOps<i64> = (i64::checked_mul, i64::wrapping_mul, i64::saturating_mul);
+
+    match ty {
+        AbstractType::Number => Ok(Value::new(
+            AbstractType::Number,
+            ValueData::Number(int_arith(
+                mode,
+                (val1.try_as_number()?, val2.try_as_number()?),
+                u64_ops,
+                "*",
+            )?),
+        )),
+        AbstractType::NegNumber => Ok(Value::new(
+            AbstractType::NegNumber,
+            ValueData::NegNumber(int_arith(
+                mode,
+                (val1.try_as_negnumber()?, val2.try_as_negnumber()?),
+                i64_ops,
+                "*",
+            )?),
+        )),
+        AbstractType::List => Err(format!(
+            "Unexpectedly encountered AbstractType::List in eval step: {:?}",
+            ty
+        )
+        .into()),
+        AbstractType::ConcreteType(ct) => match ct {
+            Type::Int => {
+                Ok(int_arith(mode, (val1.try_as_int()?, val2.try_as_int()?), i64_ops, "*")?.into())
+            }
+            Type::UInt => Ok(int_arith(
+                mode,
+                (val1.try_as_uint()?, val2.try_as_uint()?),
+                u64_ops,
+                "*",
+            )?
+            .into()),
+            Type::Float => Ok(result_value_helper!(ct; Float, try_as_float, val1, val2, f64, mul)),
+            Type::Float32 => {
+                Ok(result_value_helper!(ct; Float32, try_as_float32, val1, val2, f32, mul))
+            }
+            _ => Err(format!("Unable to multiply values of type {:?}", ct).into()),
+        },
+    }
+}
+
+/// Integer division truncates towards zero. Unlike the other operations, dividing two number
+/// literals (`Number` or `NegNumber`) doesn't leave the result abstract, it's always an `int`, so
+/// `(div 7 2)` is `3` and `(div -7 2)` is `-3` even with `NumberDefault::UInt`. If either operand
+/// is a float it's float division instead, so `(div 7.0 2)` is `3.5`. Dividing an integer by zero
+/// is an error in every arithmetic mode, while float division follows IEEE 754
+pub fn eval_div(mut args: Vec<Argument>, mode: ArithmeticMode) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (arg2, arg1) = (args.pop().unwrap(), args.pop().unwrap());
+
+    let ty = AbstractType::coerce_types(arg1.try_get_val_type()?, arg2.try_get_val_type()?)?;
+
+    let (val1, val2) = (arg1.try_get_val()?, arg2.try_get_val()?);
+
+    use std::ops::Div;
+
+    let u64_ops: IntOps<u64> = (u64::checked_div, u64::wrapping_div, u64::saturating_div);
+    let i64_ops: IntOps<i64> = (i64::checked_div, i64::wrapping_div, i64::saturating_div);
+
+    // The wrapping and saturating ops panic on a zero divisor, so it's checked up front
+    fn int_div<T: Display + Copy + Default + PartialEq>(
+        mode: ArithmeticMode,
+        (a, b): (T, T),
+        ops: IntOps<T>,
+    ) -> InterpreteResult<T> {
+        if b == T::default() {
+            Err(format!("Division by zero evaluating {} / {}", a, b).into())
+        } else {
+            int_arith(mode, (a, b), ops, "/")
+        }
+    }
+
+    match ty {
+        AbstractType::Number => {
+            let (n1, n2) = (val1.try_as_number()?, val2.try_as_number()?);
+            let res = int_div(mode, (n1, n2), u64_ops)?;
+
+            // Only possible when the dividend is larger than any `int`
+            i64::try_from(res)
+                .map(Value::from)
+                .map_err(|_| format!("Result of {} / {} doesn't fit in an int", n1, n2).into())
+        }
+        AbstractType::NegNumber => Ok(int_div(
+            mode,
+            (val1.try_as_negnumber()?, val2.try_as_negnumber()?),
+            i64_ops,
+        )?
+        .into()),
+        AbstractType::List => Err(format!(
+            "Unexpectedly encountered AbstractType::List in eval step: {:?}",
+            ty
+        )
+        .into()),
+        AbstractType::ConcreteType(ct) => match ct {
+            Type::Int => {
+                Ok(int_div(mode, (val1.try_as_int()?, val2.try_as_int()?), i64_ops)?.into())
+            }
+            Type::UInt => {
+                Ok(int_div(mode, (val1.try_as_uint()?, val2.try_as_uint()?), u64_ops)?.into())
+            }
+            Type::Float => Ok(result_value_helper!(ct; Float, try_as_float, val1, val2, f64, div)),
+            Type::Float32 => {
+                Ok(result_value_helper!(ct; Float32, try_as_float32, val1, val2, f32, div))
+            }
+            _ => Err(format!("Unable to divide values of type {:?}", ct).into()),
+        },
+    }
+}
+
+/// Returns whichever argument is smaller, keeping its original type. If they're equal the first
+/// is returned
+pub fn eval_min(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (val2, val1) = (args.pop().unwrap(), args.pop().unwrap());
+    let (val1, val2) = (val1.try_get_val()?, val2.try_get_val()?);
+
+    if val2.try_cmp(val1)? == Ordering::Less {
+        Ok(val2.clone())
+    } else {
+        Ok(val1.clone())
+    }
+}
+
+/// Returns whichever argument is larger, keeping its original type. If they're equal the first
+/// is returned
+pub fn eval_max(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (val2, val1) = (args.pop().unwrap(), args.pop().unwrap());
+    let (val1, val2) = (val1.try_get_val()?, val2.try_get_val()?);
+
+    if val2.try_cmp(val1)? == Ordering::Greater {
+        Ok(val2.clone())
+    } else {
+        Ok(val1.clone())
+    }
+}
+
+/// Absolute value, keeping the type of the argument. Unsigned values are returned as-is, and
+/// `i64::MIN` gives an Err since its absolute value doesn't fit
+pub fn eval_abs(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let arg = args.pop().unwrap();
+    let val = arg.try_get_val()?;
+
+    let checked_abs = |n: i64| {
+        n.checked_abs()
+            .ok_or(format!("Overflow when taking the absolute value of {}", n))
     };
 
-    use super::eval_add;
+    match arg.try_get_val_type()? {
+        AbstractType::Number => Ok(val.clone()),
+        AbstractType::NegNumber => Ok(Value::new(
+            AbstractType::NegNumber,
+            ValueData::NegNumber(checked_abs(val.try_as_negnumber()?)?),
+        )),
+        AbstractType::ConcreteType(Type::Int) => Ok(Value::new(
+            Type::Int.into(),
+            ValueData::Int(checked_abs(val.try_as_int()?)?),
+        )),
+        AbstractType::ConcreteType(Type::UInt) => Ok(val.clone()),
+        AbstractType::ConcreteType(Type::Float) => Ok(val.try_as_float()?.abs().into()),
+        AbstractType::ConcreteType(Type::Float32) => Ok(val.try_as_float32()?.abs().into()),
+        ty => Err(format!("Unable to take the absolute value of type {:?}", ty).into()),
+    }
+}
 
-    #[test]
-    fn eval_add_test() -> InterpreTestResult {
-        let args = vec![Argument::Value(1.2.into()), Argument::Value(2.5.into())];
+/// Equality after coercion, see `Value::semantic_eq`. Strings and char lists are the same type so
+/// `(eq "ab" ['a' 'b'])` is true
+pub fn eval_eq(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
 
-        let res = eval_add(args)?;
+    let (val2, val1) = (args.pop().unwrap(), args.pop().unwrap());
 
-        assert_eq!(res, Value::from(3.7));
+    Ok(val1.try_get_val()?.semantic_eq(val2.try_get_val()?)?.into())
+}
 
-        Ok(())
+pub fn eval_neq(args: Vec<Argument>) -> InterpreteResult<Value> {
+    Ok((!eval_eq(args)?.try_as_bool()?).into())
+}
+
+/// Shared implementation of the ordering functions (`lt`, `geq`, etc), `pred` decides whether the
+/// ordering of the first argument relative to the second gives true. Lists (and so strings) are
+/// ordered lexicographically, see `Value::try_cmp`
+pub fn eval_cmp(mut args: Vec<Argument>, pred: fn(Ordering) -> bool) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (val2, val1) = (args.pop().unwrap(), args.pop().unwrap());
+
+    Ok(pred(val1.try_get_val()?.try_cmp(val2.try_get_val()?)?).into())
+}
+
+/// Shared implementation of `band`, `bor`, and `bxor`, which apply the operation in `ops` for the
+/// type the arguments coerce to. Only defined for integers
+pub fn eval_bitwise(
+    mut args: Vec<Argument>,
+    func: ReservedIdent,
+    ops: BitOps,
+) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (arg2, arg1) = (args.pop().unwrap(), args.pop().unwrap());
+
+    let ty = AbstractType::coerce_types(arg1.try_get_val_type()?, arg2.try_get_val_type()?)?;
+
+    let (val1, val2) = (arg1.try_get_val()?, arg2.try_get_val()?);
+    let (u64_op, i64_op) = ops;
+
+    match ty {
+        AbstractType::Number => Ok(Value::new(
+            AbstractType::Number,
+            ValueData::Number(u64_op(val1.try_as_number()?, val2.try_as_number()?)),
+        )),
+        AbstractType::NegNumber => Ok(Value::new(
+            AbstractType::NegNumber,
+            ValueData::NegNumber(i64_op(val1.try_as_negnumber()?, val2.try_as_negnumber()?)),
+        )),
+        AbstractType::ConcreteType(Type::Int) => {
+            Ok(i64_op(val1.try_as_int()?, val2.try_as_int()?).into())
+        }
+        AbstractType::ConcreteType(Type::UInt) => {
+            Ok(u64_op(val1.try_as_uint()?, val2.try_as_uint()?).into())
+        }
+        ty => Err(format!("{} is only defined for integers, got {:?}", func, ty).into()),
     }
+}
 
-    #[test]
-    fn basic_e2e() -> InterpreTestResult {
-        let input1 = "(+ 1.5 1)";
-        let input2 = "(add 1.5 1)";
+/// Shared implementation of `shl` and `shr`, shifting the first argument by the number of bits
+/// given by the second. The result keeps the type of the first argument, and `shr` on a signed
+/// value keeps its sign. Shifting by 64 or more bits is an Err, since every bit would be lost
+pub fn eval_shift(mut args: Vec<Argument>, func: ReservedIdent) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
 
-        let tokens1 = tokenize(input1.chars().collect())?;
-        let tokens2 = tokenize(input2.chars().collect())?;
+    let (amount, arg) = (args.pop().unwrap(), args.pop().unwrap());
+    let (amount, val) = (amount.try_get_val()?, arg.try_get_val()?);
 
-        assert_eq!(tokens1, tokens2);
+    let bits = match amount.try_as_uint() {
+        Ok(n) => Some(n),
+        Err(_) => u64::try_from(amount.try_as_int()?).ok(),
+    }
+    .filter(|&n| n < 64)
+    .ok_or(format!(
+        "Unable to shift by {:?}, the amount must be between 0 and 63",
+        amount
+    ))? as u32;
 
-        let node = parse_prog(tokens1.as_slice())?;
-        let val = eval(node.0)?;
+    let left = func == ReservedIdent::Shl;
+    let shift_u64 = |n: u64| if left { n << bits } else { n >> bits };
+    let shift_i64 = |n: i64| if left { n << bits } else { n >> bits };
 
-        assert_eq!(val, 2.5.into());
+    match arg.try_get_val_type()? {
+        AbstractType::Number => Ok(Value::new(
+            AbstractType::Number,
+            ValueData::Number(shift_u64(val.try_as_number()?)),
+        )),
+        AbstractType::NegNumber => Ok(Value::new(
+            AbstractType::NegNumber,
+            ValueData::NegNumber(shift_i64(val.try_as_negnumber()?)),
+        )),
+        AbstractType::ConcreteType(Type::Int) => Ok(shift_i64(val.try_as_int()?).into()),
+        AbstractType::ConcreteType(Type::UInt) => Ok(shift_u64(val.try_as_uint()?).into()),
+        ty => Err(format!("{} is only defined for integers, got {:?}", func, ty).into()),
+    }
+}
 
-        Ok(())
+/// Converts any number to an `int`, truncating floats towards zero. Returns an Err if the value
+/// is out of range
+pub fn eval_toint(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let arg = args.pop().unwrap();
+    let val = arg.try_get_val()?;
+
+    let res = match arg.try_get_val_type()? {
+        AbstractType::ConcreteType(Type::Float | Type::Float32) => {
+            let f = float_value(val)?.trunc();
+
+            // `i64::MAX as f64` rounds up to 2^63, which is out of range
+            if f >= i64::MIN as f64 && f < i64::MAX as f64 {
+                Ok(f as i64)
+            } else {
+                Err(format!("Value out of range for int: {}", f).into())
+            }
+        }
+        AbstractType::Number | AbstractType::ConcreteType(Type::UInt) => {
+            i64::try_from(val.try_as_uint()?)
+                .map_err(|_| format!("Value out of range for int: {:?}", val).into())
+        }
+        AbstractType::NegNumber | AbstractType::ConcreteType(Type::Int) => val.try_as_int(),
+        ty => Err(format!("toint expects a number, got type {:?}", ty).into()),
+    }?;
+
+    Ok(res.into())
+}
+
+/// Converts any non-negative number to a `uint`, truncating floats towards zero. Returns an Err
+/// for negative values or values that are out of range
+pub fn eval_touint(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let arg = args.pop().unwrap();
+    let val = arg.try_get_val()?;
+
+    let res = match arg.try_get_val_type()? {
+        AbstractType::ConcreteType(Type::Float | Type::Float32) => {
+            let f = float_value(val)?;
+
+            if f < 0.0 {
+                Err(format!("Can't convert negative value to uint: {}", f).into())
+            } else if f.trunc() < u64::MAX as f64 {
+                Ok(f.trunc() as u64)
+            } else {
+                Err(format!("Value out of range for uint: {}", f).into())
+            }
+        }
+        AbstractType::Number | AbstractType::ConcreteType(Type::UInt) => val.try_as_uint(),
+        AbstractType::NegNumber | AbstractType::ConcreteType(Type::Int) => {
+            let n = val.try_as_int()?;
+
+            u64::try_from(n)
+                .map_err(|_| format!("Can't convert negative value to uint: {}", n).into())
+        }
+        ty => Err(format!("touint expects a number, got type {:?}", ty).into()),
+    }?;
+
+    Ok(res.into())
+}
+
+/// Converts any number to a `float`
+pub fn eval_tofloat(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let arg = args.pop().unwrap();
+    let val = arg.try_get_val()?;
+
+    let res = match arg.try_get_val_type()? {
+        AbstractType::ConcreteType(Type::Float | Type::Float32) => float_value(val)?,
+        AbstractType::Number | AbstractType::ConcreteType(Type::UInt) => val.try_as_uint()? as f64,
+        AbstractType::NegNumber | AbstractType::ConcreteType(Type::Int) => val.try_as_int()? as f64,
+        ty => return Err(format!("tofloat expects a number, got type {:?}", ty).into()),
+    };
+
+    Ok(res.into())
+}
+
+/// Converts any number to a `float32`, rounding to the nearest value it can hold
+pub fn eval_tofloat32(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let arg = args.pop().unwrap();
+    let val = arg.try_get_val()?;
+
+    let res = match arg.try_get_val_type()? {
+        AbstractType::ConcreteType(Type::Float32) => val.try_as_float32()?,
+        AbstractType::ConcreteType(Type::Float) => val.try_as_float()? as f32,
+        AbstractType::Number | AbstractType::ConcreteType(Type::UInt) => val.try_as_uint()? as f32,
+        AbstractType::NegNumber | AbstractType::ConcreteType(Type::Int) => val.try_as_int()? as f32,
+        ty => return Err(format!("tofloat32 expects a number, got type {:?}", ty).into()),
+    };
+
+    Ok(res.into())
+}
+
+/// The byte value of a char, as a `uint`
+pub fn eval_ord(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let c = args.pop().unwrap().try_get_val()?.try_as_char()?;
+
+    Ok((c as u64).into())
+}
+
+/// The char with the given byte value. Returns an Err if the value doesn't fit in a byte
+pub fn eval_chr(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let n = args.pop().unwrap().try_get_val()?.try_as_uint()?;
+
+    u8::try_from(n)
+        .map(Value::from)
+        .map_err(|_| format!("Value out of range for char: {}", n).into())
+}
+
+/// Most digits `tostringprec` will write after the decimal point, well past what a `float` can
+/// represent
+const MAX_PRECISION: u64 = 32;
+
+/// Writes an integer in the given radix using lowercase letters for digits above 9, with a
+/// leading `-` for negative values
+pub fn eval_tostring_radix(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (radix, arg) = (args.pop().unwrap(), args.pop().unwrap());
+    let (radix, val) = (radix.try_get_val()?, arg.try_get_val()?);
+
+    let radix = radix
+        .try_as_uint()
+        .ok()
+        .filter(|r| (2..=36).contains(r))
+        .ok_or(format!(
+            "Unable to use radix {:?}, it must be between 2 and 36",
+            radix
+        ))? as u32;
+
+    let (negative, mut n) = match arg.try_get_val_type()? {
+        AbstractType::Number | AbstractType::ConcreteType(Type::UInt) => {
+            (false, val.try_as_uint()?)
+        }
+        AbstractType::NegNumber | AbstractType::ConcreteType(Type::Int) => {
+            let n = val.try_as_int()?;
+            (n < 0, n.unsigned_abs())
+        }
+        ty => {
+            return Err(format!(
+                "{} is only defined for integers, got {:?}",
+                ReservedIdent::ToStringRadix,
+                ty
+            )
+            .into())
+        }
+    };
+
+    let mut digits = Vec::new();
+    loop {
+        digits.push(char::from_digit((n % radix as u64) as u32, radix).unwrap());
+        n /= radix as u64;
+
+        if n == 0 {
+            break;
+        }
+    }
+    if negative {
+        digits.push('-');
     }
 
-    #[test]
-    fn nested_add_test() -> InterpreTestResult {
-        let input1 = "(+ 2 (add 1.5 1))";
-        let input2 = "(add 2 (+ 1.5 1))";
+    Ok(digits.into_iter().rev().collect::<String>().into())
+}
 
-        let tokens1 = tokenize(input1.chars().collect())?;
-        let tokens2 = tokenize(input2.chars().collect())?;
+/// Writes a float with exactly the given number of digits after the decimal point, rounding the
+/// last one
+pub fn eval_tostring_prec(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
 
-        assert_eq!(tokens1, tokens2);
+    let (prec, arg) = (args.pop().unwrap(), args.pop().unwrap());
+    let (prec, val) = (prec.try_get_val()?, arg.try_get_val()?);
 
-        let node = parse_prog(tokens1.as_slice())?;
-        let val = eval(node.0)?;
+    let prec = prec
+        .try_as_uint()
+        .ok()
+        .filter(|&p| p <= MAX_PRECISION)
+        .ok_or(format!(
+            "Unable to use precision {:?}, it must be between 0 and {}",
+            prec, MAX_PRECISION
+        ))? as usize;
 
-        assert_eq!(val, 4.5.into());
+    let f = float_value(val).map_err(|_| {
+        format!(
+            "{} is only defined for floats, got {:?}",
+            ReservedIdent::ToStringPrec,
+            val
+        )
+    })?;
 
-        Ok(())
+    Ok(format!("{:.*}", prec, f).into())
+}
+
+// Value of a `float` or `float32`, widened if needed
+fn float_value(val: &Value) -> InterpreteResult<f64> {
+    val.try_as_float()
+        .or_else(|_| val.try_as_float32().map(f64::from))
+}
+
+pub fn eval_concat(mut args: Vec<Argument>, state: &State) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (list2, list1) = (args.pop().unwrap(), args.pop().unwrap());
+
+    let mut vals = list1.try_get_val()?.try_as_list()?;
+    vals.append(&mut list2.try_get_val()?.try_as_list()?);
+
+    // Also catches lists with incompatible element types
+    let res = Value::list_from(vals)?;
+    state.check_heap_size(res.heap_size())?;
+
+    Ok(res)
+}
+
+pub fn eval_prepend(mut args: Vec<Argument>, state: &State) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (list, val) = (args.pop().unwrap(), args.pop().unwrap());
+
+    let mut vals = vec![val.try_get_val()?.clone()];
+    vals.append(&mut list.try_get_val()?.try_as_list()?);
+
+    // The element type is recomputed from scratch, which also resolves the type of an
+    // empty list from the prepended value
+    let ty = infer_list_element_type(&vals, &[], state.number_default())?;
+    let res = Value::new(Type::List(Box::new(ty)).into(), ValueData::List(vals));
+    state.check_heap_size(res.heap_size())?;
+
+    Ok(res)
+}
+
+pub fn eval_contains(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (list, val) = (args.pop().unwrap(), args.pop().unwrap());
+    let val = val.try_get_val()?;
+
+    for item in list.try_get_val()?.try_as_list()? {
+        if val.semantic_eq(&item)? {
+            return Ok(true.into());
+        }
     }
 
-    #[should_panic(expected = "Unable to coerce Float into UInt")]
-    #[test]
-    fn invalid_type_test1() {
-        let input = "(+ 1u (add 1.5 1))";
+    Ok(false.into())
+}
+
+pub fn eval_isempty(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let list = args.pop().unwrap();
+
+    Ok(list.try_get_val()?.try_as_list()?.is_empty().into())
+}
+
+/// Returns the first element of a list, which must not be empty
+pub fn eval_head(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let list = args.pop().unwrap();
+
+    list.try_get_val()?
+        .try_as_list()?
+        .into_iter()
+        .next()
+        .ok_or("Can't take the head of an empty list".into())
+}
+
+/// Returns everything but the first element of a list, which must not be empty
+pub fn eval_tail(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let list = args.pop().unwrap();
+    let mut vals = list.try_get_val()?.try_as_list()?;
+
+    if vals.is_empty() {
+        return Err("Can't take the tail of an empty list".into());
+    }
+    vals.remove(0);
+
+    // There's nothing to recompute the element type from once the list is empty
+    if vals.is_empty() {
+        Ok(Value::new(list.try_get_val_type()?, ValueData::List(vals)))
+    } else {
+        Value::list_from(vals)
+    }
+}
+
+/// Builds the `list<int>` from start (inclusive) to end (exclusive). If start >= end the
+/// result is an empty `list<int>`, same as a Rust range
+pub fn eval_range(mut args: Vec<Argument>, state: &State) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (end, start) = (args.pop().unwrap(), args.pop().unwrap());
+    let (start, end) = (
+        start.try_get_val()?.try_as_int()?,
+        end.try_get_val()?.try_as_int()?,
+    );
+
+    // Checked up front so a huge range fails before anything is allocated
+    let len = end.saturating_sub(start).max(0) as usize;
+    state.check_heap_size(len.saturating_mul(mem::size_of::<Value>()))?;
+
+    Ok(Value::new(
+        Type::List(Box::new(Type::Int)).into(),
+        ValueData::List(
+            (start..end)
+                .map(|i| Value::new(Type::Int.into(), ValueData::Int(i)))
+                .collect(),
+        ),
+    ))
+}
+
+/// Pairs up the elements of two lists into a `list<tuple<A, B>>`, stopping at the end of the
+/// shorter list
+pub fn eval_zip(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (second, first) = (args.pop().unwrap(), args.pop().unwrap());
+    let (first_vals, second_vals) = (
+        first.try_get_val()?.try_as_list()?,
+        second.try_get_val()?.try_as_list()?,
+    );
+
+    // Empty lists don't always have an element type, in which case the result is empty too
+    let (first_ty, second_ty) = match (first.try_get_val_type()?, second.try_get_val_type()?) {
+        (
+            AbstractType::ConcreteType(Type::List(first_ty)),
+            AbstractType::ConcreteType(Type::List(second_ty)),
+        ) => (*first_ty, *second_ty),
+        _ => return Ok(Value::new(AbstractType::List, ValueData::List(vec![]))),
+    };
+    let tuple_ty = Type::Tuple(vec![first_ty, second_ty]);
+
+    Ok(Value::new(
+        Type::List(Box::new(tuple_ty.clone())).into(),
+        ValueData::List(
+            first_vals
+                .into_iter()
+                .zip(second_vals)
+                .map(|(a, b)| Value::new(tuple_ty.clone().into(), ValueData::Tuple(vec![a, b])))
+                .collect(),
+        ),
+    ))
+}
+
+/// Sorts a list in ascending order, see `Value::try_cmp`. The sort is stable and the list keeps
+/// its type
+pub fn eval_sort(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let list = args.pop().unwrap();
+    let mut vals = list.try_get_val()?.try_as_list()?;
+
+    // `sort_by` needs an infallible comparator, so the first error is stored and returned after
+    let mut err = None;
+    vals.sort_by(|v1, v2| {
+        v1.try_cmp(v2).unwrap_or_else(|e| {
+            err.get_or_insert(e);
+            Ordering::Equal
+        })
+    });
+
+    match err {
+        Some(e) => Err(e),
+        None => Ok(Value::new(list.try_get_val_type()?, ValueData::List(vals))),
+    }
+}
+
+/// The chars of a `list<char>` (or an empty list). Unlike `Value::as_string`, they don't need to
+/// make up valid UTF-8
+fn char_list_bytes(val: &Value) -> InterpreteResult<Vec<u8>> {
+    val.try_as_list()?.iter().map(Value::try_as_char).collect()
+}
+
+/// Joins a list of strings into one, with the separator string between each pair of them. Works
+/// on the chars directly, so any `list<char>` can be joined
+pub fn eval_join(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (list, sep) = (args.pop().unwrap(), args.pop().unwrap());
+    let sep = char_list_bytes(sep.try_get_val()?)?;
+
+    let parts = list
+        .try_get_val()?
+        .try_as_list()?
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            char_list_bytes(part).map_err(|_| {
+                format!(
+                    "join expects a list of strings, found {:?} at element {}",
+                    part, i
+                )
+                .into()
+            })
+        })
+        .collect::<InterpreteResult<Vec<_>>>()?;
+
+    Ok(parts.join(sep.as_slice()).into())
+}
+
+/// Concatenates the sublists of a list of lists, flattening one level. The element type is found
+/// again from the combined elements, so the sublists need compatible element types
+pub fn eval_flatten(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let list = args.pop().unwrap();
+    let mut vals = Vec::new();
+
+    for (i, sublist) in list.try_get_val()?.try_as_list()?.into_iter().enumerate() {
+        let mut sublist = sublist.try_as_list().map_err(|_| {
+            format!(
+                "flatten expects a list of lists, found {:?} at element {}",
+                sublist, i
+            )
+        })?;
+
+        vals.append(&mut sublist);
+    }
+
+    // Without elements to infer from, a `list<list<T>>` still flattens to a `list<T>`
+    match list.try_get_val_type()? {
+        AbstractType::ConcreteType(Type::List(inner)) if vals.is_empty() => {
+            Ok(Value::new((*inner).into(), ValueData::List(vals)))
+        }
+        _ => Value::list_from(vals),
+    }
+}
+
+/// Writes a string to the State's writer, returning it unchanged
+pub fn eval_write(mut args: Vec<Argument>, state: &mut State) -> InterpreteResult<Value> {
+    assert!(args.len() == 1);
+
+    let arg = args.pop().unwrap();
+    let val = arg.try_get_val()?;
+
+    state.writer().write_all(val.as_string()?.as_bytes())?;
+
+    Ok(val.clone())
+}
+
+/// Reads a line from the State's reader as a `list<char>`, without the line ending. Reaching the
+/// end of the input before reading anything is an Err, so it can be told apart from an empty line
+pub fn eval_readline(args: Vec<Argument>, state: &mut State) -> InterpreteResult<Value> {
+    assert!(args.is_empty());
+
+    let mut line = Vec::new();
+
+    if state.reader().read_until(b'\n', &mut line)? == 0 {
+        return Err("Reached the end of the input while reading a line".into());
+    }
+
+    if line.last() == Some(&b'\n') {
+        line.pop();
+
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    }
+
+    Ok(line.into())
+}
+
+/// The arguments have already been evaluated in order by the time this is called, so all that's
+/// left is returning the last one
+pub fn eval_do(mut args: Vec<Argument>) -> InterpreteResult<Value> {
+    assert!(!args.is_empty());
+
+    Ok(args.pop().unwrap().try_get_val()?.clone())
+}
+
+/// Calls a two-parameter function with the accumulator and each element of a list in turn,
+/// starting from the initial value. An empty list gives back the initial value
+pub fn eval_fold(mut args: Vec<Argument>, state: &mut State) -> InterpreteResult<Value> {
+    assert!(args.len() == 3);
+
+    let (list, init, func) = (
+        args.pop().unwrap(),
+        args.pop().unwrap(),
+        args.pop().unwrap(),
+    );
+    let func = func.try_get_val()?.try_as_closure()?;
+
+    let mut acc = init.try_get_val()?.clone();
+
+    for item in list.try_get_val()?.try_as_list()? {
+        acc = call_closure(&func, vec![acc, item], state)?;
+    }
+
+    Ok(acc)
+}
+
+/// Keeps the elements of a list for which a one-parameter function returns `true`. The result
+/// keeps the type of the original list, even if it ends up empty
+pub fn eval_filter(mut args: Vec<Argument>, state: &mut State) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (list, func) = (args.pop().unwrap(), args.pop().unwrap());
+    let func = func.try_get_val()?.try_as_closure()?;
+
+    let mut vals = Vec::new();
+
+    for item in list.try_get_val()?.try_as_list()? {
+        let keep = call_closure(&func, vec![item.clone()], state)?;
+
+        if keep
+            .try_as_bool()
+            .map_err(|_| format!("Filter function must return a bool, got {:?}", keep))?
+        {
+            vals.push(item);
+        }
+    }
+
+    Ok(Value::new(list.try_get_val_type()?, ValueData::List(vals)))
+}
+
+/// Splits a list into a tuple of the elements the function returns `true` for and the rest, each
+/// keeping their original order
+pub fn eval_partition(mut args: Vec<Argument>, state: &mut State) -> InterpreteResult<Value> {
+    assert!(args.len() == 2);
+
+    let (list, func) = (args.pop().unwrap(), args.pop().unwrap());
+    let func = func.try_get_val()?.try_as_closure()?;
+
+    // A tuple's type has to be concrete, so the halves of a list without an element type (like
+    // `[]`, which is always empty) are lists of `unit`
+    let list_ty = match list.try_get_val_type()? {
+        AbstractType::ConcreteType(ty) => ty,
+        _ => Type::List(Box::new(Type::Unit)),
+    };
+
+    let (mut matched, mut rejected) = (Vec::new(), Vec::new());
+
+    for item in list.try_get_val()?.try_as_list()? {
+        let keep = call_closure(&func, vec![item.clone()], state)?;
+
+        if keep
+            .try_as_bool()
+            .map_err(|_| format!("Partition function must return a bool, got {:?}", keep))?
+        {
+            matched.push(item);
+        } else {
+            rejected.push(item);
+        }
+    }
+
+    // An empty half has nothing to infer a type from, so it keeps the type of the original list
+    let part_ty = |vals: &Vec<Value>| -> InterpreteResult<Type> {
+        if vals.is_empty() {
+            Ok(list_ty.clone())
+        } else {
+            Ok(Type::List(Box::new(infer_list_element_type(
+                vals,
+                &[],
+                state.number_default(),
+            )?)))
+        }
+    };
+    let (matched_ty, rejected_ty) = (part_ty(&matched)?, part_ty(&rejected)?);
+
+    Ok(Value::new(
+        Type::Tuple(vec![matched_ty.clone(), rejected_ty.clone()]).into(),
+        ValueData::Tuple(vec![
+            Value::new(matched_ty.into(), ValueData::List(matched)),
+            Value::new(rejected_ty.into(), ValueData::List(rejected)),
+        ]),
+    ))
+}
+
+/// Returns Unit if the argument is `true`, and otherwise an Err that includes the source of the
+/// asserted expression when it's given
+pub fn eval_assert(mut args: Vec<Argument>, source: Option<&str>) -> InterpreteResult<Value> {
+    if args.len() != 1 {
+        return Err(format!("assert expects 1 argument, got {}", args.len()).into());
+    }
+
+    let val = args.pop().unwrap();
+    let val = val.try_get_val()?;
+
+    let passed = val
+        .try_as_bool()
+        .map_err(|_| format!("assert expects a bool, got {:?}", val))?;
+
+    match (passed, source) {
+        (true, _) => Ok(().into()),
+        (false, Some(source)) => Err(format!("Assertion failed: {}", source).into()),
+        (false, None) => Err("Assertion failed".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        blisp::{
+            interpreter::{
+                eval, eval_with_setup, eval_with_state, AbstractType, Argument, ArithmeticMode,
+                NumberDefault, State, Value, ValueData,
+            },
+            lexer::{tokenize, NumLiteral, Type},
+            parser::parse_prog,
+        },
+        error::InterpreTestResult,
+    };
+
+    use super::{eval_abs, eval_add, eval_sub, eval_toint, eval_touint};
+    use crate::blisp::macros::{assert_eval, assert_eval_eq, assert_eval_err};
+
+    #[test]
+    fn eval_add_test() -> InterpreTestResult {
+        let args = vec![Argument::Value(1.2.into()), Argument::Value(2.5.into())];
+
+        let res = eval_add(args, ArithmeticMode::default())?;
+
+        assert_eq!(res, Value::from(3.7));
+
+        Ok(())
+    }
+
+    #[test]
+    fn float32_test() -> InterpreTestResult {
+        let args = vec![
+            Argument::Value(0.1f32.into()),
+            Argument::Value(0.2f32.into()),
+        ];
+        let res = eval_add(args, ArithmeticMode::default())?;
+
+        // Single precision rounds differently from the default double precision path
+        assert_eq!(res, Value::from(0.1f32 + 0.2f32));
+        assert_ne!(res.try_as_float32()? as f64, 0.1 + 0.2);
+        assert_eq!(
+            eval_add(
+                vec![Value::from(0.1).into(), Value::from(0.2).into()],
+                ArithmeticMode::default()
+            )?,
+            Value::from(0.1 + 0.2)
+        );
+
+        // Numbers coerce to float32, but float and float32 don't mix
+        let num = Value::new(AbstractType::Number, ValueData::Number(1));
+        assert_eq!(
+            eval_add(
+                vec![num.into(), Value::from(0.5f32).into()],
+                ArithmeticMode::default()
+            )?,
+            Value::from(1.5f32)
+        );
+        assert!(eval_add(
+            vec![Value::from(0.5).into(), Value::from(0.5f32).into()],
+            ArithmeticMode::default()
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn basic_e2e() -> InterpreTestResult {
+        assert_eval_eq!("(+ 1.5 1)", "(add 1.5 1)");
+        assert_eval_eq!("(add 1.5 1)", "2.5");
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_add_test() -> InterpreTestResult {
+        assert_eval_eq!("(+ 2 (add 1.5 1))", "(add 2 (+ 1.5 1))");
+        assert_eval_eq!("(add 2 (+ 1.5 1))", "4.5");
+
+        Ok(())
+    }
+
+    #[should_panic(expected = "Unable to coerce Float into UInt")]
+    #[test]
+    fn invalid_type_test1() {
+        let input = "(+ 1u (add 1.5 1))";
+
+        let tokens = tokenize(input.chars().collect()).expect("Failed lexing");
+
+        let node = parse_prog(tokens.as_slice()).expect("Failed parsing");
+        eval(&node.0).unwrap();
+    }
+
+    #[test]
+    fn sub_test() -> InterpreTestResult {
+        let inputs = [
+            ("(- 5 2)", Value::from(3i64)),
+            ("(- 1 2)", Value::from(-1i64)),
+            (
+                "(sub 5u 2)",
+                Value::new(Type::UInt.into(), ValueData::UInt(3)),
+            ),
+            ("(- 1.5 2)", Value::from(-0.5)),
+            ("(- 0 9223372036854775808)", Value::from(i64::MIN)),
+            (
+                "(- 9223372036854775809 18446744073709551615)",
+                Value::from(-9223372036854775806i64),
+            ),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        // A difference below the smallest `int` is an overflow rather than wrapping or panicking
+        for (input, exp) in [
+            (
+                "(- 1 9223372036854775810)",
+                "Integer overflow evaluating 1 - 9223372036854775810",
+            ),
+            (
+                "(- 1 18446744073709551615)",
+                "Integer overflow evaluating 1 - 18446744073709551615",
+            ),
+            (
+                "(- 0 9223372036854775809)",
+                "Integer overflow evaluating 0 - 9223372036854775809",
+            ),
+        ] {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0).unwrap_err().to_string(), exp);
+        }
+
+        let number =
+            |n: u64| Argument::Value(Value::new(AbstractType::Number, ValueData::Number(n)));
+        let args = || vec![number(1), number(u64::MAX)];
+
+        assert_eq!(
+            eval_sub(args(), ArithmeticMode::Wrapping)?,
+            Value::new(AbstractType::NegNumber, ValueData::NegNumber(2))
+        );
+        assert_eq!(
+            eval_sub(args(), ArithmeticMode::Saturating)?,
+            Value::new(AbstractType::NegNumber, ValueData::NegNumber(i64::MIN))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mul_test() -> InterpreTestResult {
+        let inputs = [
+            ("(* 5 2)", Value::from(10i64)),
+            ("(mul -3 2)", Value::from(-6i64)),
+            ("(mul 5u 2)", Value::from(10u64)),
+            ("(* 1.5 2)", Value::from(3.0)),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        let tokens = tokenize("(mul 'a' 2c)".chars().collect())?;
+        assert_eq!(
+            eval(&parse_prog(&tokens)?.0).unwrap_err().to_string(),
+            "Unable to multiply values of type Char"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn div_test() -> InterpreTestResult {
+        let inputs = [
+            ("(/ 10 2)", Value::from(5i64)),
+            ("(div 11u 2)", Value::from(5u64)),
+            ("(div 9.0 2.0)", Value::from(4.5)),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        let tokens = tokenize("(div 5u 0)".chars().collect())?;
+        assert_eq!(
+            eval(&parse_prog(&tokens)?.0).unwrap_err().to_string(),
+            "Division by zero evaluating 5 / 0"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn div_result_type_test() -> InterpreTestResult {
+        let run = |input: &str, default| {
+            eval_with_setup(input, |state| state.set_number_default(default)).map(|(val, _)| val)
+        };
+
+        for default in [NumberDefault::Int, NumberDefault::UInt] {
+            assert_eq!(run("(div 7 2)", default)?, Value::from(3i64));
+            assert_eq!(run("(div -7 2)", default)?, Value::from(-3i64));
+            assert_eq!(run("(div 7 -2)", default)?, Value::from(-3i64));
+            assert_eq!(run("(div 7.0 2)", default)?, Value::from(3.5));
+            assert_eq!(run("(div 7 2.0)", default)?, Value::from(3.5));
+        }
+
+        // The result is an `int` rather than a literal, so it doesn't take on another type
+        assert_eq!(
+            run("[(div 7 2) 1]", NumberDefault::UInt)?,
+            Value::from(vec![3i64, 1])
+        );
+        assert!(run("[(div 7 2) 1u]", NumberDefault::Int).is_err());
+        assert_eq!(
+            run("(- 10u (div 9u 3))", NumberDefault::Int).unwrap(),
+            7u64.into()
+        );
+        assert_eq!(
+            run("(- 10u (div 9 3))", NumberDefault::Int)
+                .unwrap_err()
+                .to_string(),
+            "Unable to coerce UInt into Int"
+        );
+
+        // -18446744073709551615 doesn't fit in an int
+        assert_eq!(
+            run("(div 18446744073709551615 -1)", NumberDefault::Int)
+                .unwrap_err()
+                .to_string(),
+            "Number too large to use as a negnumber: 18446744073709551615"
+        );
+
+        assert_eq!(
+            run("(div 18446744073709551615 1)", NumberDefault::Int)
+                .unwrap_err()
+                .to_string(),
+            "Result of 18446744073709551615 / 1 doesn't fit in an int"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn char_math_test() -> InterpreTestResult {
+        let inputs = [
+            ("(+ 'a' 1c)", b'b'),
+            ("(+ 1c 'a')", b'b'),
+            ("(+ 255c 1c)", 0),
+            ("(+ 200c 100c)", 44),
+            ("(- 'b' 1c)", b'a'),
+            ("(- 0c 1c)", 255),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, Value::from(exp));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn min_max_test() -> InterpreTestResult {
+        let inputs = [
+            ("(min 3 5)", Value::from(3i64)),
+            ("(max 3 5)", Value::from(5i64)),
+            ("(max 3.0 5.0)", Value::from(5.0)),
+            ("(min 3.0 -5.5)", Value::from(-5.5)),
+            ("(min 3 -5)", Value::from(-5i64)),
+            ("(max 3 -5)", Value::from(3i64)),
+            ("(max 2 1.5)", Value::from(2i64)),
+            ("(min 7u 4)", Value::from(4i64)),
+            ("(max 'a' 'z')", Value::from(b'z')),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        Ok(())
+    }
+
+    #[should_panic(expected = "Unable to coerce")]
+    #[test]
+    fn min_invalid_type_test() {
+        let input = "(min 'a' 1.5)";
+
+        let tokens = tokenize(input.chars().collect()).expect("Failed lexing");
+
+        let node = parse_prog(tokens.as_slice()).expect("Failed parsing");
+        eval(&node.0).unwrap();
+    }
+
+    #[test]
+    fn abs_test() -> InterpreTestResult {
+        let inputs = [
+            ("(abs -5)", Value::from(5i64)),
+            ("(abs -3.5)", Value::from(3.5)),
+            ("(abs 4)", Value::from(4i64)),
+            (
+                "(abs 4u)",
+                Value::new(Type::UInt.into(), ValueData::UInt(4)),
+            ),
+            ("(abs 2.0)", Value::from(2.0)),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        let min = Value::new(Type::Int.into(), ValueData::Int(i64::MIN));
+        assert!(eval_abs(vec![min.into()]).is_err());
+
+        // The smallest `int` can be written as a literal, and taking its absolute value is an
+        // error rather than a panic
+        let tokens = tokenize("-9223372036854775808".chars().collect())?;
+        assert_eq!(eval(&parse_prog(&tokens)?.0)?, Value::from(i64::MIN));
+
+        let tokens = tokenize("(abs -9223372036854775808)".chars().collect())?;
+        assert_eq!(
+            eval(&parse_prog(&tokens)?.0).unwrap_err().to_string(),
+            "Overflow when taking the absolute value of -9223372036854775808"
+        );
+
+        let too_small = NumLiteral::new_int(9223372036854775809, true);
+        assert_eq!(
+            Value::try_from(too_small).unwrap_err().to_string(),
+            "Negative number literal doesn't fit in an int: -9223372036854775809"
+        );
+
+        let neg = Value::new(Type::Int.into(), ValueData::Int(-7));
+        assert_eq!(
+            eval_abs(vec![neg.into()])?,
+            Value::new(Type::Int.into(), ValueData::Int(7))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn comparison_test() -> InterpreTestResult {
+        let inputs = [
+            ("(eq \"abc\" ['a' 'b' 'c'])", true),
+            ("(eq ['a' 'b' 'c'] \"abc\")", true),
+            ("(neq \"abc\" ['a' 'b' 'c'])", false),
+            ("(eq \"abc\" \"ab\")", false),
+            ("(eq 2 2u)", true),
+            ("(neq 2 2.5)", true),
+            ("(lt \"abc\" \"abd\")", true),
+            ("(gt \"abc\" \"abd\")", false),
+            ("(lt \"ab\" \"abc\")", true),
+            ("(lt \"\" \"a\")", true),
+            ("(lt \"b\" \"abc\")", false),
+            ("(leq \"abc\" ['a' 'b' 'c'])", true),
+            ("(geq \"Z\" \"a\")", false),
+            ("(lt -1 2)", true),
+            ("(geq 2.5 2)", true),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, Value::from(exp), "{}", input);
+        }
+
+        // String ordering should match byte-wise comparison
+        let strs = ["abc", "abd", "ab", "", "B", "b", "a c"];
+
+        for s1 in strs {
+            for s2 in strs {
+                let input = format!("(lt \"{}\" \"{}\")", s1, s2);
+                let tokens = tokenize(input.chars().collect())?;
+                let node = parse_prog(tokens.as_slice())?;
+
+                assert_eq!(eval(&node.0)?, Value::from(s1.as_bytes() < s2.as_bytes()));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sort_test() -> InterpreTestResult {
+        let int = |n: i64| Value::from(n);
+
+        let inputs = [
+            (
+                "(sort [3 1 2])",
+                Value::new(
+                    Type::List(Box::new(Type::Int)).into(),
+                    ValueData::List(vec![int(1), int(2), int(3)]),
+                ),
+            ),
+            (
+                "(sort [3 -1 2])",
+                Value::new(
+                    Type::List(Box::new(Type::Int)).into(),
+                    ValueData::List(vec![int(-1), int(2), int(3)]),
+                ),
+            ),
+            ("(sort \"hello\")", Value::from("ehllo".to_string())),
+            ("(sort ['b' 'B' 'a'])", Value::from("Bab".to_string())),
+            (
+                "(sort [\"bc\" \"b\" \"ab\"])",
+                Value::new(
+                    Type::List(Box::new(Type::List(Box::new(Type::Char)))).into(),
+                    ValueData::List(vec![
+                        Value::from("ab".to_string()),
+                        Value::from("b".to_string()),
+                        Value::from("bc".to_string()),
+                    ]),
+                ),
+            ),
+            (
+                "(sort [])",
+                Value::new(AbstractType::List, ValueData::List(vec![])),
+            ),
+            (
+                "(sort [5])",
+                Value::new(
+                    Type::List(Box::new(Type::Int)).into(),
+                    ValueData::List(vec![int(5)]),
+                ),
+            ),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn prepend_test() -> InterpreTestResult {
+        let input1 = "(prepend 1 [])";
+        let input2 = "(prepend 'a' \"bc\")";
+        let input3 = "(prepend 1u [2 3])";
+
+        let node1 = parse_prog(tokenize(input1.chars().collect())?.as_slice())?;
+        let node2 = parse_prog(tokenize(input2.chars().collect())?.as_slice())?;
+        let node3 = parse_prog(tokenize(input3.chars().collect())?.as_slice())?;
+
+        assert_eq!(
+            eval(&node1.0)?,
+            Value::new(
+                Type::List(Box::new(Type::Int)).into(),
+                ValueData::List(vec![Value::from(1i64)])
+            )
+        );
+        assert_eq!(eval(&node2.0)?, Value::from("abc".to_string()));
+        assert_eq!(
+            eval(&node3.0)?,
+            Value::new(
+                Type::List(Box::new(Type::UInt)).into(),
+                ValueData::List(vec![
+                    Value::new(Type::UInt.into(), ValueData::UInt(1)),
+                    Value::from(2u64),
+                    Value::from(3u64),
+                ])
+            )
+        );
+
+        Ok(())
+    }
+
+    #[should_panic(expected = "Unable to coerce")]
+    #[test]
+    fn prepend_invalid_type_test() {
+        let input = "(prepend 'a' [1 2])";
+
+        let tokens = tokenize(input.chars().collect()).expect("Failed lexing");
+
+        let node = parse_prog(tokens.as_slice()).expect("Failed parsing");
+        eval(&node.0).unwrap();
+    }
+
+    #[test]
+    fn contains_test() -> InterpreTestResult {
+        let inputs = [
+            ("(contains 2 [1 2 3])", true),
+            ("(contains 'x' \"abc\")", false),
+            ("(contains 'b' \"abc\")", true),
+            ("(contains 2 [1u 2u 3u])", true),
+            ("(contains -2 [1.5 -2.0])", true),
+            ("(contains [1 2] [[1u 2u] [3u]])", true),
+            ("(contains 1 [])", false),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, Value::from(exp));
+        }
+
+        Ok(())
+    }
+
+    #[should_panic(expected = "Unable to coerce")]
+    #[test]
+    fn contains_invalid_type_test() {
+        let input = "(contains 'a' [1 2])";
+
+        let tokens = tokenize(input.chars().collect()).expect("Failed lexing");
+
+        let node = parse_prog(tokens.as_slice()).expect("Failed parsing");
+        eval(&node.0).unwrap();
+    }
+
+    #[test]
+    fn range_test() -> InterpreTestResult {
+        let int_list = |vals: Vec<i64>| {
+            Value::new(
+                Type::List(Box::new(Type::Int)).into(),
+                ValueData::List(
+                    vals.into_iter()
+                        .map(|i| Value::new(Type::Int.into(), ValueData::Int(i)))
+                        .collect(),
+                ),
+            )
+        };
+
+        let inputs = [
+            ("(range 1 5)", int_list(vec![1, 2, 3, 4])),
+            ("(range -2 1)", int_list(vec![-2, -1, 0])),
+            ("(range 0 0)", int_list(vec![])),
+            ("(range 5 1)", int_list(vec![])),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_recursion_test() -> InterpreTestResult {
+        let inputs = [
+            ("(isempty [])", Value::from(true)),
+            ("(isempty [1 2])", Value::from(false)),
+            ("(isempty \"\")", Value::from(true)),
+            ("(head [1 2 3])", Value::from(1i64)),
+            ("(head \"abc\")", Value::from(b'a')),
+            ("(tail [1 2 3])", Value::from(vec![2i64, 3])),
+            ("(tail \"abc\")", Value::from("bc".to_string())),
+            (
+                "(tail [1u])",
+                Value::new(
+                    Type::List(Box::new(Type::UInt)).into(),
+                    ValueData::List(vec![]),
+                ),
+            ),
+            ("(head (tail (tail [1 2 3])))", Value::from(3i64)),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        for (input, exp) in [
+            ("(head [])", "Can't take the head of an empty list"),
+            ("(tail \"\")", "Can't take the tail of an empty list"),
+        ] {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0).unwrap_err().to_string(), exp);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn bitwise_test() -> InterpreTestResult {
+        assert_eval!("(band 6 3)", Value::from(2i64));
+        assert_eval!("(bor 4 1)", Value::from(5i64));
+        assert_eval!("(bxor 5 3)", Value::from(6i64));
+        assert_eval!("(shl 1 4)", Value::from(16i64));
+        assert_eval!("(shr 16 2)", Value::from(4i64));
+        assert_eval!("(band 6u 3)", Value::from(2u64));
+        assert_eval!("(bxor -1 5)", Value::from(-6i64));
+        assert_eval!("(shr -16 2)", Value::from(-4i64));
+        assert_eval!("(shl 1u 63)", Value::from(1u64 << 63));
+        assert_eval!("(shr 1 0)", Value::from(1i64));
+
+        assert_eval_err!(
+            "(band 1.5 2)",
+            "band is only defined for integers, got ConcreteType(Float)"
+        );
+        assert_eval_err!(
+            "(bor 'a' 'b')",
+            "bor is only defined for integers, got ConcreteType(Char)"
+        );
+        assert_eval_err!(
+            "(shl 2.0 1)",
+            "shl is only defined for integers, got ConcreteType(Float)"
+        );
+        assert_eval_err!(
+            "(shl 1 64)",
+            "Unable to shift by Value { ty: Number, val: Number(64) }, the amount must be between 0 \
+             and 63"
+        );
+        assert_eval_err!(
+            "(shr 1 -1)",
+            "Unable to shift by Value { ty: NegNumber, val: NegNumber(-1) }, the amount must be \
+             between 0 and 63"
+        );
+
+        // Mixing with a negative literal makes both signed, so the other has to fit in an int
+        assert_eval_err!(
+            "(band -1 18446744073709551615)",
+            "Number too large to use as a negnumber: 18446744073709551615"
+        );
+        assert_eval_err!(
+            "(bor -2 9223372036854775808)",
+            "Number too large to use as a negnumber: 9223372036854775808"
+        );
+        assert_eval_err!(
+            "(bxor (toint 1) 9223372036854775808)",
+            "Number too large to use as an int: 9223372036854775808"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn join_test() -> InterpreTestResult {
+        let string = |s: &str| Value::from(s.to_string());
+
+        assert_eval!("(join \", \" [\"a\" \"b\" \"c\"])", string("a, b, c"));
+        assert_eval!("(join \"\" [\"ab\" \"cd\"])", string("abcd"));
+        assert_eval!("(join \", \" [\"abc\"])", string("abc"));
+        assert_eval!("(join \", \" [])", string(""));
+        assert_eval!("(join \"-\" [\"\" \"\"])", string("-"));
+
+        // The chars don't need to be valid UTF-8
+        assert_eval!(
+            "(join \"-\" [['\\xFF'] \"a\"])",
+            Value::from(vec![0xFFu8, b'-', b'a'])
+        );
+
+        assert_eval_err!(
+            "(join \", \" ['a' 'b'])",
+            "join expects a list of strings, found Value { ty: ConcreteType(Char), val: Char(97) } at \
+             element 0"
+        );
+        assert_eval_err!(
+            "(join 1 [\"a\"])",
+            "Tried to convert invalid value to list: Value { ty: Number, val: Number(1) }"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tostring_format_test() -> InterpreTestResult {
+        let string = |s: &str| Value::from(s.to_string());
+
+        assert_eval!("(tostringradix 255 16u)", string("ff"));
+        assert_eval!("(tostringradix 5u 2u)", string("101"));
+        assert_eval!("(tostringradix 0 2u)", string("0"));
+        assert_eval!("(tostringradix -255 16)", string("-ff"));
+        assert_eval!("(tostringradix 35 36u)", string("z"));
+        assert_eval!("(tostringprec 3.14159 2u)", string("3.14"));
+        assert_eval!("(tostringprec 2.5 0u)", string("2"));
+        assert_eval!("(tostringprec -1.0 3u)", string("-1.000"));
+
+        assert_eval_err!(
+            "(tostringradix 255 1u)",
+            "Unable to use radix Value { ty: ConcreteType(UInt), val: UInt(1) }, it must be between 2 \
+             and 36"
+        );
+        assert_eval_err!(
+            "(tostringradix 255 37u)",
+            "Unable to use radix Value { ty: ConcreteType(UInt), val: UInt(37) }, it must be between \
+             2 and 36"
+        );
+        assert_eval_err!(
+            "(tostringradix 1.5 16u)",
+            "tostringradix is only defined for integers, got ConcreteType(Float)"
+        );
+        assert_eval_err!(
+            "(tostringprec 1.5 33u)",
+            "Unable to use precision Value { ty: ConcreteType(UInt), val: UInt(33) }, it must be \
+             between 0 and 32"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn flatten_test() -> InterpreTestResult {
+        assert_eval!("(flatten [[1 2] [3 4]])", Value::from(vec![1i64, 2, 3, 4]));
+        assert_eval!("(flatten [[1u] [] [2u 3u]])", Value::from(vec![1u64, 2, 3]));
+        assert_eval!(
+            "(flatten [\"ab\" \"\" \"cd\"])",
+            Value::from("abcd".to_string())
+        );
+        assert_eval!(
+            "(flatten [[[1] [2]] [[3]]])",
+            Value::from(vec![vec![1i64], vec![2], vec![3]])
+        );
+
+        let tokens = tokenize("(flatten [])".chars().collect())?;
+        assert_eq!(eval(&parse_prog(&tokens)?.0)?.try_as_list()?, vec![]);
+
+        // Sublists that are all empty keep their element type
+        assert_eval!("(flatten [\"\" \"\"])", Value::from(String::new()));
+        assert_eval!("(flatten [[1u] []])", Value::from(vec![1u64]));
+        assert_eval!("(flatten [[] \"\" []])", Value::from(String::new()));
+
+        assert_eval_err!(
+            "(flatten [1 2])",
+            "flatten expects a list of lists, found Value { ty: Number, val: Number(1) } at element 0"
+        );
+        assert_eval_err!(
+            "(flatten 1)",
+            "Tried to convert invalid value to list: Value { ty: Number, val: Number(1) }"
+        );
+
+        // The sublists need compatible element types, which a list literal already guarantees
+        let list = Value::new(
+            AbstractType::List,
+            ValueData::List(vec![Value::from(vec![1i64]), Value::from(vec![1.5])]),
+        );
+        assert_eq!(
+            super::eval_flatten(vec![list.into()])
+                .unwrap_err()
+                .to_string(),
+            "Unable to coerce Int into Float at element 1"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_test() -> InterpreTestResult {
+        let tuple_ty = Type::Tuple(vec![Type::Int, Type::Char]);
+        let zipped = |vals: Vec<(i64, u8)>| {
+            Value::new(
+                Type::List(Box::new(tuple_ty.clone())).into(),
+                ValueData::List(
+                    vals.into_iter()
+                        .map(|(i, c)| {
+                            Value::new(
+                                tuple_ty.clone().into(),
+                                ValueData::Tuple(vec![Value::from(i), Value::from(c)]),
+                            )
+                        })
+                        .collect(),
+                ),
+            )
+        };
+
+        let inputs = [
+            (
+                "(zip [1 2 3] ['a' 'b' 'c'])",
+                zipped(vec![(1, b'a'), (2, b'b'), (3, b'c')]),
+            ),
+            ("(zip [1 2 3] \"ab\")", zipped(vec![(1, b'a'), (2, b'b')])),
+            ("(zip [1] \"\")", zipped(vec![])),
+            (
+                "(zip [] [])",
+                Value::new(AbstractType::List, ValueData::List(vec![])),
+            ),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_heap_size_test() -> InterpreTestResult {
+        let run = |input: &str, max| {
+            eval_with_setup(input, |state| state.set_max_heap_size(max)).map(|(val, _)| val)
+        };
+        let size = std::mem::size_of::<Value>();
+
+        assert_eq!(
+            run("(range 0 1000000)", Some(1024))
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "List would take up {} bytes, more than the limit of 1024",
+                1000000 * size
+            )
+        );
+        assert_eq!(
+            run("(range 0 1000000)", None)?.try_as_list()?.len(),
+            1000000
+        );
+
+        // Exactly at the limit is fine
+        assert_eq!(run("(range 0 4)", Some(4 * size))?.try_as_list()?.len(), 4);
+        assert!(run("(range 0 5)", Some(4 * size)).is_err());
+        assert!(run("(range 5 0)", Some(0)).is_ok());
+
+        assert!(run("(concat [1 2] [3 4])", Some(4 * size)).is_ok());
+        assert!(run("(concat [1 2] [3 4 5])", Some(4 * size)).is_err());
+        assert!(run("(prepend 1 [2 3 4 5])", Some(4 * size)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn arithmetic_mode_test() -> InterpreTestResult {
+        let run = |input: &str, mode| {
+            eval_with_setup(input, |state| state.set_arithmetic_mode(mode)).map(|(val, _)| val)
+        };
+
+        let add = "(+ (toint 9223372036854775806) 5)";
+        let sub = "(- 1u 2)";
+        let mul = "(* (toint 4611686018427387904) 2)";
+
+        assert_eq!(
+            run(add, ArithmeticMode::Wrapping)?,
+            Value::from(i64::MIN + 3)
+        );
+        assert_eq!(run(add, ArithmeticMode::Saturating)?, Value::from(i64::MAX));
+        assert_eq!(
+            run(add, ArithmeticMode::Checked).unwrap_err().to_string(),
+            "Integer overflow evaluating 9223372036854775806 + 5"
+        );
+
+        assert_eq!(run(sub, ArithmeticMode::Wrapping)?, Value::from(u64::MAX));
+        assert_eq!(run(sub, ArithmeticMode::Saturating)?, Value::from(0u64));
+        assert!(run(sub, ArithmeticMode::Checked).is_err());
+
+        assert_eq!(run(mul, ArithmeticMode::Wrapping)?, Value::from(i64::MIN));
+        assert_eq!(run(mul, ArithmeticMode::Saturating)?, Value::from(i64::MAX));
+        assert_eq!(
+            run(mul, ArithmeticMode::Checked).unwrap_err().to_string(),
+            "Integer overflow evaluating 4611686018427387904 * 2"
+        );
+
+        // Checked is the default
+        assert!(run(add, State::new().arithmetic_mode()).is_err());
+
+        // A literal too large for an `int` can't be mixed with a negative one, in any mode
+        for mode in [
+            ArithmeticMode::Checked,
+            ArithmeticMode::Wrapping,
+            ArithmeticMode::Saturating,
+        ] {
+            assert_eq!(
+                run("(+ -5 18446744073709551615)", mode)
+                    .unwrap_err()
+                    .to_string(),
+                "Number too large to use as a negnumber: 18446744073709551615"
+            );
+            assert_eq!(
+                run("(+ (toint 1) 9223372036854775808)", mode)
+                    .unwrap_err()
+                    .to_string(),
+                "Number too large to use as an int: 9223372036854775808"
+            );
+        }
+
+        assert_eq!(
+            run("(+ [1] [2])", ArithmeticMode::Checked)
+                .unwrap_err()
+                .to_string(),
+            "Unable to add values of type List(Int)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn arity_test() -> InterpreTestResult {
+        let inputs = [
+            (
+                "(+ 1)",
+                "add expects 2 arguments of types [Value, Value], got 1",
+            ),
+            (
+                "(+ 1 2 3)",
+                "add expects 2 arguments of types [Value, Value], got 3",
+            ),
+            (
+                "(abs 1 2)",
+                "abs expects 1 argument of types [Value], got 2",
+            ),
+            (
+                "(+ int 2)",
+                "add expects arguments of types [Value, Value], got [Type, Value]",
+            ),
+            (
+                "(do 1 uint)",
+                "do expects arguments of types [Value, Value], got [Value, Type]",
+            ),
+            (
+                "(do)",
+                "do expects at least 1 argument of types [Value], got 0",
+            ),
+            (
+                "(readline 1)",
+                "readline expects 0 arguments of types [], got 1",
+            ),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0).unwrap_err().to_string(), exp);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn do_test() -> InterpreTestResult {
+        let mut out = Vec::new();
+        let mut state = State::with_writer(&mut out);
+
+        let tokens = tokenize("(do (write \"a\") (write \"b\") 5)".chars().collect())?;
+        let val = eval_with_state(&parse_prog(&tokens)?.0, &mut state)?;
+        drop(state);
+
+        assert_eq!(val, Value::from(5i64));
+        assert_eq!(out, b"ab");
+
+        // A single argument works too, and arguments of other functions are also in order
+        let mut out = Vec::new();
+        let mut state = State::with_writer(&mut out);
+
+        let tokens = tokenize(
+            "(do (prepend (write \"x\") [(write \"y\")]))"
+                .chars()
+                .collect(),
+        )?;
+        let val = eval_with_state(&parse_prog(&tokens)?.0, &mut state)?;
+        drop(state);
+
+        assert_eq!(val.try_as_list()?.len(), 2);
+        assert_eq!(out, b"xy");
+
+        Ok(())
+    }
+
+    #[test]
+    fn readline_test() -> InterpreTestResult {
+        let mut state = State::with_writer(std::io::sink());
+        state.set_reader(std::io::Cursor::new("first line\r\n\nlast"));
+
+        let mut readline = || {
+            let tokens = tokenize("(readline)".chars().collect())?;
+            eval_with_state(&parse_prog(&tokens)?.0, &mut state)
+        };
+
+        assert_eq!(readline()?, Value::from("first line".to_string()));
+        assert_eq!(readline()?, Value::from(String::new()));
+        assert_eq!(readline()?, Value::from("last".to_string()));
+        assert_eq!(
+            readline().unwrap_err().to_string(),
+            "Reached the end of the input while reading a line"
+        );
+
+        // The line can be used like any other string
+        let mut out = Vec::new();
+        let mut state = State::with_writer(&mut out);
+        state.set_reader(std::io::Cursor::new("echo\nunused\n"));
+
+        let tokens = tokenize("(write (readline))".chars().collect())?;
+        eval_with_state(&parse_prog(&tokens)?.0, &mut state)?;
+        drop(state);
+
+        assert_eq!(out, b"echo");
+
+        let tokens = tokenize("(readline 1)".chars().collect())?;
+        assert_eq!(
+            eval_with_state(&parse_prog(&tokens)?.0, &mut State::new())
+                .unwrap_err()
+                .to_string(),
+            "readline expects 0 arguments of types [], got 1"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fold_test() -> InterpreTestResult {
+        let inputs = [
+            ("(fold (fn acc x (add acc x)) 0 [1 2 3])", Value::from(6i64)),
+            (
+                "(fold (fn acc s (concat acc s)) \"\" [\"ab\" \"c\" \"de\"])",
+                Value::from("abcde".to_string()),
+            ),
+            ("(fold (fn acc x (add acc x)) 2.5 [])", Value::from(2.5)),
+            ("(fold (fn x y (max x y)) 'a' \"hello\")", Value::from(b'o')),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        // Parameters shadow variables of the same name only for the call
+        let mut state = State::new();
+        state.create_var("x".to_string(), Some(Value::from(10i64)))?;
+
+        let tokens = tokenize("(fold (fn acc x (add acc x)) x [1 2])".chars().collect())?;
+        let val = eval_with_state(&parse_prog(&tokens)?.0, &mut state)?;
+
+        assert_eq!(val, Value::from(13i64));
+        assert_eq!(state.get_var("x")?, &Value::from(10i64));
+
+        // Functions keep the variables they use after leaving the `let` they were created in
+        assert_eval_eq!("(fold (let x 100 (fn a b (+ a x))) 0 [1])", "100");
+        assert_eval_eq!(
+            "(let f (let x 1 (fn a b (+ a (+ b x)))) (let x 10 (fold f 0 [1 2])))",
+            "5"
+        );
+
+        // The value is captured when the function is created, later changes don't affect it
+        let tokens = tokenize("(fn n (+ n x))".chars().collect())?;
+        let func = eval_with_state(&parse_prog(&tokens)?.0, &mut state)?;
+        state.set_var("x".to_string(), Value::from(20i64))?;
+        state.create_var("f".to_string(), Some(func))?;
+
+        let tokens = tokenize("(f 1)".chars().collect())?;
+        let val = eval_with_state(&parse_prog(&tokens)?.0, &mut state)?;
+        assert_eq!(val, Value::from(11i64));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fn_invalid_test() -> InterpreTestResult {
+        let inputs = [
+            // Wrong number of parameters
+            "(fold (fn x (add x 1)) 0 [1 2])",
+            "(fn (add 1 2))",
+            "(fn 1 (add 1 2))",
+            "(fn x x (add x x))",
+            // Not a function
+            "(fold 1 0 [1 2])",
+        ];
+
+        for input in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert!(eval(&node.0).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_test() -> InterpreTestResult {
+        let inputs = [
+            (
+                "(filter (fn x (gt x 2)) [1 2 3 4])",
+                Value::from(vec![3i64, 4]),
+            ),
+            (
+                "(filter (fn c (neq c 'l')) \"hello\")",
+                Value::from("heo".to_string()),
+            ),
+            (
+                "(filter (fn x (gt x 10)) [1u 2u])",
+                Value::new(
+                    Type::List(Box::new(Type::UInt)).into(),
+                    ValueData::List(vec![]),
+                ),
+            ),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn partition_test() -> InterpreTestResult {
+        let int_list = |vals: Vec<i64>| Value::from(vals);
+        let pair = |matched: Value, rejected: Value| {
+            Value::new(
+                Type::Tuple(vec![
+                    Type::List(Box::new(Type::Int)),
+                    Type::List(Box::new(Type::Int)),
+                ])
+                .into(),
+                ValueData::Tuple(vec![matched, rejected]),
+            )
+        };
+
+        let inputs = [
+            (
+                "(partition (fn x (gt x 2)) [1 2 3 4])",
+                pair(int_list(vec![3, 4]), int_list(vec![1, 2])),
+            ),
+            (
+                "(partition (fn x (gt x 0)) [1 2 3])",
+                pair(int_list(vec![1, 2, 3]), int_list(vec![])),
+            ),
+            (
+                "(partition (fn x (gt x 10)) [1 2 3])",
+                pair(int_list(vec![]), int_list(vec![1, 2, 3])),
+            ),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        let unit_list = Type::List(Box::new(Type::Unit));
+        let tokens = tokenize("(partition (fn x (gt x 2)) [])".chars().collect())?;
+        assert_eq!(
+            eval(&parse_prog(&tokens)?.0)?,
+            Value::new(
+                Type::Tuple(vec![unit_list.clone(), unit_list.clone()]).into(),
+                ValueData::Tuple(vec![
+                    Value::new(unit_list.clone().into(), ValueData::List(vec![])),
+                    Value::new(unit_list.into(), ValueData::List(vec![])),
+                ]),
+            )
+        );
+
+        Ok(())
+    }
+
+    #[should_panic(expected = "Filter function must return a bool")]
+    #[test]
+    fn filter_non_bool_test() {
+        let input = "(filter (fn x x) [1 2])";
+
+        let tokens = tokenize(input.chars().collect()).expect("Failed lexing");
+
+        let node = parse_prog(tokens.as_slice()).expect("Failed parsing");
+        eval(&node.0).unwrap();
+    }
+
+    #[test]
+    fn assert_test() -> InterpreTestResult {
+        let run = |input| eval_with_setup(input, |_| ()).map(|(val, _)| val);
+
+        assert_eq!(run("(assert (eq (+ 1 1) 2))")?, Value::from(()));
+
+        let failed = run("(assert (eq (+ 1 1) 3))").err().unwrap();
+        assert_eq!(failed.to_string(), "Assertion failed: (eq (add 1 1) 3)");
+
+        let non_bool = run("(assert (+ 1 1))").err().unwrap();
+        assert!(non_bool
+            .to_string()
+            .starts_with("assert expects a bool, got"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn conversion_test() -> InterpreTestResult {
+        let inputs = [
+            ("(tofloat 3)", Value::from(3.0)),
+            ("(tofloat -3)", Value::from(-3.0)),
+            ("(tofloat 5u)", Value::from(5.0)),
+            ("(tofloat 0.5f)", Value::from(0.5)),
+            ("(toint 3.7)", Value::from(3i64)),
+            ("(toint -3.7)", Value::from(-3i64)),
+            ("(toint 3u)", Value::from(3i64)),
+            ("(toint -2)", Value::from(-2i64)),
+            ("(touint 3.7)", Value::from(3u64)),
+            ("(touint 3)", Value::from(3u64)),
+            ("(touint (toint 4))", Value::from(4u64)),
+            ("(tofloat32 3)", Value::from(3.0f32)),
+            ("(tofloat32 -3)", Value::from(-3.0f32)),
+            ("(tofloat32 0.1)", Value::from(0.1f32)),
+            ("(tofloat (tofloat32 0.5))", Value::from(0.5)),
+            // Arithmetic on the result is done in single precision
+            (
+                "(+ (tofloat32 0.1) (tofloat32 0.2))",
+                Value::from(0.1f32 + 0.2f32),
+            ),
+            ("(+ (tofloat32 0.1) 2)", Value::from(2.1f32)),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        let invalid = [
+            "(touint -1)",
+            "(touint -0.5)",
+            "(touint (toint -1))",
+            "(toint 18446744073709551615u)",
+            "(tofloat 'a')",
+            "(tofloat32 'a')",
+            "(+ (tofloat32 0.5) 0.5)",
+            "(toint \"1\")",
+        ];
+
+        for input in invalid {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert!(eval(&node.0).is_err(), "{}", input);
+        }
+
+        assert!(eval_toint(vec![Value::from(1e30).into()]).is_err());
+        assert!(eval_touint(vec![Value::from(f64::NAN).into()]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn ord_chr_test() -> InterpreTestResult {
+        let inputs = [
+            ("(ord 'A')", Value::from(65u64)),
+            ("(chr 65u)", Value::from(b'A')),
+            ("(chr 255)", Value::from(255u8)),
+            ("(chr (ord 'Z'))", Value::from(b'Z')),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?;
+
+            assert_eq!(eval(&node.0)?, exp);
+        }
+
+        Ok(())
+    }
+
+    #[should_panic(expected = "Value out of range for char: 300")]
+    #[test]
+    fn chr_out_of_range_test() {
+        let input = "(chr 300u)";
 
         let tokens = tokenize(input.chars().collect()).expect("Failed lexing");
 
         let node = parse_prog(tokens.as_slice()).expect("Failed parsing");
-        eval(node.0).unwrap();
+        eval(&node.0).unwrap();
     }
 }
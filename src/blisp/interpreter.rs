@@ -1,29 +1,223 @@
 use std::{
+    cmp::Ordering,
     collections::{hash_map::Entry, HashMap},
-    fmt::format,
+    io::{stdin, stdout, BufRead, BufReader, Write},
+    mem,
+    rc::Rc,
 };
 
 use crate::{
-    blisp::{functions::eval_function, macros::leaf_node_pattern},
-    error::{InterpreTestResult, InterpretError, InterpreteResult},
+    blisp::{
+        functions::{eval_assert, eval_function},
+        macros::leaf_node_pattern,
+    },
+    error::{InterpretError, InterpreteResult},
 };
 
 use super::{
-    lexer::{LiteralSuffix, NumLiteral, ReservedIdent, Type},
-    macros::{list_value_helper, rule_node_pattern},
-    parser::{Node, ParseToken, ParseTree, Rule, RuleNodeData},
+    lexer::{tokenize, LiteralSuffix, NumLiteral, ReservedIdent, Token, Type},
+    macros::rule_node_pattern,
+    parser::{Node, ParseToken, Rule, RuleNodeData},
 };
 
-/// Contains variable dictionary
-pub struct State {
+// Lives in the lexer since `ReservedIdent` maps each keyword to its argument types
+pub use super::lexer::ArgumentType;
+
+/// Contains variable dictionary, the writer used by `write`, and the reader used by `readline`
+pub struct State<'a> {
     vars: HashMap<String, Option<Value>>,
+    writer: Box<dyn Write + 'a>,
+    reader: Box<dyn BufRead + 'a>,
+    /// Where the evaluation trace goes, if tracing is enabled
+    trace: Option<Box<dyn Write + 'a>>,
+    trace_depth: usize,
+    functions: HashMap<String, Box<HostFn<'a>>>,
+    arithmetic_mode: ArithmeticMode,
+    /// Largest `Value::heap_size` a list built by `range`, `concat`, or `prepend` can have
+    max_heap_size: Option<usize>,
+    /// Index of the list literal being evaluated in each enclosing list literal, used to point
+    /// at the offending element when a nested list has mismatched types
+    list_path: Vec<usize>,
+    number_default: NumberDefault,
+}
+
+/// What integer arithmetic does when the result doesn't fit in the type
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ArithmeticMode {
+    /// Wrap around, like C
+    Wrapping,
+    /// Return an Err
+    #[default]
+    Checked,
+    /// Clamp to the min or max value of the type
+    Saturating,
+}
+
+/// The type a number literal without a suffix (a `num`) gets when nothing else decides it, e.g.
+/// the result of `(5)` or the elements of `[1 2 3]`. Negative literals are always an `int`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum NumberDefault {
+    #[default]
+    Int,
+    UInt,
 }
 
-impl State {
+/// A function implemented in Rust that BLisp programs can call, see `State::register_fn`
+pub type HostFn<'a> = dyn Fn(Vec<Argument>) -> InterpreteResult<Value> + 'a;
+
+impl<'a> State<'a> {
+    /// Create a new State that writes to stdout
     pub fn new() -> Self {
+        Self::with_writer(stdout())
+    }
+
+    /// Create a new State, specifying the writer used for program output
+    pub fn with_writer(writer: impl Write + 'a) -> Self {
         Self {
             vars: HashMap::new(),
+            writer: Box::new(writer),
+            reader: Box::new(BufReader::new(stdin())),
+            trace: None,
+            trace_depth: 0,
+            functions: HashMap::new(),
+            arithmetic_mode: ArithmeticMode::default(),
+            max_heap_size: None,
+            list_path: Vec::new(),
+            number_default: NumberDefault::default(),
+        }
+    }
+
+    pub fn arithmetic_mode(&self) -> ArithmeticMode {
+        self.arithmetic_mode
+    }
+
+    /// Set how integer overflow is handled by `add`, `sub`, and `mul`, the default is
+    /// `ArithmeticMode::Checked`
+    pub fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        self.arithmetic_mode = mode;
+    }
+
+    pub fn number_default(&self) -> NumberDefault {
+        self.number_default
+    }
+
+    /// Set the type number literals without a suffix default to, the default is
+    /// `NumberDefault::Int`
+    pub fn set_number_default(&mut self, default: NumberDefault) {
+        self.number_default = default;
+    }
+
+    /// Limit the size of the lists built by `range`, `concat`, and `prepend`, as estimated by
+    /// `Value::heap_size`, so untrusted programs can't use up all the memory. `None`, the default,
+    /// means no limit
+    pub fn set_max_heap_size(&mut self, max: Option<usize>) {
+        self.max_heap_size = max;
+    }
+
+    /// Returns an Err if a list of `size` bytes would go over the limit set by `set_max_heap_size`
+    pub(crate) fn check_heap_size(&self, size: usize) -> InterpreteResult<()> {
+        match self.max_heap_size {
+            Some(max) if size > max => Err(format!(
+                "List would take up {} bytes, more than the limit of {}",
+                size, max
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Make a Rust function callable from BLisp as `(name args..)`. The arguments are evaluated
+    /// before it's called, and it's up to the function to check them. Names that would lex as
+    /// something other than an identifier (e.g. `add` or `int`) can't be registered, and
+    /// registering a name again replaces the previous function
+    pub fn register_fn<F>(&mut self, name: &str, func: F) -> InterpreteResult<()>
+    where
+        F: Fn(Vec<Argument>) -> InterpreteResult<Value> + 'a,
+    {
+        if !matches!(
+            tokenize(name.chars().collect())?.as_slice(),
+            [Token::Ident(_), Token::EOF]
+        ) {
+            return Err(format!("Can't register a function called {}", name).into());
+        }
+
+        self.functions.insert(name.to_string(), Box::new(func));
+
+        Ok(())
+    }
+
+    /// Calls the function named by an identifier at the head of a call. This is either a function
+    /// registered with `register_fn` or a variable holding a closure, e.g. a parameter of the
+    /// function passed to `fold`. Registered functions take precedence
+    pub(crate) fn call_ident(
+        &mut self,
+        name: &str,
+        args: Vec<Argument>,
+    ) -> InterpreteResult<Value> {
+        if let Some(func) = self.functions.get(name) {
+            return func(args);
+        }
+
+        let closure = match self.vars.get(name) {
+            Some(Some(Value {
+                val: ValueData::Closure(closure),
+                ..
+            })) => closure.clone(),
+            Some(_) => return Err(format!("'{}' is not a function, it's a variable", name).into()),
+            None => return Err(format!("'{}' is not a function", name).into()),
+        };
+        let args = args
+            .iter()
+            .map(|arg| arg.try_get_val().cloned())
+            .collect::<InterpreteResult<_>>()?;
+
+        call_closure(&closure, args, self)
+    }
+
+    pub(crate) fn writer(&mut self) -> &mut dyn Write {
+        &mut self.writer
+    }
+
+    /// Replace the reader used for program input, which is stdin by default
+    pub fn set_reader(&mut self, reader: impl BufRead + 'a) {
+        self.reader = Box::new(reader);
+    }
+
+    pub(crate) fn reader(&mut self) -> &mut dyn BufRead {
+        &mut self.reader
+    }
+
+    /// Enable tracing, which writes each node that's evaluated and the value it produced to
+    /// `sink`. Nested nodes are indented under the node they're part of
+    pub fn set_trace(&mut self, sink: impl Write + 'a) {
+        self.trace = Some(Box::new(sink));
+    }
+
+    /// Runs `f`, tracing it under `label` when tracing is enabled. The label is only built if
+    /// it's needed
+    fn traced<F>(&mut self, label: impl FnOnce() -> String, f: F) -> InterpreteResult<Value>
+    where
+        F: FnOnce(&mut Self) -> InterpreteResult<Value>,
+    {
+        let indent = "  ".repeat(self.trace_depth);
+
+        match self.trace.as_mut() {
+            Some(sink) => writeln!(sink, "{}{}", indent, label())?,
+            None => return f(self),
+        }
+
+        self.trace_depth += 1;
+        let res = f(self);
+        self.trace_depth -= 1;
+
+        if let Some(sink) = self.trace.as_mut() {
+            match &res {
+                Ok(val) => writeln!(sink, "{}=> {:?}", indent, val)?,
+                Err(e) => writeln!(sink, "{}=> error: {}", indent, e)?,
+            }
         }
+
+        res
     }
 
     /// Get the value of the variable with specified identifier. Returns an Err if the
@@ -47,6 +241,20 @@ impl State {
         }
     }
 
+    /// Binds a variable, shadowing any existing variable with the same name. Returns what was
+    /// there before, which `unbind_var` puts back
+    pub(crate) fn bind_var(&mut self, ident: String, val: Value) -> Option<Option<Value>> {
+        self.vars.insert(ident, Some(val))
+    }
+
+    /// Undoes `bind_var`, given the variable it replaced
+    pub(crate) fn unbind_var(&mut self, ident: &str, prev: Option<Option<Value>>) {
+        match prev {
+            Some(prev) => self.vars.insert(ident.to_string(), prev),
+            None => self.vars.remove(ident),
+        };
+    }
+
     pub fn set_var(&mut self, ident: String, val: Value) -> InterpreteResult<()> {
         match self.vars.entry(ident) {
             Entry::Occupied(mut e) => {
@@ -60,7 +268,7 @@ impl State {
     }
 }
 
-impl Default for State {
+impl Default for State<'_> {
     fn default() -> Self {
         Self::new()
     }
@@ -97,6 +305,7 @@ impl AbstractType {
                     || second == AbstractType::ConcreteType(Type::Int)
                     || second == AbstractType::ConcreteType(Type::UInt)
                     || second == AbstractType::ConcreteType(Type::Float)
+                    || second == AbstractType::ConcreteType(Type::Float32)
                 {
                     Ok(second)
                 } else {
@@ -107,6 +316,7 @@ impl AbstractType {
                 if second == AbstractType::NegNumber
                     || second == AbstractType::ConcreteType(Type::Int)
                     || second == AbstractType::ConcreteType(Type::Float)
+                    || second == AbstractType::ConcreteType(Type::Float32)
                 {
                     Ok(second)
                 } else if second == AbstractType::Number {
@@ -124,14 +334,18 @@ impl AbstractType {
                     }
                 }
                 ty @ AbstractType::Number => {
-                    if ct == &Type::Int || ct == &Type::UInt || ct == &Type::Float {
+                    if ct == &Type::Int
+                        || ct == &Type::UInt
+                        || ct == &Type::Float
+                        || ct == &Type::Float32
+                    {
                         Ok(first)
                     } else {
                         Err(format!("Unable to coerce Number into {:?}", ty).into())
                     }
                 }
                 ty @ AbstractType::NegNumber => {
-                    if ct == &Type::Int || ct == &Type::Float {
+                    if ct == &Type::Int || ct == &Type::Float || ct == &Type::Float32 {
                         Ok(first)
                     } else {
                         Err(format!("Unable to coerce NegNumber into {:?}", ty).into())
@@ -160,15 +374,65 @@ pub enum ValueData {
     Int(i64),
     UInt(u64),
     Float(f64),
+    Float32(f32),
     List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Closure(Rc<Closure>),
     Unit,
     Char(u8),
+    UChar(char),
     Bool(bool),
     // Abstract types below
     Number(u64),
     NegNumber(i64),
 }
 
+/// A function created with `fn`. The body isn't evaluated until the function is called
+#[derive(Debug, PartialEq, Clone)]
+pub struct Closure {
+    params: Vec<String>,
+    body: Node,
+    /// The variables the body uses, with the values they had when the function was created
+    captured: Vec<(String, Value)>,
+}
+
+impl Closure {
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    /// Copies the closure, capturing the current value of each variable its body uses other
+    /// than the parameters. Variables that aren't set yet are looked up when the function is
+    /// called instead, e.g. the function's own name when it calls itself
+    pub(crate) fn capture(&self, state: &State) -> Value {
+        let mut captured: Vec<(String, Value)> = Vec::new();
+        let mut nodes = vec![&self.body];
+
+        while let Some(node) = nodes.pop() {
+            match node {
+                leaf_node_pattern!(Ident(name))
+                    if !self.params.contains(name) && captured.iter().all(|(n, _)| n != name) =>
+                {
+                    if let Ok(val) = state.get_var(name) {
+                        captured.push((name.clone(), val.clone()));
+                    }
+                }
+                Node::Rule(RuleNodeData { children, .. }) => nodes.extend(children),
+                Node::Leaf(_) => (),
+            }
+        }
+
+        Value::new(
+            Type::Func.into(),
+            ValueData::Closure(Rc::new(Closure {
+                params: self.params.clone(),
+                body: self.body.clone(),
+                captured,
+            })),
+        )
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Value {
     ty: AbstractType,
@@ -180,10 +444,120 @@ impl Value {
         Self { ty, val }
     }
 
+    /// Collapses the abstract number types into concrete ones, so values handed back to the user
+    /// don't need to be interpreted. A `Number` becomes an `int` (or a `uint` if it's too large
+    /// for one) and a `NegNumber` becomes an `int`. Elements of lists and tuples are converted to
+    /// the element type of their container. An empty list on its own has no element type to
+    /// resolve to, so it's left as is
+    pub fn resolve(self) -> Value {
+        self.resolve_with(NumberDefault::Int)
+    }
+
+    /// Like `resolve`, but a `Number` becomes the type given by `default` instead of an `int`
+    pub fn resolve_with(self, default: NumberDefault) -> Value {
+        match self.ty {
+            AbstractType::Number => match (&self.val, default) {
+                (ValueData::Number(n), NumberDefault::Int) if *n > i64::MAX as u64 => {
+                    self.resolve_to(&Type::UInt)
+                }
+                (_, NumberDefault::Int) => self.resolve_to(&Type::Int),
+                (_, NumberDefault::UInt) => self.resolve_to(&Type::UInt),
+            },
+            AbstractType::NegNumber => self.resolve_to(&Type::Int),
+            AbstractType::ConcreteType(ref ty) => {
+                let ty = ty.clone();
+                self.resolve_to(&ty)
+            }
+            AbstractType::List => self,
+        }
+    }
+
+    fn resolve_to(self, ty: &Type) -> Value {
+        let val = match (self.val, ty) {
+            (ValueData::Number(n), Type::Int) => ValueData::Int(n as i64),
+            (ValueData::Number(n), Type::UInt) => ValueData::UInt(n),
+            (ValueData::Number(n), Type::Float) => ValueData::Float(n as f64),
+            (ValueData::Number(n), Type::Float32) => ValueData::Float32(n as f32),
+            (ValueData::NegNumber(n), Type::Int) => ValueData::Int(n),
+            (ValueData::NegNumber(n), Type::Float) => ValueData::Float(n as f64),
+            (ValueData::NegNumber(n), Type::Float32) => ValueData::Float32(n as f32),
+            (ValueData::List(vals), Type::List(inner)) => {
+                ValueData::List(vals.into_iter().map(|v| v.resolve_to(inner)).collect())
+            }
+            (ValueData::Tuple(vals), Type::Tuple(tys)) => ValueData::Tuple(
+                vals.into_iter()
+                    .zip(tys)
+                    .map(|(v, ty)| v.resolve_to(ty))
+                    .collect(),
+            ),
+            (val, _) => return Value::new(self.ty, val),
+        };
+
+        Value::new(ty.clone().into(), val)
+    }
+
+    /// Builds a list from its elements, finding the element type the same way a list literal
+    /// would. Returns an Err if the elements can't be coerced to a common type. With no elements
+    /// the list is left abstract, like `[]`
+    pub fn list_from<I: IntoIterator<Item = Value>>(items: I) -> InterpreteResult<Value> {
+        let vals: Vec<Value> = items.into_iter().collect();
+
+        if vals.is_empty() {
+            return Ok(Value::new(AbstractType::List, ValueData::List(vals)));
+        }
+
+        let ty = infer_list_element_type(&vals, &[], NumberDefault::Int)?;
+
+        Ok(Value::new(
+            Type::List(Box::new(ty)).into(),
+            ValueData::List(vals),
+        ))
+    }
+
+    /// Builds a `list<char>` value from a string, same as `Value::from(String)`
+    pub fn string_from(s: &str) -> Value {
+        s.to_string().into()
+    }
+
+    /// Only defined for `list<char>` values (or an empty list), the chars need to make up valid
+    /// UTF-8
+    pub fn as_string(&self) -> InterpreteResult<String> {
+        let is_string = match &self.ty {
+            AbstractType::List => true,
+            AbstractType::ConcreteType(Type::List(ty)) => **ty == Type::Char,
+            _ => false,
+        };
+
+        if !is_string {
+            return Err(format!("Tried to convert invalid value to string: {:?}", self).into());
+        }
+
+        let bytes = self
+            .try_as_list()?
+            .iter()
+            .map(Value::try_as_char)
+            .collect::<InterpreteResult<Vec<_>>>()?;
+
+        String::from_utf8(bytes).map_err(|e| format!("String is not valid UTF-8: {}", e).into())
+    }
+
     pub fn is_list(&self) -> bool {
         self.ty == AbstractType::List
     }
 
+    /// Estimates the bytes this value has allocated on the heap, not counting the `Value` itself.
+    /// Lists and tuples count each element plus whatever the elements allocate in turn. Closures
+    /// are shared between copies, so they aren't counted
+    pub fn heap_size(&self) -> usize {
+        match &self.val {
+            ValueData::List(vals) | ValueData::Tuple(vals) => vals
+                .iter()
+                .map(|val| mem::size_of::<Value>() + val.heap_size())
+                .sum(),
+            _ => 0,
+        }
+    }
+
     /// Only defined for `Number` typed vars
     pub fn try_as_number(&self) -> InterpreteResult<u64> {
         if let ValueData::Number(n) = self.val {
@@ -193,11 +567,12 @@ impl Value {
         }
     }
 
-    /// Only defined for `Number` and `NegNumber` typed vars
+    /// Only defined for `Number` and `NegNumber` typed vars. A `Number` larger than the largest
+    /// `int` gives an Err, since it can't take part in signed arithmetic
     pub fn try_as_negnumber(&self) -> InterpreteResult<i64> {
         if let ValueData::Number(n) = self.val {
-            // TODO check bounds
-            Ok(n as i64)
+            i64::try_from(n)
+                .map_err(|_| format!("Number too large to use as a negnumber: {}", n).into())
         } else if let ValueData::NegNumber(n) = self.val {
             Ok(n)
         } else {
@@ -205,10 +580,12 @@ impl Value {
         }
     }
 
-    /// Only defined for `Number`, `NegNumber`, and `int` typed vars
+    /// Only defined for `Number`, `NegNumber`, and `int` typed vars. A `Number` larger than the
+    /// largest `int` gives an Err
     pub fn try_as_int(&self) -> InterpreteResult<i64> {
         match self.val {
-            ValueData::Number(n) => Ok(n as i64),
+            ValueData::Number(n) => i64::try_from(n)
+                .map_err(|_| format!("Number too large to use as an int: {}", n).into()),
             ValueData::NegNumber(n) => Ok(n),
             ValueData::Int(n) => Ok(n),
             _ => Err(format!("Tried to convert invalid value to int: {:?}", self).into()),
@@ -234,6 +611,16 @@ impl Value {
         }
     }
 
+    /// Only defined for `Number`, `NegNumber`, and `float32` type vars
+    pub fn try_as_float32(&self) -> InterpreteResult<f32> {
+        match self.val {
+            ValueData::Number(n) => Ok(n as f32),
+            ValueData::NegNumber(n) => Ok(n as f32),
+            ValueData::Float32(f) => Ok(f),
+            _ => Err(format!("Tried to convert invalid value to float32: {:?}", self).into()),
+        }
+    }
+
     /// Only defined for `List` types (Abstract list type should never be around at this
     /// point)
     pub fn try_as_list(&self) -> InterpreteResult<Vec<Value>> {
@@ -243,6 +630,22 @@ impl Value {
         }
     }
 
+    /// Only defined for `Tuple` types
+    pub fn try_as_tuple(&self) -> InterpreteResult<Vec<Value>> {
+        match &self.val {
+            ValueData::Tuple(vals) => Ok(vals.clone()),
+            _ => Err(format!("Tried to convert invalid value to tuple: {:?}", self).into()),
+        }
+    }
+
+    /// Only defined for `fn` types
+    pub fn try_as_closure(&self) -> InterpreteResult<Rc<Closure>> {
+        match &self.val {
+            ValueData::Closure(c) => Ok(c.clone()),
+            _ => Err(format!("Tried to convert invalid value to function: {:?}", self).into()),
+        }
+    }
+
     /// Only defined for `Unit` type
     pub fn try_as_unit(&self) -> InterpreteResult<()> {
         match &self.val {
@@ -259,6 +662,14 @@ impl Value {
         }
     }
 
+    /// Only defined for `UChar` type
+    pub fn try_as_uchar(&self) -> InterpreteResult<char> {
+        match self.val {
+            ValueData::UChar(c) => Ok(c),
+            _ => Err(format!("Tried to convert invalid value to uchar: {:?}", self).into()),
+        }
+    }
+
     /// Only defined for `Bool` type
     pub fn try_as_bool(&self) -> InterpreteResult<bool> {
         match self.val {
@@ -266,6 +677,146 @@ impl Value {
             _ => Err(format!("Tried to convert invalid value to bool: {:?}", self).into()),
         }
     }
+
+    /// Check whether two values are equal after coercing them to a common type, so e.g.
+    /// `2` and `2u` are equal. Lists and tuples are compared element by element. Returns an Err
+    /// if the types can't be coerced
+    pub fn semantic_eq(&self, other: &Value) -> InterpreteResult<bool> {
+        if let (ValueData::List(vals1), ValueData::List(vals2))
+        | (ValueData::Tuple(vals1), ValueData::Tuple(vals2)) = (&self.val, &other.val)
+        {
+            if vals1.len() != vals2.len() {
+                return Ok(false);
+            }
+
+            for (v1, v2) in vals1.iter().zip(vals2.iter()) {
+                if !v1.semantic_eq(v2)? {
+                    return Ok(false);
+                }
+            }
+
+            return Ok(true);
+        }
+
+        match AbstractType::coerce_types(self.ty.clone(), other.ty.clone())? {
+            AbstractType::Number => Ok(self.try_as_number()? == other.try_as_number()?),
+            AbstractType::NegNumber => Ok(self.try_as_negnumber()? == other.try_as_negnumber()?),
+            AbstractType::ConcreteType(ct) => match ct {
+                Type::Int => Ok(self.try_as_int()? == other.try_as_int()?),
+                Type::UInt => Ok(self.try_as_uint()? == other.try_as_uint()?),
+                Type::Float => Ok(self.try_as_float()? == other.try_as_float()?),
+                Type::Float32 => Ok(self.try_as_float32()? == other.try_as_float32()?),
+                Type::Char => Ok(self.try_as_char()? == other.try_as_char()?),
+                Type::UChar => Ok(self.try_as_uchar()? == other.try_as_uchar()?),
+                Type::Bool => Ok(self.try_as_bool()? == other.try_as_bool()?),
+                Type::Unit => Ok(true),
+                Type::Func => Err("Functions can't be compared".into()),
+                Type::List(_) | Type::Tuple(_) => Err(format!(
+                    "Found list or tuple type for non-list values: {:?}, {:?}",
+                    self, other
+                )
+                .into()),
+            },
+            ty => Err(format!("Unable to compare values of type {:?}", ty).into()),
+        }
+    }
+
+    /// Order two values after coercing them to a common type, so e.g. `2 < 2.5`. Lists and
+    /// tuples are ordered lexicographically. Returns an Err if the types can't be coerced or
+    /// either value is a NaN float
+    pub fn try_cmp(&self, other: &Value) -> InterpreteResult<Ordering> {
+        if let (ValueData::List(vals1), ValueData::List(vals2))
+        | (ValueData::Tuple(vals1), ValueData::Tuple(vals2)) = (&self.val, &other.val)
+        {
+            for (v1, v2) in vals1.iter().zip(vals2.iter()) {
+                match v1.try_cmp(v2)? {
+                    Ordering::Equal => (),
+                    ord => return Ok(ord),
+                }
+            }
+
+            return Ok(vals1.len().cmp(&vals2.len()));
+        }
+
+        match AbstractType::coerce_types(self.ty.clone(), other.ty.clone())? {
+            AbstractType::Number => Ok(self.try_as_number()?.cmp(&other.try_as_number()?)),
+            AbstractType::NegNumber => Ok(self.try_as_negnumber()?.cmp(&other.try_as_negnumber()?)),
+            AbstractType::ConcreteType(ct) => match ct {
+                Type::Int => Ok(self.try_as_int()?.cmp(&other.try_as_int()?)),
+                Type::UInt => Ok(self.try_as_uint()?.cmp(&other.try_as_uint()?)),
+                Type::Float => self
+                    .try_as_float()?
+                    .partial_cmp(&other.try_as_float()?)
+                    .ok_or_else(|| {
+                        format!("Unable to order NaN values: {:?}, {:?}", self, other).into()
+                    }),
+                Type::Float32 => self
+                    .try_as_float32()?
+                    .partial_cmp(&other.try_as_float32()?)
+                    .ok_or_else(|| {
+                        format!("Unable to order NaN values: {:?}, {:?}", self, other).into()
+                    }),
+                Type::Char => Ok(self.try_as_char()?.cmp(&other.try_as_char()?)),
+                Type::UChar => Ok(self.try_as_uchar()?.cmp(&other.try_as_uchar()?)),
+                Type::Bool => Ok(self.try_as_bool()?.cmp(&other.try_as_bool()?)),
+                Type::Unit => Ok(Ordering::Equal),
+                Type::Func => Err("Functions can't be ordered".into()),
+                Type::List(_) | Type::Tuple(_) => Err(format!(
+                    "Found list or tuple type for non-list values: {:?}, {:?}",
+                    self, other
+                )
+                .into()),
+            },
+            ty => Err(format!("Unable to order values of type {:?}", ty).into()),
+        }
+    }
+}
+
+/// Rust types with a fixed BLisp type. This is what lets values converted from Rust (e.g. via
+/// `#[derive(IntoValue)]`) get a concrete type, even for empty `Vec`s
+pub trait BLispType {
+    fn blisp_type() -> Type;
+}
+
+macro_rules! impl_blisp_type {
+    ($($rust_ty:ty => $ty:expr),+ $(,)?) => {
+        $(
+            impl BLispType for $rust_ty {
+                fn blisp_type() -> Type {
+                    $ty
+                }
+            }
+        )+
+    };
+}
+
+impl_blisp_type!(
+    u64 => Type::UInt,
+    i64 => Type::Int,
+    f64 => Type::Float,
+    f32 => Type::Float32,
+    u8 => Type::Char,
+    char => Type::UChar,
+    bool => Type::Bool,
+    () => Type::Unit,
+    String => Type::List(Box::new(Type::Char)),
+);
+
+impl<T: BLispType> BLispType for Vec<T> {
+    fn blisp_type() -> Type {
+        Type::List(Box::new(T::blisp_type()))
+    }
+}
+
+pub use interprete_rs_macros::IntoValue;
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self {
+            ty: Type::Bool.into(),
+            val: ValueData::Bool(value),
+        }
+    }
 }
 
 impl From<u8> for Value {
@@ -277,6 +828,33 @@ impl From<u8> for Value {
     }
 }
 
+impl From<char> for Value {
+    fn from(value: char) -> Self {
+        Self {
+            ty: Type::UChar.into(),
+            val: ValueData::UChar(value),
+        }
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Self {
+            ty: Type::UInt.into(),
+            val: ValueData::UInt(value),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Self {
+            ty: Type::Int.into(),
+            val: ValueData::Int(value),
+        }
+    }
+}
+
 impl From<f64> for Value {
     fn from(value: f64) -> Self {
         Self {
@@ -286,6 +864,15 @@ impl From<f64> for Value {
     }
 }
 
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Self {
+            ty: Type::Float32.into(),
+            val: ValueData::Float32(value),
+        }
+    }
+}
+
 impl From<String> for Value {
     fn from(value: String) -> Self {
         Value {
@@ -295,8 +882,17 @@ impl From<String> for Value {
     }
 }
 
+impl<T: BLispType + Into<Value>> From<Vec<T>> for Value {
+    fn from(value: Vec<T>) -> Self {
+        Value {
+            ty: Vec::<T>::blisp_type().into(),
+            val: ValueData::List(value.into_iter().map(Into::into).collect()),
+        }
+    }
+}
+
 impl From<()> for Value {
-    fn from(value: ()) -> Self {
+    fn from(_: ()) -> Self {
         Value {
             ty: Type::Unit.into(),
             val: ValueData::Unit,
@@ -304,6 +900,38 @@ impl From<()> for Value {
     }
 }
 
+macro_rules! impl_try_from_value {
+    ($($rust_ty:ty => $func:ident),+ $(,)?) => {
+        $(
+            impl TryFrom<Value> for $rust_ty {
+                type Error = InterpretError;
+
+                fn try_from(value: Value) -> Result<Self, Self::Error> {
+                    value.$func()
+                }
+            }
+        )+
+    };
+}
+
+impl_try_from_value!(
+    i64 => try_as_int,
+    u64 => try_as_uint,
+    f64 => try_as_float,
+    bool => try_as_bool,
+    u8 => try_as_char,
+    Vec<Value> => try_as_list,
+);
+
+/// Only defined for `list<char>` values (including an empty list) whose bytes are valid UTF-8
+impl TryFrom<Value> for String {
+    type Error = InterpretError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.as_string()
+    }
+}
+
 impl TryFrom<NumLiteral> for Value {
     type Error = InterpretError;
 
@@ -319,10 +947,12 @@ impl TryFrom<NumLiteral> for Value {
                 if float {
                     Ok(value.to_f64_checked()?.into())
                 } else if negative {
-                    Ok(Value::new(
-                        AbstractType::NegNumber,
-                        ValueData::NegNumber(-(int_part as i64)),
-                    ))
+                    // The magnitude of the smallest `int` is one more than the largest
+                    let n = 0i64.checked_sub_unsigned(int_part).ok_or_else(|| {
+                        format!("Negative number literal doesn't fit in an int: {}", value)
+                    })?;
+
+                    Ok(Value::new(AbstractType::NegNumber, ValueData::NegNumber(n)))
                 } else {
                     Ok(Value::new(
                         AbstractType::Number,
@@ -381,26 +1011,16 @@ impl TryFrom<ParseToken> for Value {
         match value {
             ParseToken::NumLiteral(n) => Self::try_from(n),
             ParseToken::CharLiteral(c) => Ok(c.into()),
+            ParseToken::UCharLiteral(c) => Ok(c.into()),
             ParseToken::UnitLiteral => Ok(Value::new(Type::Unit.into(), ValueData::Unit)),
             ParseToken::StringLiteral(s) => Ok(s.into()),
-            t => Err("Expected a literal token".into()), //ParseToken::Ident(_) => todo!(),
+            _ => Err("Expected a literal token".into()), //ParseToken::Ident(_) => todo!(),
                                                          //ParseToken::Type(_) => todo!(),
                                                          //ParseToken::Reserved(_) => todo!(),
         }
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
-/// This holds the type of an argument. When executing a function we first check for the
-/// accepted arguments for the function via crate::blisp::functions::get_arg_types
-pub enum ArgumentType {
-    /// Specifies a value type (may include variables as well).
-    Value,
-    Type,
-    // This indicates an ident is required, as in `(set <ident> 3)`
-    Ident,
-}
-
 #[derive(PartialEq, Clone, Debug)]
 pub enum Argument {
     Value(Value),
@@ -435,34 +1055,49 @@ impl Argument {
         }
     }
 
-    /// If this is a Value-type argument get its associated type
+    /// If this is a Value-type argument get its associated type. The abstract list type
+    /// only shows up here for empty lists, since their element type is unknown
     pub fn try_get_val_type(&self) -> InterpreteResult<AbstractType> {
-        let ty = self.try_get_val()?.ty.clone();
-
-        match ty {
-            AbstractType::ConcreteType(ct) => Ok(ct.into()),
-            AbstractType::List => Err(format!(
-                "Unexpectedly found abstract list type when parsing argument {:?}",
-                self
-            )
-            .into()),
-            t => Ok(t),
-        }
+        Ok(self.try_get_val()?.ty.clone())
     }
 }
 
-pub struct Func {
-    f: ReservedIdent,
-    args: Vec<Argument>,
+/// Evaluate a program with a fresh State. The tree is only borrowed, so it can be evaluated
+/// again afterwards
+pub fn eval(node: &Node) -> InterpreteResult<Value> {
+    let mut state = State::new();
+
+    eval_with_state(node, &mut state)
 }
 
-pub fn eval(node: Node) -> InterpreteResult<Value> {
-    let mut state = State::new();
+/// Evaluate a program with an existing State, e.g. one created with `State::with_writer` to
+/// capture output. The result is resolved to a concrete type using the State's number default,
+/// see `Value::resolve_with`
+pub fn eval_with_state(node: &Node, state: &mut State) -> InterpreteResult<Value> {
+    let default = state.number_default;
+
+    eval_prog_node(node, state).map(|val| val.resolve_with(default))
+}
 
-    eval_prog_node(node, &mut state)
+/// Lexes, parses, and evaluates `input` with a new State, after `setup` has had a chance to
+/// configure it. Returns the result along with everything the program wrote
+#[cfg(test)]
+pub(crate) fn eval_with_setup(
+    input: &str,
+    setup: impl FnOnce(&mut State),
+) -> InterpreteResult<(Value, Vec<u8>)> {
+    let mut out = Vec::new();
+    let mut state = State::with_writer(&mut out);
+    setup(&mut state);
+
+    let tokens = crate::blisp::lexer::tokenize(input.chars().collect())?;
+    let val = eval_with_state(&crate::blisp::parser::parse_prog(&tokens)?.0, &mut state)?;
+    drop(state);
+
+    Ok((val, out))
 }
 
-pub fn eval_node(node: Node) -> InterpreteResult<Value> {
+pub fn eval_node(_node: &Node) -> InterpreteResult<Value> {
     unimplemented!()
 }
 
@@ -474,14 +1109,15 @@ pub fn eval_node(node: Node) -> InterpreteResult<Value> {
 //    }
 //}
 
-fn eval_leaf_node(node: Node, state: &State) -> InterpreteResult<Value> {
+fn eval_leaf_node(node: &Node, state: &State) -> InterpreteResult<Value> {
     if let Node::Leaf(tok) = node {
         match tok {
-            ParseToken::NumLiteral(n) => n.try_into(),
-            ParseToken::CharLiteral(c) => Ok(c.into()),
+            ParseToken::NumLiteral(n) => (*n).try_into(),
+            ParseToken::CharLiteral(c) => Ok((*c).into()),
+            ParseToken::UCharLiteral(c) => Ok((*c).into()),
             ParseToken::UnitLiteral => Ok(().into()),
-            ParseToken::StringLiteral(s) => Ok(s.into()),
-            ParseToken::Ident(i) => state.get_var(&i).cloned(),
+            ParseToken::StringLiteral(s) => Ok(s.clone().into()),
+            ParseToken::Ident(i) => state.get_var(i).cloned(),
             t => Err(format!("Expected literal or identifier, found {:?}", t).into()),
         }
     } else {
@@ -489,42 +1125,49 @@ fn eval_leaf_node(node: Node, state: &State) -> InterpreteResult<Value> {
     }
 }
 
-fn eval_prog_node(node: Node, state: &mut State) -> InterpreteResult<Value> {
+fn eval_prog_node(node: &Node, state: &mut State) -> InterpreteResult<Value> {
     if let Node::Rule(RuleNodeData {
         rule: Rule::Prog,
-        mut children,
+        children,
     }) = node
     {
         assert!(children.len() == 1);
-        eval_expr_node(children.pop().unwrap(), state)
+
+        state.traced(
+            || "Prog".to_string(),
+            |state| match &children[0] {
+                node @ rule_node_pattern!(Val) => eval_val_node(node, state),
+                node => eval_expr_node(node, state),
+            },
+        )
     } else {
         Err(format!("Expected Prog node, found: {:?}", node).into())
     }
 }
 
-fn eval_expr_node(node: Node, state: &mut State) -> InterpreteResult<Value> {
+fn eval_expr_node(node: &Node, state: &mut State) -> InterpreteResult<Value> {
     if let Node::Rule(RuleNodeData {
         rule: Rule::Expr,
-        mut children,
+        children,
     }) = node
     {
         assert!(children.len() == 1);
-        eval_expr_body_node(children.pop().unwrap(), state)
+        eval_expr_body_node(&children[0], state)
     } else {
         Err(format!("Expected Expr node, found: {:?}", node).into())
     }
 }
 
-fn eval_expr_body_node(node: Node, state: &mut State) -> InterpreteResult<Value> {
+fn eval_expr_body_node(node: &Node, state: &mut State) -> InterpreteResult<Value> {
     if let Node::Rule(RuleNodeData {
         rule: Rule::ExprBody,
-        mut children,
+        children,
     }) = node
     {
         assert!(children.len() == 1);
-        let node = children.pop().unwrap();
+        let node = &children[0];
 
-        match &node {
+        match node {
             Node::Rule(RuleNodeData {
                 rule: Rule::Val, ..
             }) => eval_val_node(node, state),
@@ -543,188 +1186,449 @@ fn eval_expr_body_node(node: Node, state: &mut State) -> InterpreteResult<Value>
     }
 }
 
-fn eval_val_node(node: Node, state: &mut State) -> InterpreteResult<Value> {
-    if let rule_node_pattern!(Val;mut children) = node {
+fn eval_val_node(node: &Node, state: &mut State) -> InterpreteResult<Value> {
+    if let rule_node_pattern!(Val; children) = node {
         assert!(children.len() == 1);
 
-        match children.pop().unwrap() {
-            leaf_node_pattern!(CharLiteral(c)) => Ok(c.into()),
-            leaf_node_pattern!(StringLiteral(s)) => Ok(s.into()),
-            leaf_node_pattern!(NumLiteral(n)) => n.try_into(),
-            leaf_node_pattern!(UnitLiteral) => Ok(().into()),
-            leaf_node_pattern!(Ident(i)) => state.get_var(&i).cloned(),
-            rule_node_pattern!(List => node) => eval_list_node(node, state),
-            rule_node_pattern!(Expr => node) => eval_expr_node(node, state),
-            n => Err(format!("Encountered invalid node when evaluating Val: {:?}", n).into()),
-        }
+        state.traced(
+            || "Val".to_string(),
+            |state| match &children[0] {
+                node @ Node::Leaf(_) => eval_leaf_node(node, state),
+                node @ rule_node_pattern!(List) => eval_list_node(node, state),
+                node @ rule_node_pattern!(Expr) => eval_expr_node(node, state),
+                n => Err(format!("Encountered invalid node when evaluating Val: {:?}", n).into()),
+            },
+        )
     } else {
         Err(format!("Expected Val node, found: {:?}", node).into())
     }
 }
 
-fn eval_func_call_node(node: Node, state: &mut State) -> InterpreteResult<Value> {
-    if let rule_node_pattern!(FuncCall; mut children) = node {
+fn eval_func_call_node(node: &Node, state: &mut State) -> InterpreteResult<Value> {
+    if let rule_node_pattern!(FuncCall; children) = node {
         assert!(children.len() == 2);
 
-        let args_node = children.pop().unwrap();
+        let (func_node, args_node) = (&children[0], &children[1]);
 
-        match children.pop().unwrap() {
-            leaf_node_pattern!(Reserved(rsv)) => {
-                let func = rsv;
-                let args = eval_args_node(args_node, state)?;
+        // Lists in the arguments aren't sublists of a list the call is in, so they start over
+        let list_path = mem::take(&mut state.list_path);
 
-                eval_function(func, args)
-            }
-            n => Err(format!("Expected function name, found {:?}", n).into()),
-        }
+        let res = state.traced(
+            || format!("FuncCall {}", func_node.to_source()),
+            |state| match func_node {
+                leaf_node_pattern!(Reserved(ReservedIdent::Fn)) => {
+                    Ok(parse_fn_node(args_node)?.capture(state))
+                }
+                leaf_node_pattern!(Reserved(ReservedIdent::Times)) => {
+                    eval_times_node(args_node, state)
+                }
+                leaf_node_pattern!(Reserved(ReservedIdent::Let)) => eval_let_node(args_node, state),
+                leaf_node_pattern!(Reserved(ReservedIdent::Try)) => eval_try_node(args_node, state),
+                leaf_node_pattern!(Reserved(ReservedIdent::Assert)) => {
+                    // Rendered before evaluating so a failed assertion can show what was asserted
+                    let source = args_node.to_source();
+                    let args = eval_args_node(args_node, state, &[])?;
+
+                    eval_assert(args, Some(&source))
+                }
+                leaf_node_pattern!(Reserved(rsv)) => {
+                    let func = *rsv;
+                    let args = eval_args_node(args_node, state, &func.arg_types())?;
+
+                    eval_function(func, args, state)
+                }
+                leaf_node_pattern!(Ident(name)) => {
+                    let args = eval_args_node(args_node, state, &[])?;
+
+                    state.call_ident(name, args)
+                }
+                n => Err(format!("Expected function name, found {:?}", n).into()),
+            },
+        );
+
+        state.list_path = list_path;
+        res
     } else {
         Err(format!("Expected FuncCall node, found: {:?}", node).into())
     }
 }
 
-fn eval_args_node(node: Node, state: &mut State) -> InterpreteResult<Vec<Argument>> {
-    if let rule_node_pattern!(Args; mut children) = node {
-        if children.len() == 1 {
-            // Reached terminal state, nearly done
-            match children.pop().unwrap() {
-                rule_node_pattern!(Val => node) => {
-                    Ok([eval_val_node(node, state)?.into()].to_vec())
-                }
-                n => Err(format!("Expected Val while parsing ListBody, found: {:?}", n).into()),
-            }
-        } else {
-            assert!(children.len() == 2);
-
-            let mut tail = eval_args_node(children.pop().unwrap(), state)?;
-            let val = eval_val_node(children.pop().unwrap(), state)?;
+/// Evaluates the arguments of a function call, left to right so side effects happen in source
+/// order. `arg_types` are the kinds of argument the function expects, see `eval_arg_node`
+fn eval_args_node(
+    node: &Node,
+    state: &mut State,
+    arg_types: &[ArgumentType],
+) -> InterpreteResult<Vec<Argument>> {
+    let mut res = Vec::new();
+
+    for (i, node) in flatten_args_node(node)?.into_iter().enumerate() {
+        res.push(eval_arg_node(node, state, arg_types.get(i).copied())?);
+    }
 
-            let mut res = vec![val.into()];
-            res.append(&mut tail);
+    Ok(res)
+}
 
-            Ok(res)
+/// Evaluates a single argument. A bare type is passed through as `Argument::Type` (e.g. in
+/// `(init x int)`), and so is a bare identifier in a position that expects one, as
+/// `Argument::Ident`. Anything else is evaluated as a value, which for an identifier means reading
+/// the variable
+fn eval_arg_node(
+    node: &Node,
+    state: &mut State,
+    expected: Option<ArgumentType>,
+) -> InterpreteResult<Argument> {
+    if let rule_node_pattern!(Val; children) = node {
+        match children.as_slice() {
+            [leaf_node_pattern!(Type(ty))] => return Ok(Argument::Type(ty.clone())),
+            [leaf_node_pattern!(Ident(name))] if expected == Some(ArgumentType::Ident) => {
+                return Ok(Argument::Ident(name.clone()))
+            }
+            _ => (),
         }
-    } else {
-        Err(format!("Expected ListBody node, found: {:?}", node).into())
     }
-}
 
-fn eval_list_node(node: Node, state: &mut State) -> InterpreteResult<Value> {
-    if let Node::Rule(RuleNodeData {
-        rule: Rule::List,
-        mut children,
-    }) = node
-    {
-        assert!(children.len() == 1);
-        if let Value {
-            val: ValueData::List(vals),
-            ..
-        } = eval_list_body_node(children.pop().unwrap(), state)?
-        {
-            let ty = check_list_type(vals.iter().collect())?;
+    Ok(eval_val_node(node, state)?.into())
+}
 
-            Ok(Value {
-                ty: Type::List(Box::new(ty)).into(),
-                val: ValueData::List(vals),
+/// Returns the Val node of each argument in an Args node, without evaluating them. An Args
+/// node without children stands for a call without arguments
+pub(crate) fn flatten_args_node(node: &Node) -> InterpreteResult<Vec<&Node>> {
+    if let rule_node_pattern!(Args; children) = node {
+        children
+            .iter()
+            .map(|val| match val {
+                rule_node_pattern!(Val) => Ok(val),
+                n => Err(format!("Expected Val while processing Args, found: {:?}", n).into()),
             })
-        } else {
-            Err("Malformed ListBody result".into())
-        }
+            .collect()
     } else {
         Err(format!("Expected Args node, found: {:?}", node).into())
     }
 }
 
-fn eval_list_body_node(node: Node, state: &mut State) -> InterpreteResult<Value> {
-    if let rule_node_pattern!(ListBody; mut children) = node {
-        if children.len() == 1 {
-            // Reached terminal state, nearly done
-            match children.pop().unwrap() {
-                rule_node_pattern!(Val => node) => {
-                    Ok(list_value_helper![eval_val_node(node, state)?])
-                }
-                n => Err(format!("Expected Val while parsing ListBody, found: {:?}", n).into()),
-            }
+/// Creates a closure from `(fn <params> <body>)`, before any variables are captured (see
+/// `Closure::capture`). Every argument but the last must be an identifier naming a parameter.
+/// The body is copied into the closure, since the closure can outlive the tree it was created
+/// from
+pub(crate) fn parse_fn_node(args_node: &Node) -> InterpreteResult<Closure> {
+    let args = flatten_args_node(args_node)?;
+
+    let (body, args) = match args.split_last() {
+        Some((body, args)) if !args.is_empty() => (*body, args),
+        _ => return Err("A function needs at least one parameter before its body".into()),
+    };
+
+    let mut params: Vec<String> = Vec::with_capacity(args.len());
+
+    for arg in args {
+        match arg {
+            rule_node_pattern!(Val; children) if children.len() == 1 => match &children[0] {
+                leaf_node_pattern!(Ident(i)) if params.contains(i) => {
+                    Err(format!("Duplicate parameter name: {}", i))?
+                }
+                leaf_node_pattern!(Ident(i)) => params.push(i.clone()),
+                n => return Err(format!("Expected a parameter name, found: {:?}", n).into()),
+            },
+            n => return Err(format!("Expected a parameter name, found: {:?}", n).into()),
+        }
+    }
+
+    Ok(Closure {
+        params,
+        body: body.clone(),
+        captured: Vec::new(),
+    })
+}
+
+/// Evaluates `(times <count> <body>)`. The body is evaluated once per repetition for its side
+/// effects rather than once up front like a regular argument, so it runs zero times for a count
+/// of `0`
+fn eval_times_node(args_node: &Node, state: &mut State) -> InterpreteResult<Value> {
+    let args = flatten_args_node(args_node)?;
+
+    let (count, body) = match args.as_slice() {
+        [count, body] => (*count, *body),
+        _ => {
+            return Err(format!(
+                "{} expects 2 arguments of types {:?}, got {}",
+                ReservedIdent::Times,
+                ReservedIdent::Times.arg_types(),
+                args.len()
+            )
+            .into())
+        }
+    };
+
+    let count = eval_val_node(count, state)?.try_as_uint()?;
+
+    for _ in 0..count {
+        eval_val_node(body, state)?;
+    }
+
+    Ok(().into())
+}
+
+/// Evaluates `(try <expr> <fallback>)`. If evaluating the expression fails the error is dropped
+/// and the fallback is evaluated instead, otherwise the fallback is never evaluated
+fn eval_try_node(args_node: &Node, state: &mut State) -> InterpreteResult<Value> {
+    let args = flatten_args_node(args_node)?;
+
+    let (expr, fallback) = match args.as_slice() {
+        [expr, fallback] => (*expr, *fallback),
+        _ => {
+            return Err(format!(
+                "{} expects 2 arguments of types {:?}, got {}",
+                ReservedIdent::Try,
+                ReservedIdent::Try.arg_types(),
+                args.len()
+            )
+            .into())
+        }
+    };
+
+    eval_val_node(expr, state).or_else(|_| eval_val_node(fallback, state))
+}
+
+/// Evaluates `(let <name> <value> <body>)`. The value is evaluated first, then the body with the
+/// name bound to it. Like a function parameter the variable only exists inside the body
+fn eval_let_node(args_node: &Node, state: &mut State) -> InterpreteResult<Value> {
+    let args = flatten_args_node(args_node)?;
+
+    let (name, val, body) = match args.as_slice() {
+        [name, val, body] => (*name, *val, *body),
+        _ => {
+            return Err(format!(
+                "{} expects 3 arguments of types {:?}, got {}",
+                ReservedIdent::Let,
+                ReservedIdent::Let.arg_types(),
+                args.len()
+            )
+            .into())
+        }
+    };
+
+    let name = match name {
+        rule_node_pattern!(Val; children) if children.len() == 1 => match &children[0] {
+            leaf_node_pattern!(Ident(i)) => i.clone(),
+            n => return Err(format!("Expected a variable name, found: {:?}", n).into()),
+        },
+        n => return Err(format!("Expected a variable name, found: {:?}", n).into()),
+    };
+    let val = eval_val_node(val, state)?;
+
+    eval_with_bindings(&[name], vec![val], body, state)
+}
+
+/// Calls a closure. The captured variables and then the parameters are bound as variables while
+/// the body is evaluated, shadowing any existing variables with the same names
+pub(crate) fn call_closure(
+    closure: &Closure,
+    args: Vec<Value>,
+    state: &mut State,
+) -> InterpreteResult<Value> {
+    if args.len() != closure.params.len() {
+        return Err(format!(
+            "Function expects {} arguments, got {}",
+            closure.params.len(),
+            args.len()
+        )
+        .into());
+    }
+
+    let (mut names, mut vals): (Vec<String>, Vec<Value>) = closure.captured.iter().cloned().unzip();
+    names.extend(closure.params.iter().cloned());
+    vals.extend(args);
+
+    eval_with_bindings(&names, vals, &closure.body, state)
+}
+
+/// Evaluates `body` with each name bound to the matching value, shadowing any existing variables
+/// with the same names. The previous variables are restored afterwards, even if evaluating the
+/// body fails
+fn eval_with_bindings(
+    names: &[String],
+    vals: Vec<Value>,
+    body: &Node,
+    state: &mut State,
+) -> InterpreteResult<Value> {
+    let shadowed: Vec<_> = names
+        .iter()
+        .zip(vals)
+        .map(|(name, val)| (name, state.bind_var(name.clone(), val)))
+        .collect();
+
+    let res = eval_val_node(body, state);
+
+    for (name, prev) in shadowed {
+        state.unbind_var(name, prev);
+    }
+
+    res
+}
+
+fn eval_list_node(node: &Node, state: &mut State) -> InterpreteResult<Value> {
+    if let Node::Rule(RuleNodeData {
+        rule: Rule::List,
+        children,
+    }) = node
+    {
+        assert!(children.len() <= 1);
+
+        let body = match children.first() {
+            Some(body) => body,
+            // Empty list, the element type is left abstract until it's combined with a
+            // typed value (e.g. via `prepend`)
+            None => return Value::list_from([]),
+        };
+
+        if let Value {
+            val: ValueData::List(vals),
+            ..
+        } = eval_list_body_node(body, state)?
+        {
+            let ty = infer_list_element_type(&vals, &state.list_path, state.number_default)?;
+
+            Ok(Value::new(
+                Type::List(Box::new(ty)).into(),
+                ValueData::List(vals),
+            ))
         } else {
-            assert!(children.len() == 2);
+            Err("Malformed ListBody result".into())
+        }
+    } else {
+        Err(format!("Expected Args node, found: {:?}", node).into())
+    }
+}
 
-            let tail = eval_list_body_node(children.pop().unwrap(), state)?;
-            let val = eval_val_node(children.pop().unwrap(), state)?;
+fn eval_list_body_node(node: &Node, state: &mut State) -> InterpreteResult<Value> {
+    if let rule_node_pattern!(ListBody; children) = node {
+        let mut vals = Vec::with_capacity(children.len());
 
-            if let Value {
-                val: ValueData::List(mut vec),
-                ..
-            } = tail
-            {
-                let mut res = vec![val];
-                res.append(&mut vec);
-
-                Ok(Value::new(AbstractType::List, ValueData::List(res)))
-            } else {
-                Err(format!("Malformed ListBody result: {:?}", tail).into())
+        for (i, child) in children.iter().enumerate() {
+            match child {
+                rule_node_pattern!(Val) => {
+                    state.list_path.push(i);
+                    let val = eval_val_node(child, state);
+                    state.list_path.pop();
+
+                    vals.push(val?)
+                }
+                n => Err(format!(
+                    "Expected Val while parsing ListBody, found: {:?}",
+                    n
+                ))?,
             }
         }
+
+        Ok(Value::new(AbstractType::List, ValueData::List(vals)))
     } else {
         Err(format!("Expected ListBody node, found: {:?}", node).into())
     }
 }
 
-fn check_list_type(vec: Vec<&Value>) -> InterpreteResult<Type> {
-    let init = vec[0];
+/// Finds the element type of a list from its values, which is where number literals without a
+/// suffix get their type. The rules, in order of precedence:
+/// - Every element with a concrete type must have the same one, and the number literals take on
+///   that type if they can. So `[1 2u]` is a `list<uint>` and `[1 2.0]` a `list<float>`, but
+///   `[1u -1]` is an error since a negative literal can't be a `uint`
+/// - Otherwise the list holds only number literals and is a `list<int>`, like `[1 -2]`. The one
+///   exception is a literal too large for an `int`, which makes the list a `list<uint>` as long as
+///   there are no negative literals. With `NumberDefault::UInt` a list of only non-negative
+///   literals is always a `list<uint>`
+///
+/// Non-empty sublists already have a concrete type from when they were evaluated, so this never
+/// needs to look inside them. `path` is the index of this list in each enclosing list literal,
+/// outermost first, and is used to say where the offending element is in an error
+pub(crate) fn infer_list_element_type(
+    vals: &[Value],
+    path: &[usize],
+    number_default: NumberDefault,
+) -> InterpreteResult<Type> {
+    if vals.is_empty() {
+        return Err("Unable to determine the element type of an empty list".into());
+    }
 
-    let ty = vec
-        .iter()
-        .map(|v| v.ty.clone())
-        .try_fold(init.ty.clone(), AbstractType::coerce_types)?;
-
-    match ty {
-        AbstractType::Number | AbstractType::NegNumber => Ok(Type::Int),
-        AbstractType::ConcreteType(ct) => Ok(ct),
-        AbstractType::List => {
-            // Need to recursively find the type of the nested lists
-            if let Value {
-                val: ValueData::List(vals),
-                ..
-            } = init
-            {
-                let init = check_list_type(vals.iter().collect())?;
-
-                // Fold over sublists of current list, trying to match types
-                let ty = vec
-                    .iter()
-                    .map(|&v| {
-                        if let Value {
-                            val: ValueData::List(_),
-                            ..
-                        } = v
-                        {
-                            AbstractType::ConcreteType(
-                                check_list_type(vals.iter().collect())
-                                    .expect("Something went wrong when parsing sublist types"),
-                            )
-                        } else {
-                            panic!("Something went wrong when parsing sublist types")
-                        }
-                    })
-                    .try_fold(AbstractType::ConcreteType(init), AbstractType::coerce_types)?;
-
-                if let AbstractType::ConcreteType(ct) = ty {
-                    Ok(ct)
-                } else {
-                    Err(format!(
-                        "Unable to find a concrete type for the list, found type: {:?}",
-                        ty
-                    )
-                    .into())
+    // Adds the location of element `i` to an error message, e.g. `at element 1 of sublist 0`
+    let at = |msg: String, i: usize| -> InterpretError {
+        let mut res = format!("{} at element {}", msg, i);
+
+        for sublist in path.iter().rev() {
+            res.push_str(&format!(" of sublist {}", sublist));
+        }
+
+        res.into()
+    };
+
+    // The concrete type so far and the first element that had it
+    let mut concrete: Option<(AbstractType, usize)> = None;
+    // The first element of each kind of number literal
+    let (mut number, mut negative, mut too_large) = (None, None, None);
+
+    for (i, val) in vals.iter().enumerate() {
+        match &val.ty {
+            AbstractType::Number => {
+                number = number.or(Some(i));
+
+                if val.try_as_number()? > i64::MAX as u64 {
+                    too_large = too_large.or(Some(i));
                 }
-            } else {
-                Err(format!(
-                    "Got {:?} as type of the list but initial value is not a list: {:?}",
-                    ty, init
-                )
-                .into())
+            }
+            AbstractType::NegNumber => negative = negative.or(Some(i)),
+            ty => {
+                concrete = Some(match concrete {
+                    Some((prev, first)) => (
+                        AbstractType::coerce_types(prev, ty.clone())
+                            .map_err(|e| at(e.to_string(), i))?,
+                        first,
+                    ),
+                    None => (ty.clone(), i),
+                })
             }
         }
     }
+
+    // The kind of literal that can't take on the concrete type and where it first shows up, if
+    // there is one
+    let conflict = |ty: &Type| match ty {
+        Type::Int | Type::Float | Type::Float32 => None,
+        Type::UInt => negative.map(|i| ("NegNumber", i)),
+        _ => negative
+            .map(|i| ("NegNumber", i))
+            .or(number.map(|i| ("Number", i))),
+    };
+
+    // Whichever of the two conflicting elements comes second is the one reported, since that's
+    // where the list stops making sense reading left to right
+    match concrete {
+        Some((AbstractType::ConcreteType(ty), first)) => match conflict(&ty) {
+            Some((lit, i)) => Err(at(
+                format!("Unable to coerce {} into {:?}", lit, ty),
+                first.max(i),
+            )),
+            None => Ok(ty),
+        },
+        // Every element with a concrete type is an empty list, so there's nothing to infer the
+        // type from
+        Some((_, first)) => match negative.or(number) {
+            Some(i) => Err(at(
+                "Unable to coerce a number into a list".to_string(),
+                first.max(i),
+            )),
+            None => Err("Unable to determine the element type of a list of empty lists".into()),
+        },
+        None => match (too_large, negative) {
+            (Some(large), Some(neg)) => Err(at(
+                "Unable to find a common type for negative numbers and numbers too large for an \
+                 int"
+                .to_string(),
+                large.max(neg),
+            )),
+            (Some(_), None) => Ok(Type::UInt),
+            (None, None) if number_default == NumberDefault::UInt => Ok(Type::UInt),
+            (None, _) => Ok(Type::Int),
+        },
+    }
 }
 
 #[cfg(test)]
@@ -772,7 +1676,7 @@ mod tests {
                     let input = $input.chars().collect();
                     let tokens = tokenize(input)?;
                     let prog = parse_prog(tokens.as_slice())?;
-                    let value = eval(prog.0)?;
+                    let value = eval(&prog.0)?;
 
                     assert_eq!(value, $value);
                 }
@@ -780,12 +1684,74 @@ mod tests {
         };
     }
 
+    #[test]
+    fn long_list_test() -> InterpreTestResult {
+        let input = format!(
+            "[{}]",
+            (0..50_000)
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        let tokens = tokenize(input.chars().collect())?;
+        let prog = parse_prog(tokens.as_slice())?;
+        let value = eval(&prog.0)?;
+
+        assert_eq!(value.ty, Type::List(Box::new(Type::Int)).into());
+
+        let vals = value.try_as_list()?;
+        assert_eq!(vals.len(), 50_000);
+        assert_eq!(vals[49_999].try_as_int()?, 49_999);
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_list_type_test() -> InterpreTestResult {
+        // 10 lists of 10 lists of 10 chars, 1000 elements in total
+        let inner = format!("[{}]", ["'a'"; 10].join(" "));
+        let middle = format!("[{}]", vec![inner; 10].join(" "));
+        let outer = format!("[{}]", vec![middle; 10].join(" "));
+
+        let tokens = tokenize(outer.chars().collect())?;
+        let prog = parse_prog(tokens.as_slice())?;
+        let value = eval(&prog.0)?;
+
+        assert_eq!(
+            value.ty,
+            Type::List(Box::new(Type::List(Box::new(Type::List(Box::new(
+                Type::Char
+            ))))))
+            .into()
+        );
+
+        // Only the declared type of a sublist is looked at, so one whose elements don't match it
+        // still counts as a `list<char>`
+        let sublist = |vals: Vec<Value>| {
+            Value::new(
+                Type::List(Box::new(Type::Char)).into(),
+                ValueData::List(vals),
+            )
+        };
+        let vals = [
+            sublist(vec!['a'.into()]),
+            sublist(vec![1.5.into(), true.into()]),
+        ];
+
+        assert_eq!(
+            infer_list_element_type(&vals, &[], NumberDefault::Int)?,
+            Type::List(Box::new(Type::Char))
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn basic_val_test() -> InterpreTestResult {
         let input1 = "(-1.2)".chars().collect();
         let tokens1 = tokenize(input1)?;
         let prog1 = parse_prog(tokens1.as_slice())?;
-        let value1 = eval(prog1.0)?;
+        let value1 = eval(&prog1.0)?;
         let exp1 = Value {
             ty: Type::Float.into(),
             val: ValueData::Float(-1.2),
@@ -795,41 +1761,67 @@ mod tests {
 
         do_eval_test!(
             ["(48c)", Value::from(b'0')],
+            ["(123)", Value::from(123i64)],
+            ["(-123)", Value::from(-123i64)],
             [
-                "(123)",
-                Value::new(AbstractType::Number, ValueData::Number(123))
+                "([1 2])",
+                Value::new(
+                    AbstractType::ConcreteType(Type::List(Box::new(Type::Int))),
+                    ValueData::List(vec![Value::from(1i64), Value::from(2i64),])
+                )
             ],
             [
-                "(-123)",
-                Value::new(AbstractType::NegNumber, ValueData::NegNumber(-123))
+                "([-1 2])",
+                Value::new(
+                    AbstractType::ConcreteType(Type::List(Box::new(Type::Int))),
+                    ValueData::List(vec![Value::from(-1i64), Value::from(2i64),])
+                )
             ],
             [
-                "([1 2])",
+                "([1 2u])",
                 Value::new(
-                    AbstractType::ConcreteType(Type::List(Box::new(Type::Int))),
+                    AbstractType::ConcreteType(Type::List(Box::new(Type::UInt))),
                     ValueData::List(vec![
-                        Value::new(AbstractType::Number, ValueData::Number(1)),
-                        Value::new(AbstractType::Number, ValueData::Number(2)),
+                        Value::from(1u64),
+                        Value::new(Type::UInt.into(), ValueData::UInt(2)),
                     ])
                 )
             ],
             [
-                "([-1 2])",
+                "([])",
+                Value::new(AbstractType::List, ValueData::List(vec![]))
+            ],
+            [
+                "([[] [1u]])",
                 Value::new(
-                    AbstractType::ConcreteType(Type::List(Box::new(Type::Int))),
+                    AbstractType::ConcreteType(Type::List(Box::new(Type::List(Box::new(
+                        Type::UInt
+                    ))))),
                     ValueData::List(vec![
-                        Value::new(AbstractType::NegNumber, ValueData::NegNumber(-1)),
-                        Value::new(AbstractType::Number, ValueData::Number(2)),
+                        Value::new(
+                            Type::List(Box::new(Type::UInt)).into(),
+                            ValueData::List(vec![])
+                        ),
+                        Value::new(
+                            Type::List(Box::new(Type::UInt)).into(),
+                            ValueData::List(vec![Value::new(
+                                Type::UInt.into(),
+                                ValueData::UInt(1)
+                            )])
+                        ),
                     ])
                 )
             ],
+            ["5", Value::from(5i64)],
+            ["\"hi\"", Value::from("hi".to_string())],
             [
-                "([1 2u])",
+                "[1 2 3]",
                 Value::new(
-                    AbstractType::ConcreteType(Type::List(Box::new(Type::UInt))),
+                    AbstractType::ConcreteType(Type::List(Box::new(Type::Int))),
                     ValueData::List(vec![
-                        Value::new(AbstractType::Number, ValueData::Number(1)),
-                        Value::new(Type::UInt.into(), ValueData::UInt(2)),
+                        Value::from(1i64),
+                        Value::from(2i64),
+                        Value::from(3i64),
                     ])
                 )
             ],
@@ -855,4 +1847,523 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn resolve_test() {
+        let num = |n| Value::new(AbstractType::Number, ValueData::Number(n));
+
+        assert_eq!(num(5).resolve(), Value::from(5i64));
+        assert_eq!(num(u64::MAX).resolve(), Value::from(u64::MAX));
+        assert_eq!(
+            Value::new(AbstractType::NegNumber, ValueData::NegNumber(-5)).resolve(),
+            Value::from(-5i64)
+        );
+
+        // Elements take the type of their container
+        let list = Value::new(
+            Type::List(Box::new(Type::Float)).into(),
+            ValueData::List(vec![num(1), Value::from(2.5)]),
+        );
+        assert_eq!(list.resolve(), Value::from(vec![1.0, 2.5]));
+
+        let tuple = Value::new(
+            Type::Tuple(vec![Type::UInt, Type::Char]).into(),
+            ValueData::Tuple(vec![num(1), Value::from(b'a')]),
+        );
+        assert_eq!(
+            tuple.resolve(),
+            Value::new(
+                Type::Tuple(vec![Type::UInt, Type::Char]).into(),
+                ValueData::Tuple(vec![Value::from(1u64), Value::from(b'a')])
+            )
+        );
+
+        let empty = Value::new(AbstractType::List, ValueData::List(vec![]));
+        assert_eq!(empty.clone().resolve(), empty);
+    }
+
+    #[test]
+    fn number_default_test() -> InterpreTestResult {
+        let run = |input: &str, default| {
+            eval_with_setup(input, |state| state.set_number_default(default)).map(|(val, _)| val)
+        };
+
+        assert_eq!(
+            run("([1 2 3])", NumberDefault::Int)?,
+            Value::from(vec![1i64, 2, 3])
+        );
+        assert_eq!(
+            run("([1 2 3])", NumberDefault::UInt)?,
+            Value::from(vec![1u64, 2, 3])
+        );
+        assert_eq!(run("(5)", NumberDefault::UInt)?, Value::from(5u64));
+
+        // Negative literals and other concrete types aren't affected
+        assert_eq!(
+            run("([1 -2])", NumberDefault::UInt)?,
+            Value::from(vec![1i64, -2])
+        );
+        assert_eq!(
+            run("([1 2.5])", NumberDefault::UInt)?,
+            Value::from(vec![1.0, 2.5])
+        );
+        assert_eq!(
+            run("([[1] [2 3]])", NumberDefault::UInt)?,
+            Value::from(vec![vec![1u64], vec![2, 3]])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn try_from_value_test() -> InterpreTestResult {
+        let num = Value::new(AbstractType::Number, ValueData::Number(5));
+
+        assert_eq!(i64::try_from(Value::from(-3i64))?, -3);
+        assert_eq!(i64::try_from(num.clone())?, 5);
+        assert_eq!(u64::try_from(num)?, 5);
+        assert_eq!(f64::try_from(Value::from(1.5))?, 1.5);
+        assert!(bool::try_from(Value::from(true))?);
+        assert_eq!(u8::try_from(Value::from(b'a'))?, b'a');
+        assert_eq!(String::try_from(Value::from("abc".to_string()))?, "abc");
+        assert_eq!(
+            String::try_from(Value::new(AbstractType::List, ValueData::List(vec![])))?,
+            ""
+        );
+        assert_eq!(
+            Vec::<Value>::try_from(Value::from(vec![1i64, 2]))?,
+            vec![Value::from(1i64), Value::from(2i64)]
+        );
+
+        // Type mismatches
+        assert!(i64::try_from(Value::from(1u64)).is_err());
+        assert!(u64::try_from(Value::from(-1i64)).is_err());
+        assert!(f64::try_from(Value::from(1.5f32)).is_err());
+        assert!(bool::try_from(Value::from(1i64)).is_err());
+        assert!(u8::try_from(Value::from(1u64)).is_err());
+        assert!(String::try_from(Value::from(vec![1i64])).is_err());
+        assert!(String::try_from(Value::from(vec![0xffu8])).is_err());
+        assert!(Vec::<Value>::try_from(Value::from(())).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_element_type_test() -> InterpreTestResult {
+        let list_ty = |input: &str| -> crate::error::InterpreteResult<AbstractType> {
+            let tokens = tokenize(input.chars().collect())?;
+            Ok(eval(&parse_prog(tokens.as_slice())?.0)?.ty)
+        };
+        let list_of = |ty| AbstractType::ConcreteType(Type::List(Box::new(ty)));
+
+        assert_eq!(list_ty("[1 2u]")?, list_of(Type::UInt));
+        assert_eq!(list_ty("[1 -2]")?, list_of(Type::Int));
+        assert_eq!(list_ty("[1 2.0]")?, list_of(Type::Float));
+        assert_eq!(list_ty("[-1 2.0 3]")?, list_of(Type::Float));
+        assert_eq!(list_ty("[1 18446744073709551615]")?, list_of(Type::UInt));
+
+        assert_eq!(
+            list_ty("[1u -1]").unwrap_err().to_string(),
+            "Unable to coerce NegNumber into UInt at element 1"
+        );
+        assert_eq!(
+            list_ty("['d' 2]").unwrap_err().to_string(),
+            "Unable to coerce Number into Char at element 1"
+        );
+        assert!(list_ty("[1u 2.0]").is_err());
+        assert!(list_ty("[-1 18446744073709551615]").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_list_error_test() -> InterpreTestResult {
+        let err = |input: &str| -> crate::error::InterpreteResult<String> {
+            let tokens = tokenize(input.chars().collect())?;
+            Ok(eval(&parse_prog(tokens.as_slice())?.0)
+                .unwrap_err()
+                .to_string())
+        };
+
+        assert_eq!(
+            err("[[1 2] [3 'a']]")?,
+            "Unable to coerce Number into Char at element 1 of sublist 1"
+        );
+        assert_eq!(
+            err("[[] [[1] [2 'b' 3]]]")?,
+            "Unable to coerce Number into Char at element 1 of sublist 1 of sublist 1"
+        );
+
+        // Sublists that are fine on their own but don't match each other
+        assert_eq!(
+            err("[[[1u]] [[2u] ['a']]]")?,
+            "Unable to coerce List(UInt) into List(Char) at element 1 of sublist 1"
+        );
+        assert_eq!(
+            err("[[[1u]] [['a']]]")?,
+            "Unable to coerce List(List(UInt)) into List(List(Char)) at element 1"
+        );
+
+        // A list in a function call is counted from scratch
+        assert_eq!(
+            err("[[(head [[1] ['a']])]]")?,
+            "Unable to coerce List(Int) into List(Char) at element 1"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn ident_args_test() -> InterpreTestResult {
+        fn find_args(node: Node) -> Option<Node> {
+            match node {
+                rule_node_pattern!(Args) => Some(node),
+                Node::Rule(RuleNodeData { children, .. }) => {
+                    children.into_iter().find_map(find_args)
+                }
+                Node::Leaf(_) => None,
+            }
+        }
+
+        let args = |input: &str, func: ReservedIdent| -> crate::error::InterpreteResult<_> {
+            let tokens = tokenize(input.chars().collect())?;
+            let args_node = find_args(parse_prog(tokens.as_slice())?.0).unwrap();
+
+            eval_args_node(&args_node, &mut State::new(), &func.arg_types())
+        };
+
+        assert_eq!(
+            args("(init x int)", ReservedIdent::Init)?,
+            [Argument::Ident("x".to_string()), Argument::Type(Type::Int)]
+        );
+
+        // `x` doesn't exist yet, so reading it as a variable would be an error
+        assert_eq!(
+            args("(set x (add 1 2))", ReservedIdent::Set)?,
+            [
+                Argument::Ident("x".to_string()),
+                Argument::Value(Value::new(AbstractType::Number, ValueData::Number(3)))
+            ]
+        );
+
+        // Anywhere else an identifier is still a variable read
+        assert_eq!(
+            args("(add x 1)", ReservedIdent::Add)
+                .unwrap_err()
+                .to_string(),
+            "Variable has not been initialized at all: x"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn heap_size_test() -> InterpreTestResult {
+        let size = mem::size_of::<Value>();
+
+        assert_eq!(Value::from(5i64).heap_size(), 0);
+        assert_eq!(Value::from(vec![1u64, 2, 3]).heap_size(), 3 * size);
+
+        // The outer list holds two values, which hold three more between them
+        let tokens = tokenize("[[1 2] [3]]".chars().collect())?;
+        let nested = eval(&parse_prog(tokens.as_slice())?.0)?;
+        assert_eq!(nested.heap_size(), 5 * size);
+
+        Ok(())
+    }
+
+    #[test]
+    fn as_string_test() -> InterpreTestResult {
+        let tokens = tokenize("(\"hello\")".chars().collect())?;
+        let node = parse_prog(tokens.as_slice())?;
+        assert_eq!(eval(&node.0)?.as_string()?, "hello");
+
+        let chars = Value::new(
+            Type::List(Box::new(Type::Char)).into(),
+            ValueData::List(vec![Value::from(b'h'), Value::from(b'i')]),
+        );
+        assert_eq!(chars.as_string()?, "hi");
+
+        assert_eq!(
+            Value::from(vec![1u64, 2])
+                .as_string()
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Tried to convert invalid value to string: {:?}",
+                Value::from(vec![1u64, 2])
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn register_fn_test() -> InterpreTestResult {
+        let mut state = State::new();
+        state.register_fn("double", |args| {
+            let n = args
+                .first()
+                .ok_or("double expects an argument")?
+                .try_get_val()?
+                .try_as_int()?;
+
+            Ok(Value::from(n * 2))
+        })?;
+
+        let tokens = tokenize("(double 21)".chars().collect())?;
+        let node = parse_prog(tokens.as_slice())?;
+        assert_eq!(eval_with_state(&node.0, &mut state)?, Value::from(42i64));
+
+        // Arguments are evaluated first
+        let tokens = tokenize("(double (double (+ 1 1)))".chars().collect())?;
+        let node = parse_prog(tokens.as_slice())?;
+        assert_eq!(eval_with_state(&node.0, &mut state)?, Value::from(8i64));
+
+        // A lone identifier is a variable, so registered functions can't be called without
+        // arguments
+        let tokens = tokenize("(double)".chars().collect())?;
+        let node = parse_prog(tokens.as_slice())?;
+        assert_eq!(
+            eval_with_state(&node.0, &mut state)
+                .unwrap_err()
+                .to_string(),
+            "Variable has not been initialized at all: double"
+        );
+
+        let tokens = tokenize("(triple 1)".chars().collect())?;
+        let node = parse_prog(tokens.as_slice())?;
+        assert_eq!(
+            eval_with_state(&node.0, &mut state)
+                .unwrap_err()
+                .to_string(),
+            "'triple' is not a function"
+        );
+
+        assert!(state.register_fn("add", |_| Ok(().into())).is_err());
+        assert!(state.register_fn("list<int>", |_| Ok(().into())).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn call_ident_test() -> InterpreTestResult {
+        let run = |input| eval_with_setup(input, |_| ()).map(|(val, _)| val);
+
+        assert_eq!(
+            run("(foo 1 2)").unwrap_err().to_string(),
+            "'foo' is not a function"
+        );
+        assert_eq!(
+            run("(fold (fn acc x (x acc)) 0 [1])")
+                .unwrap_err()
+                .to_string(),
+            "'x' is not a function, it's a variable"
+        );
+
+        // A variable holding a closure can be called like any other function
+        assert_eq!(
+            run("(fold (fn acc f (f acc)) 1 [(fn n (add n 1)) (fn n (add n n))])")?,
+            Value::from(4i64)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn let_test() -> InterpreTestResult {
+        let run = |input| eval_with_setup(input, |_| ()).map(|(val, _)| val);
+
+        assert_eq!(run("(let x 5 (mul x x))")?, Value::from(25i64));
+        assert_eq!(
+            run("(let x 2 (let y (add x 1) (mul x y)))")?,
+            Value::from(6i64)
+        );
+        assert_eq!(run("(let f (fn n (add n 1)) (f 2))")?, Value::from(3i64));
+
+        // The binding shadows an outer variable of the same name, and is gone after the body
+        assert_eq!(run("(let x 1 (add (let x 10 x) x))")?, Value::from(11i64));
+        assert_eq!(
+            run("(do (let x 5 (mul x x)) x)").unwrap_err().to_string(),
+            "Variable has not been initialized at all: x"
+        );
+
+        assert!(run("(let 1 5 x)")
+            .unwrap_err()
+            .to_string()
+            .starts_with("Expected a variable name"));
+        assert_eq!(
+            run("(let x 5)").unwrap_err().to_string(),
+            "let expects 3 arguments of types [Ident, Value, Value], got 2"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn try_test() -> InterpreTestResult {
+        let run = |input| eval_with_setup(input, |_| ());
+
+        assert_eq!(run("(try (div 1 0) -1)")?.0, Value::from(-1i64));
+        assert_eq!(run("(try (+ 1 2) 99)")?.0, Value::from(3i64));
+
+        // The fallback is only evaluated when it's needed
+        assert_eq!(run("(try 1 (write \"x\"))")?.1, b"");
+        assert_eq!(run("(try (assert (== 1 2)) (write \"x\"))")?.1, b"x");
+
+        // Variables bound inside the failed expression don't leak out
+        assert!(run("(try (let x 1 (div x 0)) x)").is_err());
+
+        // Errors in the fallback aren't caught
+        assert_eq!(
+            run("(try (div 1 0) (div 2 0))").unwrap_err().to_string(),
+            "Division by zero evaluating 2 / 0"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn uchar_test() -> InterpreTestResult {
+        let run = |input| eval_with_setup(input, |_| ()).map(|(val, _)| val);
+
+        assert_eq!(run("('é')")?, Value::from('é'));
+        assert_eq!(run("'😀'")?.try_as_uchar()?, '😀');
+        assert_eq!(
+            run("['é' 'ß']")?,
+            Value::new(
+                Type::List(Box::new(Type::UChar)).into(),
+                ValueData::List(vec!['é'.into(), 'ß'.into()])
+            )
+        );
+
+        assert_eq!(run("(== 'é' 'é')")?, Value::from(true));
+        assert_eq!(run("(< 'é' '😀')")?, Value::from(true));
+        assert!(run("(== 'é' 'e')").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn times_test() -> InterpreTestResult {
+        let run = |input| eval_with_setup(input, |_| ());
+
+        assert_eq!(
+            run("(times 3 (write \"x\"))")?,
+            (().into(), b"xxx".to_vec())
+        );
+        assert_eq!(run("(times 0 (write \"x\"))")?, (().into(), vec![]));
+        assert_eq!(run("(times 2u (write \"ab\"))")?.1, b"abab");
+
+        assert!(run("(times -1 (write \"x\"))").is_err());
+        assert_eq!(
+            run("(times 1)").unwrap_err().to_string(),
+            "times expects 2 arguments of types [Value, Value], got 1"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_twice_test() -> InterpreTestResult {
+        let tokens = tokenize(
+            "(do (times 2 (write \"x\")) (fold (fn acc x (add acc x)) 0 [(add 1 2) 4]))"
+                .chars()
+                .collect(),
+        )?;
+        let node = parse_prog(&tokens)?.0;
+
+        let run = || -> crate::error::InterpreteResult<(Value, Vec<u8>)> {
+            let mut out = Vec::new();
+            let val = eval_with_state(&node, &mut State::with_writer(&mut out))?;
+
+            Ok((val, out))
+        };
+
+        // The tree is left untouched, so the second run has the same output and result
+        let first = run()?;
+        assert_eq!(first, (Value::from(7i64), b"xx".to_vec()));
+        assert_eq!(run()?, first);
+
+        Ok(())
+    }
+
+    #[test]
+    fn trace_test() -> InterpreTestResult {
+        let mut trace = Vec::new();
+        let mut state = State::with_writer(std::io::sink());
+        state.set_trace(&mut trace);
+
+        let tokens = tokenize("(+ 1 (* 2 3))".chars().collect())?;
+        let node = parse_prog(tokens.as_slice())?;
+        eval_with_state(&node.0, &mut state)?;
+        drop(state);
+
+        let trace = String::from_utf8(trace).unwrap();
+        let lines: Vec<_> = trace.lines().collect();
+        let indent = |line: &str| line.len() - line.trim_start().len();
+
+        let add = lines
+            .iter()
+            .position(|l| l.trim() == "FuncCall add")
+            .unwrap();
+        let mul = lines
+            .iter()
+            .position(|l| l.trim() == "FuncCall mul")
+            .unwrap();
+
+        // `mul` is evaluated as part of the arguments to `add`, so it's nested under it and the
+        // result of `add` comes after it
+        assert!(add < mul);
+        assert!(indent(lines[mul]) > indent(lines[add]));
+
+        let add_end = lines[add + 1..]
+            .iter()
+            .position(|l| indent(l) == indent(lines[add]))
+            .unwrap()
+            + add
+            + 1;
+        assert!(add_end > mul);
+        assert!(lines[add_end].trim().starts_with("=> "));
+
+        assert_eq!(lines.first(), Some(&"Prog"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_from_test() -> InterpreTestResult {
+        let num = |n| Value::new(AbstractType::Number, ValueData::Number(n));
+
+        assert_eq!(
+            Value::list_from([Value::from(1i64), Value::from(2i64)])?,
+            Value::from(vec![1i64, 2])
+        );
+        assert_eq!(
+            Value::list_from(["ab", "c"].map(Value::string_from))?,
+            Value::from(vec!["ab".to_string(), "c".to_string()])
+        );
+
+        // Abstract numbers coerce to the concrete element type
+        assert_eq!(
+            Value::list_from([num(1), Value::from(2u64)])?,
+            Value::new(
+                Type::List(Box::new(Type::UInt)).into(),
+                ValueData::List(vec![num(1), Value::from(2u64)])
+            )
+        );
+        assert_eq!(
+            Value::list_from([num(1), num(2)])?.resolve(),
+            Value::from(vec![1i64, 2])
+        );
+
+        assert_eq!(
+            Value::list_from([])?,
+            Value::new(AbstractType::List, ValueData::List(vec![]))
+        );
+        assert!(Value::list_from([Value::from(1i64), Value::from(b'a')]).is_err());
+        assert_eq!(Value::string_from("hi"), Value::from("hi".to_string()));
+
+        Ok(())
+    }
 }
@@ -1,3 +1,8 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Display, Formatter},
+};
+
 use crate::error::{InterpretError, InterpreteResult};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -22,6 +27,17 @@ impl From<char> for LiteralSuffix {
     }
 }
 
+impl Display for LiteralSuffix {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => Ok(()),
+            Self::Unsigned => write!(f, "u"),
+            Self::Float => write!(f, "f"),
+            Self::Char => write!(f, "c"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct NumLiteral {
     pub(crate) negative: bool,
@@ -97,18 +113,23 @@ impl NumLiteral {
         }
     }
 
-    pub fn negative(&self) -> bool {
+    /// Whether the literal had a leading `-`
+    pub fn is_negative(&self) -> bool {
         self.negative
     }
 
+    /// The digits before the decimal point, without the sign
     pub fn int_part(&self) -> u64 {
         self.int_part
     }
 
-    pub fn float(&self) -> bool {
+    /// Whether the literal had a decimal point. Note `1f` is still a float value but isn't
+    /// written as one
+    pub fn is_float(&self) -> bool {
         self.float
     }
 
+    /// The digits after the decimal point, 0 if there were none
     pub fn dec_part(&self) -> u64 {
         self.dec_part
     }
@@ -118,82 +139,179 @@ impl NumLiteral {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum ReservedIdent {
+impl Display for NumLiteral {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.negative {
+            write!(f, "-")?;
+        }
+
+        write!(f, "{}", self.int_part)?;
+
+        if self.float {
+            write!(f, ".{}", self.dec_part)?;
+        }
+
+        write!(f, "{}", self.suffix)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+/// This holds the type of an argument. When executing a function we first check for the
+/// accepted arguments for the function via crate::blisp::functions::get_arg_types
+pub enum ArgumentType {
+    /// Specifies a value type (may include variables as well).
+    Value,
+    Type,
+    // This indicates an ident is required, as in `(set <ident> 3)`
+    Ident,
+}
+
+/// Generates `ReservedIdent` along with its keyword and argument type mappings, so the three
+/// can't get out of sync. Each entry is `Variant => "keyword", [arg types]`, optionally followed by
+/// `, variadic` to mark the last arg type as repeatable
+macro_rules! reserved_idents {
+    (@variadic) => { false };
+    (@variadic variadic) => { true };
+    ($($variant:ident => $keyword:literal, [$($arg:ident),*] $(, $flag:ident)?;)+) => {
+        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        pub enum ReservedIdent {
+            $($variant,)+
+        }
+
+        impl ReservedIdent {
+            /// Every reserved identifier, in declaration order
+            pub const ALL: &'static [ReservedIdent] = &[$(Self::$variant,)+];
+
+            /// The keyword used for this identifier in source code
+            pub fn keyword(self) -> &'static str {
+                match self {
+                    $(Self::$variant => $keyword,)+
+                }
+            }
+
+            /// The types of the arguments this function accepts, checked before evaluating a
+            /// function call
+            pub fn arg_types(self) -> Vec<ArgumentType> {
+                match self {
+                    $(Self::$variant => vec![$(ArgumentType::$arg),*],)+
+                }
+            }
+
+            /// Whether the last argument type can be repeated, so the function accepts any number
+            /// of arguments of that type (at least one)
+            pub fn is_variadic(self) -> bool {
+                match self {
+                    $(Self::$variant => reserved_idents!(@variadic $($flag)?),)+
+                }
+            }
+        }
+
+        impl TryFrom<&str> for ReservedIdent {
+            type Error = InterpretError;
+
+            fn try_from(value: &str) -> Result<Self, Self::Error> {
+                match value {
+                    $($keyword => Ok(Self::$variant),)+
+                    _ => Err("Not a valid reserved identifier".into()),
+                }
+            }
+        }
+    };
+}
+
+reserved_idents! {
     // Math
-    Add,
-    Sub,
-    Div,
-    Mul,
+    Add => "add", [Value, Value];
+    Sub => "sub", [Value, Value];
+    Div => "div", [Value, Value];
+    Mul => "mul", [Value, Value];
+    Min => "min", [Value, Value];
+    Max => "max", [Value, Value];
+    Abs => "abs", [Value];
+
+    // Conversions
+    ToInt => "toint", [Value];
+    ToUInt => "touint", [Value];
+    ToFloat => "tofloat", [Value];
+    ToFloat32 => "tofloat32", [Value];
+    Ord => "ord", [Value];
+    Chr => "chr", [Value];
 
     // I/O
-    Write,
-    Read,
+    Write => "write", [Value];
+    Read => "read", [Value];
+    ReadLine => "readline", [];
 
     // Control flow
-    If,
-    While,
+    If => "if", [Value, Value, Value];
+    While => "while", [Value, Value];
+    Times => "times", [Value, Value];
+    // Handled when the call is evaluated, since the fallback is only evaluated if the first
+    // argument fails
+    Try => "try", [Value, Value];
+    Do => "do", [Value], variadic;
 
     // Boolean ops
-    Eq,
-    Neq,
-    Leq,
-    Geq,
-    Lt,
-    Gt,
-    And,
-    Or,
+    Eq => "eq", [Value, Value];
+    Neq => "neq", [Value, Value];
+    Leq => "leq", [Value, Value];
+    Geq => "geq", [Value, Value];
+    Lt => "lt", [Value, Value];
+    Gt => "gt", [Value, Value];
+    And => "and", [Value, Value];
+    Or => "or", [Value, Value];
+
+    // Bitwise ops
+    BAnd => "band", [Value, Value];
+    BOr => "bor", [Value, Value];
+    BXor => "bxor", [Value, Value];
+    Shl => "shl", [Value, Value];
+    Shr => "shr", [Value, Value];
 
     // Vars
-    Set,
-    Init,
-    Def,
+    Set => "set", [Ident, Value];
+    Init => "init", [Ident, Type];
+    Def => "def", [Ident, Value];
+    // Handled when the call is evaluated, since the body is only evaluated after the binding
+    Let => "let", [Ident, Value, Value];
 
     // Collections
-    Concat,
-    Prepend,
-    Take,
+    Concat => "concat", [Value, Value];
+    Prepend => "prepend", [Value, Value];
+    Take => "take", [Value, Value];
+    Contains => "contains", [Value, Value];
+    IsEmpty => "isempty", [Value];
+    Head => "head", [Value];
+    Tail => "tail", [Value];
+    Range => "range", [Value, Value];
+    Zip => "zip", [Value, Value];
+    Sort => "sort", [Value];
+    Join => "join", [Value, Value];
+    Flatten => "flatten", [Value];
     // Removed until/unless tuple type is reintroduced
-    //Split,
+    //Split => "split", [Value, Value];
+
+    // Functions, `fn` takes one or more parameter names before its body and is handled when the
+    // call is evaluated
+    Fn => "fn", [Ident, Value];
+    Fold => "fold", [Value, Value, Value];
+    Filter => "filter", [Value, Value];
+    Partition => "partition", [Value, Value];
 
     // Convenience
-    Eval,
-    ToString,
+    Assert => "assert", [Value];
+    Eval => "eval", [Value];
+    ToString => "tostring", [Value];
+    ToStringRadix => "tostringradix", [Value, Value];
+    ToStringPrec => "tostringprec", [Value, Value];
 }
 
-impl TryFrom<&str> for ReservedIdent {
-    type Error = InterpretError;
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "add" => Ok(Self::Add),
-            "sub" => Ok(Self::Sub),
-            "div" => Ok(Self::Div),
-            "mul" => Ok(Self::Mul),
-            "write" => Ok(Self::Write),
-            "read" => Ok(Self::Read),
-            "if" => Ok(Self::If),
-            "while" => Ok(Self::While),
-            "eq" => Ok(Self::Eq),
-            "neq" => Ok(Self::Neq),
-            "leq" => Ok(Self::Leq),
-            "geq" => Ok(Self::Geq),
-            "lt" => Ok(Self::Lt),
-            "gt" => Ok(Self::Gt),
-            "and" => Ok(Self::And),
-            "or" => Ok(Self::Or),
-            "set" => Ok(Self::Set),
-            "init" => Ok(Self::Init),
-            "def" => Ok(Self::Def),
-            "concat" => Ok(Self::Concat),
-            "prepend" => Ok(Self::Prepend),
-            "take" => Ok(Self::Take),
-            "eval" => Ok(Self::Eval),
-            "tostring" => Ok(Self::ToString),
-            _ => Err("Not a valid reserved identifier".into()),
-        }
+impl Display for ReservedIdent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.keyword())
     }
 }
+
 impl TryFrom<String> for ReservedIdent {
     type Error = InterpretError;
 
@@ -207,9 +325,20 @@ pub enum Type {
     Int,
     UInt,
     Float,
+    /// 32-bit float, arithmetic on these is done in single precision. Never coerced to or from
+    /// `float`
+    Float32,
     List(Box<Type>),
+    /// Currently only produced by values converted from Rust, there's no literal syntax yet
+    Tuple(Vec<Type>),
+    /// A function created with `fn`. There's no syntax for this type, and the parameter and
+    /// return types aren't tracked
+    Func,
     Unit,
     Char,
+    /// A Unicode scalar value, written as a char literal holding a single non-ascii char like
+    /// `'é'`. Kept separate from `char` (a byte), and strings are still lists of `char`
+    UChar,
     Bool,
     //String, // Probably want to leave out until a need arises, not sure if useful
 }
@@ -226,8 +355,10 @@ impl TryFrom<&str> for Type {
             "int" => Ok(Self::Int),
             "uint" => Ok(Self::UInt),
             "float" => Ok(Self::Float),
+            "float32" => Ok(Self::Float32),
             "unit" => Ok(Self::Unit),
             "char" => Ok(Self::Char),
+            "uchar" => Ok(Self::UChar),
             "bool" => Ok(Self::Bool),
             _ => {
                 if value.len() >= 5
@@ -243,7 +374,26 @@ impl TryFrom<&str> for Type {
                     && &value[0..6] == "tuple<"
                     && value.as_bytes()[value.len() - 1] == b'>'
                 {
-                    unimplemented!()
+                    // Split on the commas that aren't nested inside another type
+                    let inner = &value[6..value.len() - 1];
+                    let mut depth = 0;
+                    let mut start = 0;
+                    let mut subtypes = Vec::new();
+
+                    for (i, c) in inner.char_indices() {
+                        match c {
+                            '<' => depth += 1,
+                            '>' => depth -= 1,
+                            ',' if depth == 0 => {
+                                subtypes.push(Self::try_from(inner[start..i].trim())?);
+                                start = i + 1;
+                            }
+                            _ => (),
+                        }
+                    }
+                    subtypes.push(Self::try_from(inner[start..].trim())?);
+
+                    Ok(Self::Tuple(subtypes))
                 } else {
                     Err("Invalid type: {value}".into())
                 }
@@ -251,6 +401,32 @@ impl TryFrom<&str> for Type {
         }
     }
 }
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int => write!(f, "int"),
+            Self::UInt => write!(f, "uint"),
+            Self::Float => write!(f, "float"),
+            Self::Float32 => write!(f, "float32"),
+            Self::List(ty) => write!(f, "list<{}>", ty),
+            Self::Tuple(tys) => write!(
+                f,
+                "tuple<{}>",
+                tys.iter()
+                    .map(Type::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::Func => write!(f, "fn"),
+            Self::Unit => write!(f, "unit"),
+            Self::Char => write!(f, "char"),
+            Self::UChar => write!(f, "uchar"),
+            Self::Bool => write!(f, "bool"),
+        }
+    }
+}
+
 impl TryFrom<String> for Type {
     type Error = InterpretError;
 
@@ -263,6 +439,7 @@ impl TryFrom<String> for Type {
 pub enum Token {
     NumLiteral(NumLiteral),
     CharLiteral(u8),
+    UCharLiteral(char),
     UnitLiteral,
     StringLiteral(String),
     Ident(String),
@@ -342,22 +519,80 @@ impl From<u8> for Token {
     }
 }
 
-fn handle_char_literal(input: &[char]) -> InterpreteResult<u8> {
+fn handle_char_literal(input: &[char]) -> InterpreteResult<(Token, usize)> {
     // input[0] points at opening `'`
-    // we don't handle escape characters so we can assume that the body of the char literal will
-    // take up exactly one byte of input.
+    if input.get(1) == Some(&'\\') && input.get(2) == Some(&'x') {
+        let (byte, count) = handle_byte_escape(input)?;
+        return Ok((Token::CharLiteral(byte), count));
+    }
+
+    // Other than byte escapes we don't handle escape characters so we can assume that the body
+    // of the char literal will take up exactly one char of input.
     if *input
         .get(2)
         .ok_or("Reached end of input unexpectedly while parsing a char literal")?
         != '\''
     {
         Err("Did not find closing \' where expected while processing a char literal".into())
+    } else if input[1].is_ascii() {
+        Ok((Token::CharLiteral(input[1] as u8), 3))
+    } else {
+        Ok((Token::UCharLiteral(input[1]), 3))
+    }
+}
+
+/// Marks the chars the lexer looks at. Non-ascii chars are skipped, except as the body of a char
+/// literal like `'é'`. Strings and comments are stepped over, so a `'é'` inside one is skipped too
+fn lexed_mask(input: &[char]) -> Vec<bool> {
+    let mut mask: Vec<bool> = input.iter().map(char::is_ascii).collect();
+    let mut i = 0;
+
+    while i < input.len() {
+        i += match input[i] {
+            '\'' if input.get(i + 1) == Some(&'\\') && input.get(i + 2) == Some(&'x') => 6,
+            '\'' if input.get(i + 2) == Some(&'\'') => {
+                mask[i + 1] = true;
+                3
+            }
+            '\"' => input[i + 1..]
+                .iter()
+                .position(|&c| c == '\"')
+                .map_or(input.len() - i, |len| len + 2),
+            ';' => input[i..]
+                .iter()
+                .position(|&c| c == '\n')
+                .unwrap_or(input.len() - i),
+            '#' => handle_block_comment(&input[i..]).unwrap_or(input.len() - i),
+            _ => 1,
+        };
+    }
+
+    mask
+}
+
+/// Handles a char literal like `'\x41'`, giving the byte as exactly two hex digits
+fn handle_byte_escape(input: &[char]) -> InterpreteResult<(u8, usize)> {
+    let mut byte = 0;
+
+    for i in 3..5 {
+        let c = *input
+            .get(i)
+            .ok_or("Reached end of input unexpectedly while parsing a byte escape")?;
+        let digit = c
+            .to_digit(16)
+            .ok_or(format!("Invalid hex digit in byte escape: {}", c))?;
+
+        byte = byte * 16 + digit as u8;
+    }
+
+    if input.get(5) != Some(&'\'') {
+        Err("Byte escapes need exactly two hex digits followed by a closing \'".into())
     } else {
-        Ok(input[1] as u8)
+        Ok((byte, 6))
     }
 }
 
-fn handle_string_literal(input: &[char]) -> InterpreteResult<String> {
+fn handle_string_literal(input: &[char]) -> InterpreteResult<(String, usize)> {
     // Starting on character directly after opening "
     let mut curr_index = 1;
     let mut curr_str = String::new();
@@ -375,7 +610,8 @@ fn handle_string_literal(input: &[char]) -> InterpreteResult<String> {
         curr_index += 1;
     }
 
-    Ok(curr_str)
+    // Including both `"`
+    Ok((curr_str, curr_index + 1))
 }
 
 // There are three cases for any identifier:
@@ -383,46 +619,122 @@ fn handle_string_literal(input: &[char]) -> InterpreteResult<String> {
 // 2. Type name such as `int` or `list<tuple<int, char>>`, these are parsed to `Token::Type(..)`
 // 3. User-defined name for variables, these are parsed to `Token::Ident`
 //
-// First I parse the identifier, including alphanumeric characters and `<>` (only valid in types)
+// First I parse the identifier, including alphanumeric characters and `<>` (only valid in types).
+// Between `<` and `>` commas and whitespace are allowed too, to separate the types in a tuple
 fn handle_identifier(input: &[char]) -> InterpreteResult<(Token, usize)> {
     let mut curr_index = 0;
     let mut curr_ident = String::new();
 
     // Any identifier with <> must be a type, this allows me to ensure that I treat it as such
     let mut forced_type = false;
+    // How many `<` are still open
+    let mut depth = 0usize;
 
     loop {
+        // A bare identifier can make up the whole program, so the end of input is a valid end
         if curr_index >= input.len() {
-            return Err("Unexpectedly reached end of input while parsing an identifier".into());
+            break;
         }
 
         match input[curr_index] {
-            'a'..='z' | 'A'..='Z' | '0'..='9' => {
+            // `tokenize` only calls this on a letter so underscores can't start an identifier
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {
                 curr_ident.push(input[curr_index]);
             }
-            '<' | '>' => {
+            '<' => {
                 forced_type = true;
-                curr_ident.push(input[curr_index]);
+                depth += 1;
+                curr_ident.push('<');
             }
+            '>' => {
+                forced_type = true;
+                depth = depth.saturating_sub(1);
+                curr_ident.push('>');
+            }
+            c if depth > 0 && (c == ',' || c.is_ascii_whitespace()) => curr_ident.push(c),
             _ => break,
         }
 
         curr_index += 1;
     }
 
-    let adj = curr_ident.len() - 1;
+    if curr_index == 0 {
+        return Err(format!("Expected an identifier, found {:?}", input.first()).into());
+    }
+
+    // Number of characters consumed
+    let count = curr_index;
 
     if forced_type {
-        Ok((Token::from(Type::try_from(curr_ident.as_str())?), adj))
+        if curr_ident.contains('_') {
+            return Err(format!("Types can't contain underscores: {}", curr_ident).into());
+        }
+
+        Ok((Token::from(Type::try_from(curr_ident.as_str())?), count))
     } else if let Ok(ty) = Type::try_from(curr_ident.as_str()) {
-        Ok((Token::from(ty), adj))
+        Ok((Token::from(ty), count))
     } else if let Ok(rsv) = ReservedIdent::try_from(curr_ident.as_str()) {
-        Ok((Token::from(rsv), adj))
+        Ok((Token::from(rsv), count))
+    } else if curr_ident == "nil" {
+        // `unit` is already the name of the type, so the value gets its own keyword
+        Ok((Token::UnitLiteral, count))
     } else {
-        Ok((Token::Ident(curr_ident), adj))
+        Ok((Token::Ident(curr_ident), count))
     }
 }
 
+/// Whether `name` lexes as something other than an identifier, i.e. it's a type, a reserved
+/// function, or `nil`. Such names need an `@` to be used as identifiers, see
+/// `handle_escaped_identifier`
+pub(crate) fn is_keyword(name: &str) -> bool {
+    Type::try_from(name).is_ok() || ReservedIdent::try_from(name).is_ok() || name == "nil"
+}
+
+// Block comments are written `#| ... |#` and can be nested. Returns the number of characters
+// consumed, including the delimiters
+fn handle_block_comment(input: &[char]) -> InterpreteResult<usize> {
+    if input.get(1) != Some(&'|') {
+        return Err("Expected `|` after `#` to start a block comment".into());
+    }
+
+    let mut depth = 1;
+    let mut curr_index = 2;
+
+    while depth > 0 {
+        match (input.get(curr_index), input.get(curr_index + 1)) {
+            (Some('#'), Some('|')) => {
+                depth += 1;
+                curr_index += 2;
+            }
+            (Some('|'), Some('#')) => {
+                depth -= 1;
+                curr_index += 2;
+            }
+            (Some(_), _) => curr_index += 1,
+            (None, _) => return Err("Unterminated block comment, expected `|#`".into()),
+        }
+    }
+
+    Ok(curr_index)
+}
+
+// `@` forces the name after it to be parsed as `Token::Ident`, even if it's a reserved function or
+// type name, e.g. `@read`. Returns the number of characters consumed, including the `@`
+fn handle_escaped_identifier(input: &[char]) -> InterpreteResult<(Token, usize)> {
+    let name: String = input[1..]
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+        .collect();
+
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err("Expected an identifier starting with a letter after `@`".into());
+    }
+
+    let count = name.len() + 1;
+
+    Ok((Token::Ident(name), count))
+}
+
 fn handle_num_literal(input: &[char]) -> InterpreteResult<(NumLiteral, usize)> {
     let mut curr_index = 0;
 
@@ -449,13 +761,25 @@ fn handle_num_literal(input: &[char]) -> InterpreteResult<(NumLiteral, usize)> {
         }
         match input[curr_index] {
             '0'..='9' => {
-                if float {
-                    dec_part *= 10;
-                    dec_part += input[curr_index].to_digit(10).unwrap() as u64;
-                } else {
-                    int_part *= 10;
-                    int_part += input[curr_index].to_digit(10).unwrap() as u64;
-                }
+                let digit = input[curr_index].to_digit(10).unwrap() as u64;
+                let part = if float { &mut dec_part } else { &mut int_part };
+
+                *part = part
+                    .checked_mul(10)
+                    .and_then(|n| n.checked_add(digit))
+                    .ok_or_else(|| {
+                        let len = input
+                            .iter()
+                            .position(|c| !(c.is_ascii_digit() || *c == '-' || *c == '.'))
+                            .unwrap_or(input.len());
+                        let literal: String = input[..len].iter().collect();
+
+                        if float {
+                            format!("Too many digits after the decimal point: {}", literal)
+                        } else {
+                            format!("Integer literal too large: {}", literal)
+                        }
+                    })?;
 
                 curr_index += 1;
             }
@@ -482,6 +806,14 @@ fn handle_num_literal(input: &[char]) -> InterpreteResult<(NumLiteral, usize)> {
         }
     }
 
+    // A negative integer has to fit in an `int`, whose smallest value has a magnitude of 2^63.
+    // Float literals don't have that limit
+    if negative && !float && suffix == LiteralSuffix::None && int_part > i64::MIN.unsigned_abs() {
+        let literal: String = input[..curr_index].iter().collect();
+
+        return Err(format!("Integer literal too small: {}", literal).into());
+    }
+
     Ok((
         NumLiteral {
             int_part,
@@ -495,95 +827,280 @@ fn handle_num_literal(input: &[char]) -> InterpreteResult<(NumLiteral, usize)> {
 }
 
 pub fn tokenize(input: Vec<char>) -> InterpreteResult<Vec<Token>> {
-    // This way I don't need to worry about testing for ascii in every method
-    let input: Vec<char> = input.into_iter().filter(|c| c.is_ascii()).collect();
+    let mut tokens = Vec::new();
+    tokenize_into(&input, &mut tokens)?;
+
+    Ok(tokens)
+}
 
+/// Like `tokenize`, but clears `buf` and writes the tokens into it instead of allocating a new
+/// `Vec`, so one buffer can be reused when lexing many programs. On an error `buf` holds the
+/// tokens found before it
+pub fn tokenize_into(input: &[char], buf: &mut Vec<Token>) -> InterpreteResult<()> {
+    // This way I don't need to worry about testing for ascii in every method. Input is usually
+    // all ascii already, in which case it's used as-is
+    let input: Cow<[char]> = if input.iter().all(char::is_ascii) {
+        Cow::Borrowed(input)
+    } else {
+        Cow::Owned(
+            input
+                .iter()
+                .zip(lexed_mask(input))
+                .filter_map(|(&c, lexed)| lexed.then_some(c))
+                .collect(),
+        )
+    };
+
+    buf.clear();
+    tokenize_spanned(&input, |tok, _, _| buf.push(tok))?;
+    buf.push(Token::EOF);
+
+    Ok(())
+}
+
+/// Tokenizes ascii input, calling `push` with each token along with the range of input it came
+/// from (start inclusive, end exclusive). On an error the tokens found up to that point have
+/// already been pushed. No `EOF` token is added
+fn tokenize_spanned(
+    input: &[char],
+    mut push: impl FnMut(Token, usize, usize),
+) -> InterpreteResult<()> {
     let mut curr_index = 0;
-    let mut res = Vec::new();
 
     loop {
         if curr_index >= input.len() {
             break;
         }
 
-        match input[curr_index] {
-            '+' => res.push(ReservedIdent::Add.into()),
-            '/' => res.push(ReservedIdent::Div.into()),
-            '*' => res.push(ReservedIdent::Mul.into()),
-            '(' => {
-                // Important to note that this means `( )` is not a valid unit literal
-                if *input
-                    .get(curr_index + 1)
-                    .ok_or("Unexpectedly reached end of input")?
-                    == ')'
-                {
-                    res.push(Token::UnitLiteral);
-                    curr_index += 1;
-                } else {
-                    res.push(Token::LParen);
-                }
+        let start = curr_index;
+
+        let tok = match input[curr_index] {
+            '+' => Some(ReservedIdent::Add.into()),
+            '/' => Some(ReservedIdent::Div.into()),
+            '*' => Some(ReservedIdent::Mul.into()),
+            '<' | '>' | '=' | '!' => {
+                // Types like `list<int>` start with a letter, so a `<` or `>` here is always a
+                // comparison
+                let next = input.get(curr_index + 1).copied();
+                let (rsv, count) = match (input[curr_index], next) {
+                    ('<', Some('=')) => (ReservedIdent::Leq, 2),
+                    ('>', Some('=')) => (ReservedIdent::Geq, 2),
+                    ('<', Some('>')) | ('!', Some('=')) => (ReservedIdent::Neq, 2),
+                    ('=', Some('=')) => (ReservedIdent::Eq, 2),
+                    ('<', _) => (ReservedIdent::Lt, 1),
+                    ('>', _) => (ReservedIdent::Gt, 1),
+                    ('=', _) => (ReservedIdent::Eq, 1),
+                    _ => return Err("Expected `=` after `!`".into()),
+                };
+                curr_index += count - 1;
+                Some(rsv.into())
             }
-            ')' => res.push(Token::RParen),
-            '[' => res.push(Token::LBrack),
-            ']' => res.push(Token::RBrack),
+            '(' => Some(Token::LParen),
+            ')' => Some(Token::RParen),
+            '[' => Some(Token::LBrack),
+            ']' => Some(Token::RBrack),
             '0'..='9' => {
                 let (lit, count) = handle_num_literal(&input[curr_index..])?;
                 curr_index += count - 1;
-                res.push(Token::NumLiteral(lit))
+                Some(Token::NumLiteral(lit))
             }
             '\'' => {
-                let c = handle_char_literal(&input[curr_index..])?;
-                // Since a char literal takes up 3 characters
-                curr_index += 2;
-                res.push(Token::CharLiteral(c));
+                let (tok, count) = handle_char_literal(&input[curr_index..])?;
+                curr_index += count - 1;
+                Some(tok)
             }
             '\"' => {
-                let s = handle_string_literal(&input[curr_index..])?;
-                // Need to ultimately shift by s.len() + 2, including standard shift by 1
-                curr_index += s.len() + 1;
-                res.push(Token::StringLiteral(s));
+                let (s, count) = handle_string_literal(&input[curr_index..])?;
+                curr_index += count - 1;
+                Some(Token::StringLiteral(s))
             }
             '-' => {
-                if *input
+                // Only a `-` directly followed by a digit starts a negative number, anything else
+                // (e.g. `(- 5)`, `(-x)`, or `(-(+ 1 2) 3)`) is subtraction
+                if input
                     .get(curr_index + 1)
-                    .ok_or("Unexpectedly reached end of input")?
-                    == ' '
+                    .is_some_and(|c| c.is_ascii_digit())
                 {
-                    res.push(ReservedIdent::Sub.into());
-                } else {
                     let (lit, count) = handle_num_literal(&input[curr_index..])?;
                     curr_index += count - 1;
-                    res.push(Token::NumLiteral(lit));
+                    Some(Token::NumLiteral(lit))
+                } else {
+                    Some(ReservedIdent::Sub.into())
                 }
             }
             'a'..='z' | 'A'..='Z' => {
-                let (tok, adj) = handle_identifier(&input[curr_index..])?;
-                res.push(tok);
-                curr_index += adj;
+                let (tok, count) = handle_identifier(&input[curr_index..])?;
+                curr_index += count - 1;
+                Some(tok)
+            }
+            c if c.is_ascii_whitespace() => None,
+            ';' => {
+                // Line comment, skip up to (not including) the newline
+                while input.get(curr_index + 1).is_some_and(|&c| c != '\n') {
+                    curr_index += 1;
+                }
+                None
+            }
+            '#' => {
+                curr_index += handle_block_comment(&input[curr_index..])? - 1;
+                None
             }
-            ' ' => (),
+            '@' => {
+                let (tok, count) = handle_escaped_identifier(&input[curr_index..])?;
+                curr_index += count - 1;
+                Some(tok)
+            }
+            '_' => return Err("Identifiers can't start with an underscore".into()),
             c => return Err(format!("Haven't implemented the char {}", c).into()),
         };
 
+        if let Some(tok) = tok {
+            push(tok, start, curr_index + 1);
+        }
+
         curr_index += 1;
     }
 
-    res.push(Token::EOF);
+    Ok(())
+}
+
+/// The kind of a token, as needed for syntax highlighting
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TokenKind {
+    /// A reserved identifier, like `add` or `+`
+    Keyword,
+    Type,
+    Identifier,
+    Number,
+    String,
+    Char,
+    /// The unit literal `()`
+    Unit,
+    /// Parentheses and brackets
+    Delimiter,
+}
+
+impl From<&Token> for TokenKind {
+    fn from(value: &Token) -> Self {
+        match value {
+            Token::NumLiteral(_) => Self::Number,
+            Token::CharLiteral(_) | Token::UCharLiteral(_) => Self::Char,
+            Token::UnitLiteral => Self::Unit,
+            Token::StringLiteral(_) => Self::String,
+            Token::Ident(_) => Self::Identifier,
+            Token::Type(_) => Self::Type,
+            Token::Reserved(_) => Self::Keyword,
+            Token::LParen | Token::RParen | Token::LBrack | Token::RBrack | Token::EOF => {
+                Self::Delimiter
+            }
+        }
+    }
+}
+
+/// The chars of `src` that the lexer looks at, along with the byte offset of each
+fn lexed_chars(src: &str) -> (Vec<usize>, Vec<char>) {
+    let (offsets, chars): (Vec<usize>, Vec<char>) = src.char_indices().unzip();
+
+    let mask = lexed_mask(&chars);
+
+    (0..chars.len())
+        .filter(|&i| mask[i])
+        .map(|i| (offsets[i], chars[i]))
+        .unzip()
+}
+
+/// Lexes `src` and returns the kind of each token along with its start (inclusive) and end
+/// (exclusive) byte offsets in `src`. Lexing stops at the first error, in which case the tokens
+/// found before it are still returned along with the error
+pub fn classify_tokens(src: &str) -> (Vec<(TokenKind, usize, usize)>, Option<InterpretError>) {
+    // The lexer skips most non-ascii chars, so keep the byte offset of each char it does see
+    let (offsets, input) = lexed_chars(src);
+
+    let mut res = Vec::new();
+    let err = tokenize_spanned(&input, |tok, start, end| res.push((tok, start, end))).err();
+
+    // Every token ends in an ascii char, which is a single byte
+    let kinds = res
+        .iter()
+        .map(|(tok, start, end)| (tok.into(), offsets[*start], offsets[end - 1] + 1))
+        .collect();
+
+    (kinds, err)
+}
+
+/// The text around a token that the lexer otherwise skips, whitespace and comments
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Trivia {
+    /// Everything between the previous token's trailing trivia and this token
+    pub leading: String,
+    /// The token exactly as it was written
+    pub text: String,
+    /// Whitespace and comments after the token on the same line, not including the newline
+    pub trailing: String,
+}
+
+/// Like `tokenize`, but keeps the whitespace and comments around each token so the input can be
+/// reconstructed exactly by concatenating the `leading`, `text`, and `trailing` of every token in
+/// order. Anything after the last token ends up in the leading trivia of the final `EOF`. Unlike
+/// `tokenize` non-ascii chars are kept, as part of the trivia
+pub fn tokenize_with_trivia(input: &str) -> InterpreteResult<Vec<(Token, Trivia)>> {
+    let (offsets, chars) = lexed_chars(input);
+
+    let mut spans = Vec::new();
+    tokenize_spanned(&chars, |tok, start, end| spans.push((tok, start, end)))?;
+
+    // Byte ranges of each token in the input, every token ends in an ascii char and so one byte
+    let ranges: Vec<_> = spans
+        .iter()
+        .map(|(_, start, end)| (offsets[*start], offsets[end - 1] + 1))
+        .collect();
+
+    let mut res = Vec::new();
+    // Start of the input that isn't part of a token or its trivia yet
+    let mut pos = 0;
+
+    for (i, ((tok, _, _), &(start, end))) in spans.into_iter().zip(&ranges).enumerate() {
+        let next_start = ranges.get(i + 1).map_or(input.len(), |&(s, _)| s);
+        let gap = &input[end..next_start];
+        let trailing_end = end + gap.find('\n').unwrap_or(gap.len());
+
+        res.push((
+            tok,
+            Trivia {
+                leading: input[pos..start].to_string(),
+                text: input[start..end].to_string(),
+                trailing: input[end..trailing_end].to_string(),
+            },
+        ));
+
+        pos = trailing_end;
+    }
+
+    res.push((
+        Token::EOF,
+        Trivia {
+            leading: input[pos..].to_string(),
+            ..Default::default()
+        },
+    ));
 
     Ok(res)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::error::InterpreTestResult;
+    use crate::{
+        blisp::macros::{assert_fails, assert_fails_lexer},
+        error::InterpreTestResult,
+    };
 
     use super::*;
 
     #[test]
     fn parentheses() -> InterpreTestResult {
         let (input1, output1) = (
-            // Without the space this is interpreted as a unit literal
-            "(( ))".chars().collect(),
+            // With or without the space these are just parentheses, the unit literal is `nil`
+            "(())".chars().collect(),
             [
                 Token::LParen,
                 Token::LParen,
@@ -607,6 +1124,21 @@ mod tests {
         assert_eq!(tokenize(input1)?, output1);
         assert_eq!(tokenize(input2)?, output2);
 
+        for input in ["()", "( )"] {
+            assert_eq!(
+                tokenize(input.chars().collect())?,
+                [Token::LParen, Token::RParen, Token::EOF]
+            );
+        }
+        assert_eq!(
+            tokenize("(nil)".chars().collect())?,
+            [Token::LParen, Token::UnitLiteral, Token::RParen, Token::EOF]
+        );
+        assert_eq!(
+            tokenize("@nil".chars().collect())?,
+            [Token::Ident("nil".to_string()), Token::EOF]
+        );
+
         Ok(())
     }
 
@@ -818,8 +1350,6 @@ mod tests {
 
     #[test]
     fn type_ident_test() -> InterpreTestResult {
-        // TODO Add and test the tuple type, parsing it will be annoying so I haven't
-        // done it yet
         let (input1, output1) = (
             "(int uint float char list<char> list<list<uint>>)"
                 .chars()
@@ -839,13 +1369,311 @@ mod tests {
 
         assert_eq!(tokenize(input1)?, output1);
 
+        // Tuple types can be written the way they're displayed, with a space after each comma
+        let tuple = Type::Tuple(vec![
+            Type::Int,
+            Type::List(Box::new(Type::Tuple(vec![Type::Char, Type::Bool]))),
+        ]);
+        for input in [
+            "(tuple<int, list<tuple<char, bool>>> 1)",
+            "(tuple<int,list<tuple<char,bool>>> 1)",
+        ] {
+            assert_eq!(
+                tokenize(input.chars().collect())?,
+                [
+                    Token::LParen,
+                    Token::Type(tuple.clone()),
+                    Token::NumLiteral(NumLiteral::new_int(1, false)),
+                    Token::RParen,
+                    Token::EOF,
+                ]
+            );
+        }
+        assert_eq!(
+            tokenize(tuple.to_string().chars().collect())?,
+            [Token::Type(tuple), Token::EOF]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn num_literal_accessors_test() {
+        let lit = NumLiteral::new_float_with_suffix(12, 5, true, 'f');
+
+        assert!(lit.is_negative());
+        assert_eq!(lit.int_part(), 12);
+        assert!(lit.is_float());
+        assert_eq!(lit.dec_part(), 5);
+        assert_eq!(lit.suffix(), LiteralSuffix::Float);
+
+        let lit = NumLiteral::new_int_with_suffix(7, false, 'u');
+
+        assert!(!lit.is_negative());
+        assert_eq!(lit.int_part(), 7);
+        assert!(!lit.is_float());
+        assert_eq!(lit.dec_part(), 0);
+        assert_eq!(lit.suffix(), LiteralSuffix::Unsigned);
+    }
+
+    #[test]
+    fn underscore_ident_test() -> InterpreTestResult {
+        assert_eq!(
+            tokenize("my_var".chars().collect())?,
+            vec![Token::Ident("my_var".to_string()), Token::EOF]
+        );
+        assert_eq!(
+            tokenize("(init my_var_2 int)".chars().collect())?,
+            vec![
+                Token::LParen,
+                ReservedIdent::Init.into(),
+                Token::Ident("my_var_2".to_string()),
+                Token::Type(Type::Int),
+                Token::RParen,
+                Token::EOF,
+            ]
+        );
+        assert_eq!(
+            tokenize("(x_)".chars().collect())?,
+            vec![
+                Token::LParen,
+                Token::Ident("x_".to_string()),
+                Token::RParen,
+                Token::EOF,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn identifier_length_test() -> InterpreTestResult {
+        let input: Vec<char> = "abc".chars().collect();
+        assert_eq!(
+            handle_identifier(&input)?,
+            (Token::Ident("abc".to_string()), 3)
+        );
+
+        let input: Vec<char> = "int)".chars().collect();
+        assert_eq!(handle_identifier(&input)?, (Token::Type(Type::Int), 3));
+
+        assert!(handle_identifier(&[]).is_err());
+        assert!(handle_identifier(&[')']).is_err());
+
+        // Identifiers right at the end of input, with no trailing delimiter
+        assert_eq!(
+            tokenize("x".chars().collect())?,
+            vec![Token::Ident("x".to_string()), Token::EOF]
+        );
+        assert_eq!(
+            tokenize("1 tostring".chars().collect())?,
+            vec![
+                Token::NumLiteral(NumLiteral::new_int(1, false)),
+                ReservedIdent::ToString.into(),
+                Token::EOF
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn escaped_ident_test() -> InterpreTestResult {
+        assert_eq!(
+            tokenize("(set @read read)".chars().collect())?,
+            vec![
+                Token::LParen,
+                ReservedIdent::Set.into(),
+                Token::Ident("read".to_string()),
+                ReservedIdent::Read.into(),
+                Token::RParen,
+                Token::EOF,
+            ]
+        );
+        assert_eq!(
+            tokenize("(@int @my_var)".chars().collect())?,
+            vec![
+                Token::LParen,
+                Token::Ident("int".to_string()),
+                Token::Ident("my_var".to_string()),
+                Token::RParen,
+                Token::EOF,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn byte_escape_test() -> InterpreTestResult {
+        assert_eq!(
+            tokenize("('\\x41' '\\xFF' '\\x0a' '\\')".chars().collect())?,
+            vec![
+                Token::LParen,
+                Token::CharLiteral(b'A'),
+                Token::CharLiteral(255),
+                Token::CharLiteral(b'\n'),
+                // Without an `x` a backslash is just a char
+                Token::CharLiteral(b'\\'),
+                Token::RParen,
+                Token::EOF,
+            ]
+        );
+
+        Ok(())
+    }
+
+    assert_fails_lexer!(
+        num_literal_too_large_test,
+        "(+ 1234567890123456789012345 1)";
+        "Integer literal too large: 1234567890123456789012345"
+    );
+
+    assert_fails_lexer!(
+        num_literal_just_too_large_test,
+        "(+ -18446744073709551616u 1)";
+        "Integer literal too large: -18446744073709551616"
+    );
+
+    assert_fails_lexer!(
+        num_literal_long_decimal_test,
+        "(1.1234567890123456789012345)";
+        "Too many digits after the decimal point: 1.1234567890123456789012345"
+    );
+
+    #[test]
+    fn num_literal_max_test() -> InterpreTestResult {
+        assert_eq!(
+            tokenize("18446744073709551615u".chars().collect())?,
+            [
+                Token::from(NumLiteral::new_int_with_suffix(u64::MAX, false, 'u')),
+                Token::EOF
+            ]
+        );
+        assert_eq!(
+            tokenize("-9223372036854775808".chars().collect())?,
+            [
+                Token::from(NumLiteral::new_int(i64::MIN.unsigned_abs(), true)),
+                Token::EOF
+            ]
+        );
+
+        // Only integers are limited to the range of an `int`
+        assert_eq!(
+            tokenize("-18446744073709551615.0".chars().collect())?,
+            [
+                Token::from(NumLiteral::new_float(u64::MAX, 0, true)),
+                Token::EOF
+            ]
+        );
+
+        Ok(())
+    }
+
+    assert_fails_lexer!(
+        negative_literal_too_small_test,
+        "(+ -9223372036854775809 1)";
+        "Integer literal too small: -9223372036854775809"
+    );
+
+    assert_fails_lexer!(
+        negative_literal_u64_max_test,
+        "-18446744073709551615";
+        "Integer literal too small: -18446744073709551615"
+    );
+
+    assert_fails_lexer!(
+        byte_escape_invalid_digit_test,
+        "('\\xG0')";
+        "Invalid hex digit in byte escape: G"
+    );
+
+    assert_fails_lexer!(
+        byte_escape_too_long_test,
+        "('\\x100')";
+        "Byte escapes need exactly two hex digits"
+    );
+
+    assert_fails_lexer!(
+        byte_escape_too_short_test,
+        "('\\x4')";
+        "Invalid hex digit in byte escape: '"
+    );
+
+    assert_fails_lexer!(
+        escaped_non_ident_test,
+        "(@1)";
+        "Expected an identifier starting with a letter after `@`"
+    );
+
+    assert_fails_lexer!(
+        leading_underscore_test,
+        "(init _x int)";
+        "Identifiers can't start with an underscore"
+    );
+    assert_fails_lexer!(
+        underscore_type_test,
+        "(list<my_type>)";
+        "Types can't contain underscores"
+    );
+
+    #[test]
+    fn reserved_ident_table_test() -> InterpreTestResult {
+        for &rsv in ReservedIdent::ALL {
+            assert_eq!(ReservedIdent::try_from(rsv.keyword())?, rsv);
+            assert_eq!(
+                tokenize(format!("({})", rsv.keyword()).chars().collect())?,
+                vec![Token::LParen, rsv.into(), Token::RParen, Token::EOF]
+            );
+            // Only `readline` takes no arguments, and a variadic function needs a type to repeat
+            assert!(!rsv.arg_types().is_empty() || rsv == ReservedIdent::ReadLine);
+        }
+
+        assert!(!ReservedIdent::ReadLine.is_variadic());
+
+        assert!(ReservedIdent::try_from("split").is_err());
+        assert!(ReservedIdent::Do.is_variadic());
+        assert!(!ReservedIdent::Add.is_variadic());
+
+        Ok(())
+    }
+
+    #[test]
+    fn display_test() -> InterpreTestResult {
+        let types = [
+            (Type::Int, "int"),
+            (Type::Unit, "unit"),
+            (Type::Float32, "float32"),
+            (Type::List(Box::new(Type::Char)), "list<char>"),
+            (
+                Type::List(Box::new(Type::List(Box::new(Type::UInt)))),
+                "list<list<uint>>",
+            ),
+            (Type::Tuple(vec![Type::Int, Type::Char]), "tuple<int, char>"),
+            (
+                Type::Tuple(vec![
+                    Type::Tuple(vec![Type::Bool, Type::Float]),
+                    Type::List(Box::new(Type::Tuple(vec![Type::UInt, Type::Char]))),
+                ]),
+                "tuple<tuple<bool, float>, list<tuple<uint, char>>>",
+            ),
+        ];
+
+        for (ty, s) in types {
+            assert_eq!(format!("{}", ty), s);
+            assert_eq!(Type::try_from(format!("{}", ty).as_str())?, ty);
+        }
+
+        assert_eq!(format!("{}", ReservedIdent::Concat), "concat");
+        assert_eq!(format!("{}", ReservedIdent::ToString), "tostring");
+
         Ok(())
     }
 
     #[test]
     fn reserved_ident_test() -> InterpreTestResult {
         let (input1, output1) = (
-            "(add + sub - div / mul * write read if while eq neq leq geq lt gt and or set init def concat prepend take eval tostring)".chars().collect(),
+            "(add + sub - div / mul * write read if while eq neq leq geq lt gt and or set init def concat prepend take contains range eval tostring)".chars().collect(),
             [
                 Token::LParen,
                 ReservedIdent::Add.into(),
@@ -874,6 +1702,8 @@ mod tests {
                 ReservedIdent::Concat.into(),
                 ReservedIdent::Prepend.into(),
                 ReservedIdent::Take.into(),
+                ReservedIdent::Contains.into(),
+                ReservedIdent::Range.into(),
                 ReservedIdent::Eval.into(),
                 ReservedIdent::ToString.into(),
                 Token::RParen,
@@ -932,7 +1762,7 @@ mod tests {
     #[test]
     fn list_test() -> InterpreTestResult {
         let (input1, output1) = (
-            "([-14.6 [12.4 'c' \"ABCD\"] ()])".chars().collect(),
+            "([-14.6 [12.4 'c' \"ABCD\"] nil])".chars().collect(),
             [
                 Token::LParen,
                 Token::LBrack,
@@ -954,4 +1784,305 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn minus_test() -> InterpreTestResult {
+        let inputs = [
+            (
+                "(-x)",
+                vec![ReservedIdent::Sub.into(), Token::Ident("x".to_string())],
+            ),
+            (
+                "(- 5)",
+                vec![
+                    ReservedIdent::Sub.into(),
+                    NumLiteral::new_int(5, false).into(),
+                ],
+            ),
+            ("(-5)", vec![NumLiteral::new_int(5, true).into()]),
+            (
+                "(-(+ 1 2) 3)",
+                vec![
+                    ReservedIdent::Sub.into(),
+                    Token::LParen,
+                    ReservedIdent::Add.into(),
+                    NumLiteral::new_int(1, false).into(),
+                    NumLiteral::new_int(2, false).into(),
+                    Token::RParen,
+                    NumLiteral::new_int(3, false).into(),
+                ],
+            ),
+            ("(-)", vec![ReservedIdent::Sub.into()]),
+        ];
+
+        for (input, inner) in inputs {
+            let mut exp = vec![Token::LParen];
+            exp.extend(inner);
+            exp.extend([Token::RParen, Token::EOF]);
+
+            assert_eq!(tokenize(input.chars().collect())?, exp);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn comparison_alias_test() -> InterpreTestResult {
+        let inputs = [
+            ("(< 1 2)", ReservedIdent::Lt),
+            ("(> 1 2)", ReservedIdent::Gt),
+            ("(<= 1 2)", ReservedIdent::Leq),
+            ("(>= 1 2)", ReservedIdent::Geq),
+            ("(= 1 2)", ReservedIdent::Eq),
+            ("(== 1 2)", ReservedIdent::Eq),
+            ("(!= 1 2)", ReservedIdent::Neq),
+            ("(<> 1 2)", ReservedIdent::Neq),
+        ];
+
+        for (input, rsv) in inputs {
+            assert_eq!(
+                tokenize(input.chars().collect())?,
+                vec![
+                    Token::LParen,
+                    rsv.into(),
+                    NumLiteral::new_int(1, false).into(),
+                    NumLiteral::new_int(2, false).into(),
+                    Token::RParen,
+                    Token::EOF,
+                ]
+            );
+        }
+
+        // Types still take precedence after a letter
+        assert_eq!(
+            tokenize("(< list<int> x)".chars().collect())?,
+            vec![
+                Token::LParen,
+                ReservedIdent::Lt.into(),
+                Token::Type(Type::List(Box::new(Type::Int))),
+                Token::Ident("x".to_string()),
+                Token::RParen,
+                Token::EOF,
+            ]
+        );
+
+        Ok(())
+    }
+
+    assert_fails_lexer!(bare_bang_test, "(! 1 2)"; "Expected `=` after `!`");
+
+    #[test]
+    fn non_ascii_test() -> InterpreTestResult {
+        let ascii = "(add x_1 [2u \"hi\" 'c' nil] int)";
+        let exp = vec![
+            Token::LParen,
+            ReservedIdent::Add.into(),
+            Token::Ident("x_1".to_string()),
+            Token::LBrack,
+            Token::from(NumLiteral::new_int_with_suffix(2, false, 'u')),
+            Token::from("hi"),
+            Token::CharLiteral(b'c'),
+            Token::UnitLiteral,
+            Token::RBrack,
+            Token::Type(Type::Int),
+            Token::RParen,
+            Token::EOF,
+        ];
+
+        assert_eq!(tokenize(ascii.chars().collect())?, exp);
+
+        // Non-ascii chars are dropped wherever they are
+        let non_ascii = "(aédd x_é1 [2éu \"hié\" 'c' nil] int)→";
+        assert_eq!(tokenize(non_ascii.chars().collect())?, exp);
+
+        Ok(())
+    }
+
+    #[test]
+    fn uchar_literal_test() -> InterpreTestResult {
+        let input = "(== 'é' '😀' 'a') é";
+        let exp = [
+            Token::LParen,
+            ReservedIdent::Eq.into(),
+            Token::UCharLiteral('é'),
+            Token::UCharLiteral('😀'),
+            Token::CharLiteral(b'a'),
+            Token::RParen,
+            Token::EOF,
+        ];
+
+        assert_eq!(tokenize(input.chars().collect())?, exp);
+
+        // The spans cover the whole multi-byte char
+        let (kinds, err) = classify_tokens(input);
+        assert!(err.is_none());
+        assert_eq!(kinds[2], (TokenKind::Char, 4, 8));
+        assert_eq!(kinds[3], (TokenKind::Char, 9, 15));
+
+        let trivia = tokenize_with_trivia(input)?;
+        assert_eq!(trivia[3].1.text, "'😀'");
+
+        // Only char literals keep their non-ascii chars, not strings or comments
+        let exp = [
+            Token::LParen,
+            Token::from("a''b"),
+            Token::RParen,
+            Token::EOF,
+        ];
+        assert_eq!(tokenize("(\"a'é'b\")".chars().collect())?, exp);
+        assert_eq!(
+            tokenize("(\"a'é'b\" ; 'é'\n #| 'ü' |# )".chars().collect())?,
+            exp
+        );
+        assert_eq!(
+            tokenize("('\"' 'é')".chars().collect())?,
+            [
+                Token::LParen,
+                Token::CharLiteral(b'"'),
+                Token::UCharLiteral('é'),
+                Token::RParen,
+                Token::EOF,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn whitespace_comment_test() -> InterpreTestResult {
+        let input = "; adds things\n(+\t1 ; first\r\n   2) ;done";
+
+        assert_eq!(
+            tokenize(input.chars().collect())?,
+            tokenize("(+ 1 2)".chars().collect())?
+        );
+        assert_eq!(tokenize(";only a comment".chars().collect())?, [Token::EOF]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tokenize_into_test() -> InterpreTestResult {
+        let mut buf = Vec::new();
+        let first: Vec<char> = "(+ 1 (- 3 2) [4 5])".chars().collect();
+        let second: Vec<char> = "(isempty x)".chars().collect();
+
+        tokenize_into(&first, &mut buf)?;
+        assert_eq!(buf, tokenize(first.clone())?);
+
+        // The shorter second input leaves nothing of the first behind
+        tokenize_into(&second, &mut buf)?;
+        assert_eq!(buf, tokenize(second)?);
+
+        tokenize_into(&first, &mut buf)?;
+        assert_eq!(buf, tokenize(first)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn block_comment_test() -> InterpreTestResult {
+        let exp = tokenize("(+ 1 2)".chars().collect())?;
+        let inputs = [
+            "(+ #| the first |# 1 2)",
+            "#| adds\n   two numbers |#\n(+ 1 2 #|\n|#)",
+            "(+ 1 #| outer #| inner |# still outer |# 2)",
+            "(+ 1 #|#||##||#|# 2)",
+        ];
+
+        for input in inputs {
+            assert_eq!(tokenize(input.chars().collect())?, exp);
+        }
+
+        let tokens = tokenize_with_trivia("(+ #| a #| b |# |# 1 2)")?;
+        assert_eq!(tokens[1].1.trailing, " #| a #| b |# |# ");
+
+        Ok(())
+    }
+
+    assert_fails_lexer!(
+        unterminated_block_comment_test,
+        "(+ 1 #| outer #| inner |# 2)";
+        "Unterminated block comment, expected `|#`"
+    );
+    assert_fails_lexer!(bare_hash_test, "(+ 1 # 2)"; "Expected `|` after `#`");
+
+    #[test]
+    fn trivia_test() -> InterpreTestResult {
+        let inputs = [
+            "(+ 1 2)",
+            "; adds things\n(+\t1 ; first\r\n   2) ; done é\n\n",
+            "  [\"a b\" 'c' ; ;;\n  list<int>]",
+            "",
+        ];
+
+        for input in inputs {
+            let tokens = tokenize_with_trivia(input)?;
+            let rebuilt: String = tokens
+                .iter()
+                .map(|(_, t)| format!("{}{}{}", t.leading, t.text, t.trailing))
+                .collect();
+
+            assert_eq!(rebuilt, input);
+            assert_eq!(
+                tokens.into_iter().map(|(tok, _)| tok).collect::<Vec<_>>(),
+                tokenize(input.chars().collect())?
+            );
+        }
+
+        let tokens = tokenize_with_trivia("; adds\n(+ 1 ; first\n 2)")?;
+        assert_eq!(
+            tokens[0].1,
+            Trivia {
+                leading: "; adds\n".to_string(),
+                text: "(".to_string(),
+                trailing: "".to_string(),
+            }
+        );
+        assert_eq!(
+            tokens[2].1,
+            Trivia {
+                leading: "".to_string(),
+                text: "1".to_string(),
+                trailing: " ; first".to_string(),
+            }
+        );
+        assert_eq!(tokens[3].1.leading, "\n ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn classify_tokens_test() {
+        use TokenKind::*;
+
+        let (kinds, err) = classify_tokens("(add x_1 [2u \"hi\" 'c' nil] int)");
+
+        assert!(err.is_none());
+        assert_eq!(
+            kinds,
+            vec![
+                (Delimiter, 0, 1),
+                (Keyword, 1, 4),
+                (Identifier, 5, 8),
+                (Delimiter, 9, 10),
+                (Number, 10, 12),
+                (String, 13, 17),
+                (Char, 18, 21),
+                (Unit, 22, 25),
+                (Delimiter, 25, 26),
+                (Type, 27, 30),
+                (Delimiter, 30, 31),
+            ]
+        );
+
+        // Offsets are in bytes even though non-ascii chars are skipped, and the tokens before an
+        // error are kept
+        let (kinds, err) = classify_tokens("(- é 1 $)");
+
+        assert!(err.is_some());
+        assert_eq!(
+            kinds,
+            vec![(Delimiter, 0, 1), (Keyword, 1, 2), (Number, 6, 7)]
+        );
+    }
 }
@@ -3,7 +3,7 @@
 //! as examples for syntax/usage. Since they are mostly not public members doctests don't
 //! work so providing examples in the comments is subject to breakage.
 
-//#![allow(unused_macros)]
+#![allow(unused_macros)]
 
 macro_rules! import {
     (lexer) => {
@@ -57,6 +57,51 @@ macro_rules! assert_fails_parser {
     };
 }
 
+// Assert that two programs evaluate to equal values after coercion (see `Value::semantic_eq`),
+// e.g. `(+ 1 2)` and `(add 1 2)`. Since both go through the lexer, comments and whitespace
+// don't matter. Meant for tests that return an InterpreteResult, errors are passed on with `?`
+macro_rules! assert_eval_eq {
+    ($input1:expr, $input2:expr $(,)?) => {{
+        let eval = |input: &str| {
+            let tokens = $crate::blisp::lexer::tokenize(input.chars().collect())?;
+            $crate::blisp::interpreter::eval(&$crate::blisp::parser::parse_prog(&tokens)?.0)
+        };
+        let (val1, val2) = (eval($input1)?, eval($input2)?);
+
+        assert!(
+            val1.semantic_eq(&val2)?,
+            "`{}` evaluated to {:?} but `{}` evaluated to {:?}",
+            $input1,
+            val1,
+            $input2,
+            val2
+        );
+    }};
+}
+
+// Assert that a program evaluates to exactly the given value, e.g. `(+ 1 2)` and
+// `Value::from(3i64)`. Errors from any step are passed on with `?`, like `assert_eval_eq`
+macro_rules! assert_eval {
+    ($input:expr, $value:expr $(,)?) => {{
+        let input: &str = $input;
+        let tokens = $crate::blisp::lexer::tokenize(input.chars().collect())?;
+        let val = $crate::blisp::interpreter::eval(&$crate::blisp::parser::parse_prog(&tokens)?.0)?;
+
+        assert_eq!(val, $value, "{}", input);
+    }};
+}
+
+// Assert that a program lexes and parses but fails to evaluate, with the given error message
+macro_rules! assert_eval_err {
+    ($input:expr, $message:expr $(,)?) => {{
+        let input: &str = $input;
+        let tokens = $crate::blisp::lexer::tokenize(input.chars().collect())?;
+        let res = $crate::blisp::interpreter::eval(&$crate::blisp::parser::parse_prog(&tokens)?.0);
+
+        assert_eq!(res.unwrap_err().to_string(), $message, "{}", input);
+    }};
+}
+
 // Pattern that represents the valid tokens in Val rule
 macro_rules! val_pattern {
     () => {
@@ -65,6 +110,7 @@ macro_rules! val_pattern {
             | Token::Ident(_)
             | Token::Type(_)
             | Token::CharLiteral(_)
+            | Token::UCharLiteral(_)
             | Token::StringLiteral(_)
             | Token::NumLiteral(_)
             | Token::UnitLiteral
@@ -73,6 +119,7 @@ macro_rules! val_pattern {
         Token::Ident(_)
             | Token::Type(_)
             | Token::CharLiteral(_)
+            | Token::UCharLiteral(_)
             | Token::StringLiteral(_)
             | Token::NumLiteral(_)
             | Token::UnitLiteral
@@ -115,19 +162,9 @@ macro_rules! list_node_helper {
     [$($item:expr),+] => {{
         $crate::blisp::macros::import!(*);
 
-        let mut vec: Vec<Node> = [$($item),+].to_vec();
-        let mut node = rule_node_helper!(ListBody, [vec.pop().unwrap()]);
-
-        while let Some(item) = vec.pop() {
-            node = rule_node_helper!(ListBody, [item, node]);
-        }
-
-        node = rule_node_helper!(
-            List,
-            [node]
-        );
+        let node = rule_node_helper!(ListBody, [$($item),+]);
 
-        node
+        rule_node_helper!(List, [node])
     }};
 }
 
@@ -136,21 +173,13 @@ macro_rules! func_call_node_helper {
     ($func:ident, [$($arg:expr),+]) => {{
         $crate::blisp::macros::import!(*);
 
-        let mut vec: Vec<Node> = [$($arg),+].to_vec();
-        let mut node = rule_node_helper!(Args, [vec.pop().unwrap()]);
-
-        while let Some(item) = vec.pop() {
-            node = rule_node_helper!(Args, [item, node]);
-        }
-
+        let node = rule_node_helper!(Args, [$($arg),+]);
         let func_node = Node::Leaf(ParseToken::Reserved(ReservedIdent::$func));
 
-        node = rule_node_helper!(
+        rule_node_helper!(
             FuncCall,
             [func_node, node]
-        );
-
-        node
+        )
     }};
 }
 
@@ -204,19 +233,50 @@ macro_rules! list_value_helper {
 
 #[cfg(test)]
 mod tests {
-    use std::rc::Rc;
-
     use crate::{
-        blisp::{lexer::Token, parser::Node},
+        blisp::{interpreter::Value, lexer::Token, parser::Node},
         error::InterpreTestResult,
     };
 
-    use super::*;
-
     // Test assert_fails
     assert_fails!(assert_fails_test1 => panic!("TestMessage"); "TestMessage");
     assert_fails!(assert_fails_test2=> panic!());
 
+    #[test]
+    fn assert_eval_eq_test() -> InterpreTestResult {
+        assert_eval_eq!("(+ 1 2)", "(add 1 2)");
+        assert_eval_eq!("(+ 1 2)", "(- 5 2)");
+        assert_eval_eq!("\"ab\"", "['a' 'b']");
+        assert_eval_eq!("(+ 1   ; one\n 2)", "#| three |# 3");
+
+        Ok(())
+    }
+
+    assert_fails!(assert_eval_eq_test2 => {
+        (|| -> InterpreTestResult {
+            assert_eval_eq!("(+ 1 2)", "(+ 1 1)");
+            Ok(())
+        })()
+        .unwrap()
+    }; "`(+ 1 2)` evaluated to");
+
+    #[test]
+    fn assert_eval_test() -> InterpreTestResult {
+        assert_eval!("(+ 1 2)", Value::from(3i64));
+        assert_eval!("\"ab\"", Value::from("ab".to_string()));
+        assert_eval_err!("(div 1 0)", "Division by zero evaluating 1 / 0");
+
+        Ok(())
+    }
+
+    assert_fails!(assert_eval_test2 => {
+        (|| -> InterpreTestResult {
+            assert_eval_err!("(+ 1 2)", "");
+            Ok(())
+        })()
+        .unwrap()
+    }; "called `Result::unwrap_err()` on an `Ok` value");
+
     // Test assert_fails_lexer
     assert_fails_lexer!(
         assert_fails_lexer_test1,
@@ -238,7 +298,7 @@ mod tests {
     assert_fails_parser!(
         assert_fails_parser_test2,
         "(['a' 12 \"ART\")";
-        "Unexpected token encountered while parsing expression body: RParen"
+        "Unmatched [ opened at token 1, expected ] but found RParen at token 5"
     );
 
     #[test]
@@ -335,21 +395,11 @@ mod tests {
                             12, 4, false
                         )))]
                     ),
-                    Node::Rule(RuleNodeData::new(
-                        Rule::ListBody,
-                        [
-                            rule_node_helper!(Val, [Node::from(ParseToken::CharLiteral(b'c'))]),
-                            Node::Rule(RuleNodeData::new(
-                                Rule::ListBody,
-                                [rule_node_helper!(
-                                    Val,
-                                    [Node::from(ParseToken::StringLiteral("ABCD".to_string()))]
-                                )]
-                                .to_vec(),
-                            )),
-                        ]
-                        .to_vec(),
-                    )),
+                    rule_node_helper!(Val, [Node::from(ParseToken::CharLiteral(b'c'))]),
+                    rule_node_helper!(
+                        Val,
+                        [Node::from(ParseToken::StringLiteral("ABCD".to_string()))]
+                    ),
                 ]
                 .to_vec(),
             ))]
@@ -372,17 +422,8 @@ mod tests {
                                 13, 5, false
                             )))]
                         ),
-                        Node::Rule(RuleNodeData::new(
-                            Rule::Args,
-                            [
-                                rule_node_helper!(Val, [Node::from(ParseToken::CharLiteral(b'd'))]),
-                                Node::Rule(RuleNodeData::new(
-                                    Rule::Args,
-                                    [rule_node_helper!(Val, [get_test_list_node()])].to_vec(),
-                                )),
-                            ]
-                            .to_vec(),
-                        )),
+                        rule_node_helper!(Val, [Node::from(ParseToken::CharLiteral(b'd'))]),
+                        rule_node_helper!(Val, [get_test_list_node()]),
                     ]
                     .to_vec(),
                 )),
@@ -559,6 +600,9 @@ crate_publish_macros!(
     assert_fails,
     assert_fails_lexer,
     assert_fails_parser,
+    assert_eval_eq,
+    assert_eval,
+    assert_eval_err,
     val_pattern,
     rule_node_helper,
     val_node_helper,
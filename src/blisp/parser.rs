@@ -1,9 +1,9 @@
-use std::rc::Rc;
+use std::fmt::{self, Display, Formatter};
 
 use crate::error::{InterpretError, InterpreteResult};
 
 use super::{
-    lexer::{NumLiteral, ReservedIdent, Token, Type},
+    lexer::{is_keyword, NumLiteral, ReservedIdent, Token, Type},
     macros::{rule_node_helper, val_pattern},
 };
 
@@ -26,6 +26,7 @@ pub enum Rule {
 pub enum ParseToken {
     NumLiteral(NumLiteral),
     CharLiteral(u8),
+    UCharLiteral(char),
     UnitLiteral,
     StringLiteral(String),
     Ident(String),
@@ -33,6 +34,25 @@ pub enum ParseToken {
     Reserved(ReservedIdent),
 }
 
+impl Display for ParseToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NumLiteral(n) => write!(f, "{}", n),
+            Self::CharLiteral(c) if c.is_ascii_graphic() || *c == b' ' => {
+                write!(f, "'{}'", *c as char)
+            }
+            Self::CharLiteral(c) => write!(f, "'\\x{:02X}'", c),
+            Self::UCharLiteral(c) => write!(f, "'{}'", c),
+            Self::UnitLiteral => write!(f, "nil"),
+            Self::StringLiteral(s) => write!(f, "\"{}\"", s),
+            Self::Ident(i) if is_keyword(i) => write!(f, "@{}", i),
+            Self::Ident(i) => write!(f, "{}", i),
+            Self::Type(t) => write!(f, "{}", t),
+            Self::Reserved(r) => write!(f, "{}", r),
+        }
+    }
+}
+
 impl From<NumLiteral> for ParseToken {
     fn from(value: NumLiteral) -> Self {
         Self::NumLiteral(value)
@@ -45,6 +65,12 @@ impl From<u8> for ParseToken {
     }
 }
 
+impl From<char> for ParseToken {
+    fn from(value: char) -> Self {
+        Self::UCharLiteral(value)
+    }
+}
+
 impl From<String> for ParseToken {
     fn from(value: String) -> Self {
         Self::StringLiteral(value)
@@ -75,6 +101,7 @@ impl TryFrom<Token> for ParseToken {
         match value {
             Token::NumLiteral(n) => Ok(Self::NumLiteral(n)),
             Token::CharLiteral(c) => Ok(Self::CharLiteral(c)),
+            Token::UCharLiteral(c) => Ok(Self::UCharLiteral(c)),
             Token::UnitLiteral => Ok(Self::UnitLiteral),
             Token::StringLiteral(s) => Ok(Self::StringLiteral(s)),
             Token::Ident(i) => Ok(Self::Ident(i)),
@@ -85,9 +112,23 @@ impl TryFrom<Token> for ParseToken {
     }
 }
 
+/// How deeply expressions and lists can be nested in a program parsed with `parse_prog`. The
+/// parser is recursive so this stops deeply nested input from overflowing the stack
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
 pub fn parse_prog(tokens: &[Token]) -> ParseResult {
-    let (child, cnt) = parse_expr(tokens)?;
-    let node = rule_node_helper!(Prog, child);
+    parse_prog_with_limit(tokens, DEFAULT_MAX_DEPTH)
+}
+
+/// Same as `parse_prog`, but returns an Err if expressions or lists are nested more than
+/// `max_depth` levels deep
+pub fn parse_prog_with_limit(tokens: &[Token], max_depth: usize) -> ParseResult {
+    // Empty programs are rejected rather than given a value, since they're most likely a mistake
+    if tokens.first().ok_or("Unexpected empty token stream")? == &Token::EOF {
+        return Err("Empty program, expected an expression or value".into());
+    }
+
+    let (node, cnt) = parse_top_level(tokens, max_depth)?;
 
     if tokens
         .get(cnt)
@@ -100,17 +141,57 @@ pub fn parse_prog(tokens: &[Token]) -> ParseResult {
     }
 }
 
-fn parse_expr(tokens: &[Token]) -> ParseResult {
+/// Parses the next top-level expression from a stream holding any number of them, for a REPL or
+/// a file that's evaluated one expression at a time. Returns the expression wrapped in a Prog
+/// node, so it can be passed to `eval` directly, along with the number of tokens it took up. At
+/// EOF (or the end of the tokens) this returns `None`. Token positions in errors are relative to
+/// the start of `tokens`
+pub fn parse_next(tokens: &[Token]) -> InterpreteResult<Option<(Node, usize)>> {
+    match tokens.first() {
+        None | Some(Token::EOF) => Ok(None),
+        Some(_) => parse_top_level(tokens, DEFAULT_MAX_DEPTH).map(Some),
+    }
+}
+
+/// Parses a single top-level expression into a Prog node. This is either a parenthesized
+/// expression or a bare value, like `5` or `[1 2]`
+fn parse_top_level(tokens: &[Token], max_depth: usize) -> ParseResult {
+    let (child, cnt) = match &tokens[0] {
+        Token::LParen => parse_expr(tokens, max_depth, 0)?,
+        Token::LBrack | val_pattern!(terminals) => parse_val(tokens, max_depth, 0)?,
+        t => {
+            return Err(format!(
+                "Expected an expression or value at the start of the program, found {:?}",
+                t
+            )
+            .into())
+        }
+    };
+
+    Ok((rule_node_helper!(Prog, child), cnt))
+}
+
+// `depth` is the number of nesting levels still allowed, this is called whenever the parser
+// descends into a nested expression or list
+fn nested_depth(depth: usize) -> InterpreteResult<usize> {
+    depth
+        .checked_sub(1)
+        .ok_or("Exceeded the maximum nesting depth while parsing".into())
+}
+
+fn parse_expr(tokens: &[Token], depth: usize, pos: usize) -> ParseResult {
     if tokens[0] == Token::LParen {
-        let (child, cnt) = parse_expr_body(&tokens[1..])?;
+        let (child, cnt) = parse_expr_body(&tokens[1..], depth, pos + 1)?;
         let node = rule_node_helper!(Expr, [child]);
 
         if tokens[cnt + 1] == Token::RParen {
             Ok((node, cnt + 2))
         } else {
             Err(format!(
-                "Expected ) while parsing expression, encountered {:?}",
-                tokens[cnt + 1]
+                "Unmatched ( opened at token {}, expected ) but found {:?} at token {}",
+                pos,
+                tokens[cnt + 1],
+                pos + cnt + 1
             )
             .into())
         }
@@ -123,21 +204,26 @@ fn parse_expr(tokens: &[Token]) -> ParseResult {
     }
 }
 
-fn parse_expr_body(tokens: &[Token]) -> ParseResult {
+fn parse_expr_body(tokens: &[Token], depth: usize, pos: usize) -> ParseResult {
     match &tokens[0] {
-        Token::Reserved(_) => {
-            let (child, cnt) = parse_func_call(&tokens[0..])?;
+        // An identifier followed by arguments calls a function registered with the State (see
+        // `State::register_fn`), on its own it's still a variable
+        Token::Reserved(_) | Token::Ident(_)
+            if tokens[0].is_reserved() || tokens.get(1) != Some(&Token::RParen) =>
+        {
+            let (child, cnt) = parse_func_call(&tokens[0..], depth, pos)?;
             let node = rule_node_helper!(ExprBody, child);
 
             Ok((node, cnt))
         }
         val_pattern!() => {
             // We have <Val> and need to process it
-            let (child, cnt) = parse_val(&tokens[0..])?;
+            let (child, cnt) = parse_val(&tokens[0..], depth, pos)?;
             let node = rule_node_helper!(ExprBody, child);
 
             Ok((node, cnt))
         }
+        Token::RParen => Err("Empty expression `()`, the unit value is written as `nil`".into()),
         t => Err(format!(
             "Unexpected token encountered while parsing expression body: {:?}",
             t
@@ -146,51 +232,71 @@ fn parse_expr_body(tokens: &[Token]) -> ParseResult {
     }
 }
 
-fn parse_func_call(tokens: &[Token]) -> ParseResult {
-    let func = tokens[0].assert_reserved()?;
+fn parse_func_call(tokens: &[Token], depth: usize, pos: usize) -> ParseResult {
+    let func = match &tokens[0] {
+        Token::Reserved(rsv) => ParseToken::from(*rsv),
+        Token::Ident(name) => ParseToken::Ident(name.clone()),
+        t => return Err(format!("Expected function name, found {:?}", t).into()),
+    };
 
-    let (child, cnt) = parse_args(&tokens[1..])?;
-    let node = rule_node_helper!(FuncCall, [Node::Leaf(ParseToken::from(*func)), child]);
+    let (child, cnt) = parse_args(&tokens[1..], depth, pos + 1)?;
+    let node = rule_node_helper!(FuncCall, [Node::Leaf(func), child]);
 
     Ok((node, cnt + 1))
 }
 
-fn parse_args(tokens: &[Token]) -> ParseResult {
-    match &tokens[0] {
-        val_pattern!() => {
-            // We have <Val> and need to process it
-            let (val, val_cnt) = parse_val(tokens)?;
-
-            Ok(
-                if tokens.get(val_cnt).ok_or::<InterpretError>(
-                    "Unexpectedly reached end of input while parsing arguments".into(),
-                )? == &Token::RParen
-                {
-                    (rule_node_helper!(Args, val), val_cnt)
-                } else {
-                    let (tail, tail_cnt) = parse_args(&tokens[val_cnt..])?;
-                    (rule_node_helper!(Args, [val, tail]), val_cnt + tail_cnt)
-                },
-            )
+// Like ListBody, the Args node is kept flat with one Val child per argument, so a long argument
+// list doesn't need recursion proportional to its length
+fn parse_args(tokens: &[Token], depth: usize, pos: usize) -> ParseResult {
+    let mut vals = Vec::new();
+    let mut cnt = 0;
+
+    loop {
+        match &tokens[cnt] {
+            // A call without arguments, like `(readline)`. Only reserved functions get here this
+            // way, an identifier on its own is a variable (see `parse_expr_body`)
+            Token::RParen if vals.is_empty() => {
+                return Ok((Node::Rule(RuleNodeData::new(Rule::Args, vals)), 0))
+            }
+            val_pattern!() => {
+                // We have <Val> and need to process it
+                let (val, val_cnt) = parse_val(&tokens[cnt..], depth, pos + cnt)?;
+                vals.push(val);
+                cnt += val_cnt;
+
+                // Any closing delimiter ends the arguments, `parse_expr` reports it if it's not a `)`
+                if matches!(
+                    tokens.get(cnt).ok_or::<InterpretError>(
+                        "Unexpectedly reached end of input while parsing arguments".into(),
+                    )?,
+                    Token::RParen | Token::RBrack | Token::EOF
+                ) {
+                    return Ok((Node::Rule(RuleNodeData::new(Rule::Args, vals)), cnt));
+                }
+            }
+            t => {
+                return Err(format!(
+                    "Unexpected token encountered while parsing arguments: {:?}",
+                    t
+                )
+                .into())
+            }
         }
-        t => Err(format!(
-            "Unexpected token encountered while parsing arguments: {:?}",
-            t
-        )
-        .into()),
     }
 }
 
-fn parse_val(tokens: &[Token]) -> ParseResult {
+fn parse_val(tokens: &[Token], depth: usize, pos: usize) -> ParseResult {
     match &tokens[0] {
         Token::LBrack => {
-            let (child, cnt) = parse_list(tokens)?;
+            let depth = nested_depth(depth)?;
+            let (child, cnt) = parse_list(tokens, depth, pos)?;
             let node = rule_node_helper!(Val, child);
 
             Ok((node, cnt))
         }
         Token::LParen => {
-            let (child, cnt) = parse_expr(tokens)?;
+            let depth = nested_depth(depth)?;
+            let (child, cnt) = parse_expr(tokens, depth, pos)?;
             let node = rule_node_helper!(Val, child);
 
             Ok((node, cnt))
@@ -206,17 +312,24 @@ fn parse_val(tokens: &[Token]) -> ParseResult {
     }
 }
 
-fn parse_list(tokens: &[Token]) -> ParseResult {
+fn parse_list(tokens: &[Token], depth: usize, pos: usize) -> ParseResult {
     if tokens[0] == Token::LBrack {
-        let (child, cnt) = parse_list_body(&tokens[1..])?;
-        let node = rule_node_helper!(List, [child.clone()]);
+        if tokens.get(1) == Some(&Token::RBrack) {
+            // Empty list, a List node with no ListBody child
+            return Ok((Node::Rule(RuleNodeData::new(Rule::List, vec![])), 2));
+        }
+
+        let (child, cnt) = parse_list_body(&tokens[1..], depth, pos + 1)?;
+        let node = rule_node_helper!(List, [child]);
 
         if tokens[cnt + 1] == Token::RBrack {
             Ok((node, cnt + 2))
         } else {
             Err(format!(
-                "Expected ] while parsing list, encountered {:?}",
-                tokens[cnt + 1]
+                "Unmatched [ opened at token {}, expected ] but found {:?} at token {}",
+                pos,
+                tokens[cnt + 1],
+                pos + cnt + 1
             )
             .into())
         }
@@ -225,34 +338,44 @@ fn parse_list(tokens: &[Token]) -> ParseResult {
     }
 }
 
-fn parse_list_body(tokens: &[Token]) -> ParseResult {
-    match &tokens[0] {
-        val_pattern!() => {
-            // We have <Val> and need to process it
-            let (val, val_cnt) = parse_val(tokens)?;
-
-            Ok(
-                if tokens.get(val_cnt).ok_or::<InterpretError>(
-                    "Unexpectedly reached end of input while trying to parse list".into(),
-                )? == &Token::RBrack
-                {
-                    (rule_node_helper!(ListBody, val), val_cnt)
-                } else {
-                    let (tail, tail_cnt) = parse_list_body(&tokens[val_cnt..])?;
-                    (rule_node_helper!(ListBody, [val, tail]), val_cnt + tail_cnt)
-                },
-            )
+// The ListBody node is kept flat, with one Val child per element. A nested chain (as in the
+// grammar) would need recursion proportional to the list's length to parse, evaluate, and drop
+fn parse_list_body(tokens: &[Token], depth: usize, pos: usize) -> ParseResult {
+    let mut vals = Vec::new();
+    let mut cnt = 0;
+
+    loop {
+        match &tokens[cnt] {
+            val_pattern!() => {
+                // We have <Val> and need to process it
+                let (val, val_cnt) = parse_val(&tokens[cnt..], depth, pos + cnt)?;
+                vals.push(val);
+                cnt += val_cnt;
+
+                // Any closing delimiter ends the body, `parse_list` reports it if it's not a `]`
+                if matches!(
+                    tokens.get(cnt).ok_or::<InterpretError>(
+                        "Unexpectedly reached end of input while trying to parse list".into(),
+                    )?,
+                    Token::RBrack | Token::RParen | Token::EOF
+                ) {
+                    return Ok((Node::Rule(RuleNodeData::new(Rule::ListBody, vals)), cnt));
+                }
+            }
+            t => {
+                return Err(format!(
+                    "Unexpected token encountered while parsing expression body: {:?}",
+                    t
+                )
+                .into())
+            }
         }
-        t => Err(format!(
-            "Unexpected token encountered while parsing expression body: {:?}",
-            t
-        )
-        .into()),
     }
 }
 
 // Want to create functions that "execute a rule" by gobbling tokens and return Nodes
 pub struct ParseTree {
+    #[allow(dead_code)]
     prog: Node,
 }
 
@@ -271,6 +394,53 @@ pub enum Node {
 }
 
 impl Node {
+    /// Reconstructs BLisp source code from the tree, so that parsing the result gives back the
+    /// same tree. Whitespace is normalized to single spaces
+    pub fn to_source(&self) -> String {
+        match self {
+            Self::Leaf(tok) => tok.to_string(),
+            Self::Rule(RuleNodeData { rule, children }) => {
+                // Skips the empty Args of a call without arguments
+                let inner = children
+                    .iter()
+                    .map(Node::to_source)
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                match rule {
+                    Rule::Expr => format!("({})", inner),
+                    Rule::List => format!("[{}]", inner),
+                    Rule::Prog
+                    | Rule::ExprBody
+                    | Rule::Val
+                    | Rule::ListBody
+                    | Rule::FuncCall
+                    | Rule::Args => inner,
+                }
+            }
+        }
+    }
+
+    /// Renders the tree as nested parenthesized text, e.g. `(Prog (Expr (ExprBody (Val 5))))`.
+    /// Leaves are written the way they appear in source code
+    pub fn to_sexp(&self) -> String {
+        match self {
+            Self::Leaf(tok) => tok.to_string(),
+            Self::Rule(RuleNodeData { rule, children }) => {
+                let mut res = format!("({:?}", rule);
+
+                for child in children {
+                    res.push(' ');
+                    res.push_str(&child.to_sexp());
+                }
+
+                res.push(')');
+                res
+            }
+        }
+    }
+
     pub fn is_val(&self) -> bool {
         matches!(
             self,
@@ -309,16 +479,88 @@ impl RuleNodeData {
     }
 }
 
+/// A node of a `FlatTree`, rule nodes refer to their children by index
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum FlatNode {
+    Leaf(ParseToken),
+    Rule { rule: Rule, children: Vec<usize> },
+}
+
+/// Parse tree with all of its nodes in one `Vec`, in pre-order so the root is always at index 0
+/// and every node comes before its children
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct FlatTree {
+    nodes: Vec<FlatNode>,
+}
+
+impl FlatTree {
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = Vec::new();
+        // Nodes still to be added, along with the index of their parent
+        let mut stack: Vec<(&Node, Option<usize>)> = vec![(node, None)];
+
+        while let Some((node, parent)) = stack.pop() {
+            let index = nodes.len();
+
+            match node {
+                Node::Leaf(tok) => nodes.push(FlatNode::Leaf(tok.clone())),
+                Node::Rule(RuleNodeData { rule, children }) => {
+                    nodes.push(FlatNode::Rule {
+                        rule: *rule,
+                        children: Vec::with_capacity(children.len()),
+                    });
+
+                    // Reversed so the children are popped, and so numbered, in order
+                    stack.extend(children.iter().rev().map(|child| (child, Some(index))));
+                }
+            }
+
+            if let Some(FlatNode::Rule { children, .. }) = parent.map(|p| &mut nodes[p]) {
+                children.push(index);
+            }
+        }
+
+        Self { nodes }
+    }
+
+    pub fn to_node(&self) -> Node {
+        self.node_at(0)
+    }
+
+    fn node_at(&self, index: usize) -> Node {
+        match &self.nodes[index] {
+            FlatNode::Leaf(tok) => Node::Leaf(tok.clone()),
+            FlatNode::Rule { rule, children } => Node::Rule(RuleNodeData::new(
+                *rule,
+                children.iter().map(|&child| self.node_at(child)).collect(),
+            )),
+        }
+    }
+
+    pub fn nodes(&self) -> &[FlatNode] {
+        &self.nodes
+    }
+
+    /// Number of nodes in the tree, leaves included
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Always false since a tree has at least its root
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
         blisp::{
-            lexer::{tokenize, NumLiteral, Token, Type},
+            lexer::tokenize,
             macros::{
                 assert_fails, assert_fails_parser, func_call_node_helper, list_node_helper,
                 prog_node_helper, val_node_helper,
             },
-            parser::parse_val,
         },
         error::InterpreTestResult,
     };
@@ -375,7 +617,7 @@ mod tests {
                 3
             ],
             [
-                "(())",
+                "(nil)",
                 prog_node_helper!(val_node_helper!(ParseToken::UnitLiteral)),
                 3
             ]
@@ -399,20 +641,273 @@ mod tests {
             val_node_helper!(ParseToken::UnitLiteral)
         );
 
+        let empty = Node::Rule(RuleNodeData::new(Rule::List, vec![]));
+
         do_parse_test!(
+            ["([])", prog_node_helper!(val_node_helper!([empty])), 4],
             [
                 "([12.4 'c' \"ABCD\"])",
                 prog_node_helper!(val_node_helper!([node1])),
                 7
             ],
             [
-                "([-14.6 [12.4 'c' \"ABCD\"] ()])",
+                "([-14.6 [12.4 'c' \"ABCD\"] nil])",
                 prog_node_helper!(val_node_helper!([node2])),
                 11
             ]
         )
     }
 
+    #[test]
+    fn parse_bare_val_test() -> InterpreTestResult {
+        let list = list_node_helper!(
+            val_node_helper!(ParseToken::from(NumLiteral::new_int(1, false))),
+            val_node_helper!(ParseToken::from(NumLiteral::new_int(2, false)))
+        );
+
+        do_parse_test!(
+            [
+                "5",
+                rule_node_helper!(
+                    Prog,
+                    [val_node_helper!(ParseToken::from(NumLiteral::new_int(
+                        5, false
+                    )))]
+                ),
+                1
+            ],
+            [
+                "\"hi\"",
+                rule_node_helper!(Prog, [val_node_helper!(ParseToken::from("hi".to_string()))]),
+                1
+            ],
+            [
+                "[1 2]",
+                rule_node_helper!(Prog, [val_node_helper!([list])]),
+                4
+            ]
+        )
+    }
+
+    assert_fails_parser!(
+        parse_bare_func_call_test,
+        "+ 1 2";
+        "Expected an expression or value at the start of the program, found Reserved(Add)"
+    );
+    assert_fails_parser!(
+        parse_empty_test,
+        "   ";
+        "Empty program, expected an expression or value"
+    );
+    assert_fails_parser!(
+        parse_empty_expr_test,
+        "()";
+        "Empty expression `()`, the unit value is written as `nil`"
+    );
+    assert_fails_parser!(
+        parse_empty_expr_space_test,
+        "( )";
+        "Empty expression `()`, the unit value is written as `nil`"
+    );
+    assert_fails_parser!(
+        parse_nested_empty_expr_test,
+        "(())";
+        "Empty expression `()`, the unit value is written as `nil`"
+    );
+    assert_fails_parser!(
+        parse_unmatched_bracket_test,
+        "([1 2)";
+        "Unmatched [ opened at token 1, expected ] but found RParen at token 4"
+    );
+    assert_fails_parser!(
+        parse_unmatched_paren_test,
+        "(+ [1] (- 2 3]";
+        "Unmatched ( opened at token 5, expected ) but found RBrack at token 9"
+    );
+    assert_fails_parser!(
+        parse_unclosed_test,
+        "(+ 1 [2";
+        "Unmatched [ opened at token 3, expected ] but found EOF at token 5"
+    );
+    assert_fails_parser!(
+        parse_bare_val_trailing_test,
+        "5 6";
+        "Unexpected token where EOF was expected"
+    );
+
+    #[test]
+    fn max_depth_test() -> InterpreTestResult {
+        let nested = |n| format!("{}5{}", "(".repeat(n), ")".repeat(n));
+
+        let tokens = tokenize(nested(100_000).chars().collect())?;
+        assert_eq!(
+            parse_prog(&tokens).unwrap_err().to_string(),
+            "Exceeded the maximum nesting depth while parsing"
+        );
+
+        let tokens = tokenize(nested(50).chars().collect())?;
+        assert!(parse_prog(&tokens).is_ok());
+        assert!(parse_prog_with_limit(&tokens, 48).is_err());
+        assert!(parse_prog_with_limit(&tokens, 49).is_ok());
+
+        let tokens = tokenize("([[[1]]])".chars().collect())?;
+        assert!(parse_prog_with_limit(&tokens, 2).is_err());
+        assert!(parse_prog_with_limit(&tokens, 3).is_ok());
+
+        // Arguments are siblings, a long argument list doesn't count as nesting
+        let tokens = tokenize(format!("(+{})", " (1)".repeat(10_000)).chars().collect())?;
+        assert!(parse_prog_with_limit(&tokens, 0).is_err());
+        assert!(parse_prog_with_limit(&tokens, 1).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn long_args_test() -> InterpreTestResult {
+        let input = format!("(+{})", " 1".repeat(100_000));
+        let tokens = tokenize(input.chars().collect())?;
+
+        let sexp = parse_prog(&tokens)?.0.to_sexp();
+        assert!(sexp.starts_with("(Prog (Expr (ExprBody (FuncCall add (Args (Val 1) (Val 1) "));
+        assert_eq!(sexp.matches("(Val 1)").count(), 100_000);
+
+        assert!(crate::blisp::validate(&input).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_next_test() -> InterpreTestResult {
+        let tokens = tokenize("(add 1 2) [3 4]".chars().collect())?;
+
+        let (first, cnt) = parse_next(&tokens)?.unwrap();
+        assert_eq!(cnt, 5);
+        assert_eq!(
+            first.to_sexp(),
+            "(Prog (Expr (ExprBody (FuncCall add (Args (Val 1) (Val 2))))))"
+        );
+
+        let (second, cnt2) = parse_next(&tokens[cnt..])?.unwrap();
+        assert_eq!(cnt2, 4);
+        assert_eq!(
+            second.to_sexp(),
+            "(Prog (Val (List (ListBody (Val 3) (Val 4)))))"
+        );
+
+        assert_eq!(parse_next(&tokens[cnt + cnt2..])?, None);
+        assert_eq!(parse_next(&[])?, None);
+
+        let tokens = tokenize("(add 1 2) )".chars().collect())?;
+        assert!(parse_next(&tokens[5..]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn to_sexp_test() -> InterpreTestResult {
+        let cases = [
+            ("(5)", "(Prog (Expr (ExprBody (Val 5))))"),
+            ("5", "(Prog (Val 5))"),
+            (
+                "(+ -1.5 [2u 'a'] \"hi\")",
+                "(Prog (Expr (ExprBody (FuncCall add (Args (Val -1.5) (Val (List (ListBody \
+                 (Val 2u) (Val 'a')))) (Val \"hi\"))))))",
+            ),
+            ("([])", "(Prog (Expr (ExprBody (Val (List)))))"),
+            // A call without arguments still has an Args node, just an empty one
+            ("(read)", "(Prog (Expr (ExprBody (FuncCall read (Args)))))"),
+            (
+                "(write (readline))",
+                "(Prog (Expr (ExprBody (FuncCall write (Args (Val (Expr (ExprBody (FuncCall \
+                 readline (Args))))))))))",
+            ),
+        ];
+
+        for (input, sexp) in cases {
+            let tokens = tokenize(input.chars().collect())?;
+
+            assert_eq!(parse_prog(&tokens)?.0.to_sexp(), sexp);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn flat_tree_test() -> InterpreTestResult {
+        let inputs = ["(5)", "[1 2]", "(+ 1 (- 3 [2 'a' \"bc\"]))", "([[] [nil]])"];
+
+        for input in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let node = parse_prog(tokens.as_slice())?.0;
+
+            let flat = FlatTree::from_node(&node);
+            assert_eq!(flat.to_node(), node);
+
+            // Children always come after their parent
+            for (i, flat_node) in flat.nodes().iter().enumerate() {
+                if let FlatNode::Rule { children, .. } = flat_node {
+                    assert!(children.iter().all(|&c| c > i));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn flat_tree_count_test() -> InterpreTestResult {
+        let tokens = tokenize("(+ 1 2)".chars().collect())?;
+        let flat = FlatTree::from_node(&parse_prog(tokens.as_slice())?.0);
+
+        // Prog, Expr, ExprBody, FuncCall, `+`, Args, Val, 1, Val, 2
+        assert_eq!(flat.len(), 10);
+        assert_eq!(
+            flat.nodes()
+                .iter()
+                .filter(|n| matches!(n, FlatNode::Leaf(_)))
+                .count(),
+            3
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn to_source_test() -> InterpreTestResult {
+        let inputs = [
+            ("(5)", "(5)"),
+            ("-2.5f", "-2.5f"),
+            ("(  add 1   (sub 2u 3) )", "(add 1 (sub 2u 3))"),
+            ("(+ 1 2)", "(add 1 2)"),
+            (
+                "([1 [2 3] [] 'a' \"bc\" nil])",
+                "([1 [2 3] [] 'a' \"bc\" nil])",
+            ),
+            ("[[1] [2 3]]", "[[1] [2 3]]"),
+            ("(['\\x41' '\\xFF' '\\x0a'])", "(['A' '\\xFF' '\\x0A'])"),
+            ("(init my_var list<char>)", "(init my_var list<char>)"),
+            ("(write ( readline ))", "(write (readline))"),
+            ("(let @read 5 @read)", "(let @read 5 @read)"),
+            ("(let @int @nil [@int])", "(let @int @nil [@int])"),
+            // The `@` is only kept where it's needed
+            ("(@double @x)", "(double x)"),
+            (
+                "(tostring (contains 48c \"abc\"))",
+                "(tostring (contains 48c \"abc\"))",
+            ),
+        ];
+
+        for (input, exp) in inputs {
+            let tokens = tokenize(input.chars().collect())?;
+            let source = parse_prog(&tokens)?.0.to_source();
+
+            assert_eq!(source, exp);
+            assert_eq!(tokenize(source.chars().collect())?, tokens);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn parse_func_call_test() -> InterpreTestResult {
         let node1 = func_call_node_helper!(
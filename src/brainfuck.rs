@@ -1,13 +1,114 @@
 pub mod optimizations;
 pub mod test_macros;
+pub mod transpile;
 
-use crate::error::{InterpretError, InterpreteResult};
+use crate::error::InterpreteResult;
+use optimizations::optimize_for;
 use std::{
     collections::HashMap,
     io::{stdin, stdout, Read, Stdin, Stdout, Write},
 };
 
+/// Checks that the brackets in a string literal of Brainfuck code are balanced at compile time,
+/// expanding to the literal itself
+///
+/// # Examples
+/// ```
+/// use interprete_rs::brainfuck::{brainfuck, BrainfuckProgram};
+/// let code: &str = brainfuck!("++[->+<]>.");
+/// let prog = BrainfuckProgram::new(code.to_string()).unwrap();
+/// ```
+///
+/// Unbalanced brackets fail to compile
+/// ```compile_fail
+/// use interprete_rs::brainfuck::brainfuck;
+/// let code = brainfuck!("++[->+<");
+/// ```
+/// ```compile_fail
+/// use interprete_rs::brainfuck::brainfuck;
+/// let code = brainfuck!("+]");
+/// ```
+pub use interprete_rs_macros::brainfuck;
+
 pub const VALID_CHARS: [char; 8] = ['<', '>', '+', '-', '.', ',', '[', ']'];
+/// Number of cells the tape starts with, and the fixed size of a tape that isn't dynamic
+pub const TAPE_LEN: usize = 30000;
+/// Length of the encoding produced by `BrainfuckProgram::snapshot` for a fixed tape, three `u64`s
+/// and the memory
+const SNAPSHOT_LEN: usize = 24 + TAPE_LEN;
+/// Characters that are kept in the code for debugging but don't affect execution. `#` is a
+/// breakpoint that calls the callback set via `BrainfuckProgram::set_breakpoint`
+pub const DEBUG_CHARS: [char; 1] = ['#'];
+
+/// Number of times each instruction was executed, useful for measuring the effect of the
+/// optimizations
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Profile {
+    pub left: u64,
+    pub right: u64,
+    pub inc: u64,
+    pub dec: u64,
+    pub output: u64,
+    pub input: u64,
+    pub loop_start: u64,
+    pub loop_end: u64,
+    /// Total number of steps, including breakpoints
+    pub total: u64,
+}
+
+impl Profile {
+    fn record(&mut self, instr: u8) {
+        match instr {
+            b'<' => self.left += 1,
+            b'>' => self.right += 1,
+            b'+' => self.inc += 1,
+            b'-' => self.dec += 1,
+            b'.' => self.output += 1,
+            b',' => self.input += 1,
+            b'[' => self.loop_start += 1,
+            b']' => self.loop_end += 1,
+            _ => (),
+        }
+
+        self.total += 1;
+    }
+}
+
+/// How the tape behaves when the data pointer moves past its end
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct TapeOptions {
+    /// Grow the tape (doubling its size) instead of returning an error. It still starts with
+    /// `TAPE_LEN` cells
+    pub dynamic: bool,
+    /// Maximum number of cells a dynamic tape can grow to, `None` for no limit
+    pub max_cells: Option<usize>,
+}
+
+/// What `+` and `-` do when the cell is already at 255 or 0
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CellArithmetic {
+    /// Wrap around, so `+` at 255 gives 0
+    #[default]
+    Wrapping,
+    /// Stay at the limit, so `+` at 255 gives 255 and `-` at 0 gives 0
+    Saturating,
+}
+
+/// Callback run on each `#` breakpoint, receives the memory block and the data pointer
+pub type Breakpoint = Box<dyn FnMut(&[u8], usize)>;
+
+/// The state of a program right after executing a single instruction via
+/// `BrainfuckProgram::step`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StepInfo {
+    /// The instruction that was executed
+    pub instr: char,
+    /// Location of the next instruction to execute
+    pub ip: usize,
+    pub dp: usize,
+    /// Value of the memory cell the data pointer is on
+    pub cell: u8,
+}
 
 /// Struct representing a Brainfuck program, storing the code, memory, pointers, bracket pairs, and
 /// a reader and writer. `BrainfuckProgram::new(input:String)` is the standard way to create a new
@@ -18,13 +119,20 @@ where
     R: Read,
     W: Write,
 {
-    code: Vec<char>,
-    mem: [u8; 30000],
+    code: Vec<u8>,
+    mem: Vec<u8>,
     ip: usize,
     dp: usize,
     loops: HashMap<usize, usize>, // Matching pairs of brackets
     writer: W,
     reader: R,
+    breakpoint: Option<Breakpoint>,
+    profile: Profile,
+    tape: TapeOptions,
+    arithmetic: CellArithmetic,
+    /// Whether the code went through `optimizations::math_reduction`, which is only correct with
+    /// wrapping arithmetic
+    math_reduced: bool,
 }
 
 impl<R, W> BrainfuckProgram<R, W>
@@ -34,114 +142,487 @@ where
 {
     /// Create a new BrainfuckProgram, specifying both the reader and the writer.
     pub fn new_full(input: String, writer: W, reader: R) -> InterpreteResult<Self> {
-        let mut code = Vec::new();
-        let mut stack = Vec::new();
-        let mut loops = HashMap::new();
-
-        for (i, c) in input
-            .chars()
-            .filter(|c| c.is_ascii() && VALID_CHARS.contains(c))
-            .enumerate()
-        {
-            code.push(c);
+        Self::new_from_bytes_full(input.as_bytes(), writer, reader)
+    }
 
-            if c == '[' {
-                stack.push(i);
-            } else if c == ']' {
-                let matching = stack
-                    .pop()
-                    .ok_or("Detected mismatched brackets, too many ]")?;
-                loops.insert(matching, i);
-                loops.insert(i, matching);
+    /// Create a new BrainfuckProgram from the raw bytes of the code, specifying both the reader
+    /// and the writer. This avoids allocating a `String` (and validating it as UTF-8) since only
+    /// the ASCII instructions matter, any other byte is ignored
+    pub fn new_from_bytes_full(input: &[u8], writer: W, reader: R) -> InterpreteResult<Self> {
+        Self::from_code(filter_bytes(input), writer, reader)
+    }
+
+    /// Create a new BrainfuckProgram, specifying both the reader and the writer. Unlike
+    /// `new_full` any character other than an instruction, a breakpoint, or whitespace is an
+    /// error instead of being ignored, which catches typos
+    pub fn new_strict_full(input: String, writer: W, reader: R) -> InterpreteResult<Self> {
+        Self::from_code(to_bytes(filter_code(&input, true)?), writer, reader)
+    }
+
+    /// Create a new BrainfuckProgram that runs the code after it has been through the
+    /// optimization passes, see `optimizations::optimize`. The macro-instructions they
+    /// introduce are executed directly by the interpreter
+    pub fn new_optimized_full(input: String, writer: W, reader: R) -> InterpreteResult<Self> {
+        Self::new_optimized_with_arithmetic_full(input, writer, reader, CellArithmetic::Wrapping)
+    }
+
+    /// Same as `new_optimized_full`, but for the given cell arithmetic. Only the passes that are
+    /// correct for it are run, see `optimizations::optimize_for`
+    pub fn new_optimized_with_arithmetic_full(
+        input: String,
+        writer: W,
+        reader: R,
+        arithmetic: CellArithmetic,
+    ) -> InterpreteResult<Self> {
+        let code = filter_code(&input, false)?;
+        // Checked before optimizing so the passes never see mismatched brackets
+        match_brackets(&code)?;
+
+        let mut prog = Self::from_code(to_bytes(optimize_for(code, arithmetic)), writer, reader)?;
+        prog.arithmetic = arithmetic;
+        prog.math_reduced = arithmetic == CellArithmetic::Wrapping;
+
+        Ok(prog)
+    }
+
+    fn from_code(code: Vec<u8>, writer: W, reader: R) -> InterpreteResult<Self> {
+        let loops = match_brackets(&code)?;
+
+        Ok(Self {
+            code,
+            loops,
+            writer,
+            reader,
+            mem: vec![0; TAPE_LEN],
+            ip: 0,
+            dp: 0,
+            breakpoint: None,
+            profile: Profile::default(),
+            tape: TapeOptions::default(),
+            arithmetic: CellArithmetic::default(),
+            math_reduced: false,
+        })
+    }
+
+    /// Run the program to completion, consuming it and returning the final memory block
+    pub fn interpret_naive(mut self) -> InterpreteResult<Vec<u8>> {
+        self.interpret()?;
+
+        Ok(self.mem)
+    }
+
+    /// Run the program to completion without consuming it. Call `reset` before running it
+    /// again, since the pointers are left at their final positions
+    pub fn interpret(&mut self) -> InterpreteResult<&[u8]> {
+        while self.step()?.is_some() {}
+
+        Ok(&self.mem)
+    }
+
+    /// Run up to `n` instructions, returning whether the program has finished. This lets the
+    /// caller run a long program in chunks, e.g. to show progress or cancel it in between
+    pub fn interpret_steps(&mut self, n: usize) -> InterpreteResult<bool> {
+        for _ in 0..n {
+            if self.step()?.is_none() {
+                return Ok(true);
             }
         }
 
-        if !stack.is_empty() {
-            Err("Detected mismatched brackets, too many [".into())
-        } else {
-            Ok(Self {
-                code,
-                loops,
-                writer,
-                reader,
-                mem: [0; 30000],
-                ip: 0,
-                dp: 0,
-            })
-        }
+        Ok(self.ip >= self.code.len())
     }
 
-    pub fn interpret_naive(mut self) -> InterpreteResult<[u8; 30000]> {
-        loop {
-            if self.ip >= self.code.len() {
-                // Reached end of selfram
-                break;
+    /// Execute exactly one instruction, returning the state of the program right after it.
+    /// Returns `None` once the end of the program has been reached
+    pub fn step(&mut self) -> InterpreteResult<Option<StepInfo>> {
+        if self.ip >= self.code.len() {
+            // Reached end of program
+            return Ok(None);
+        }
+
+        let instr = self.code[self.ip];
+        self.profile.record(instr);
+
+        match instr {
+            b'<' => {
+                self.dp = self
+                    .dp
+                    .checked_sub(1)
+                    .ok_or("Data pointer is 0, cannot decrement")?
             }
-            match self.code[self.ip] {
-                '<' => {
-                    self.dp = self
-                        .dp
-                        .checked_sub(1)
-                        .ok_or("Data pointer is 0, cannot decrement")?
+            b'>' => self.move_right(1)?,
+            b'+' => self.add_to_cell(1),
+            b'-' => self.sub_from_cell(1),
+            b'.' => self.writer.write_all(&self.mem[self.dp..self.dp + 1])?,
+            b',' => {
+                let mut buf = [0u8];
+                let cnt = self.reader.read(&mut buf)?;
+
+                if cnt != 1 {
+                    return Err(format!(
+                        "Read {} bytes from configured reader, expected exactly 1",
+                        cnt
+                    )
+                    .into());
                 }
-                '>' => {
-                    if self.dp < 29999 {
-                        self.dp += 1
-                    } else {
-                        return Err("Data pointer is 29999, cannot increment".into());
-                    }
+
+                self.mem[self.dp] = buf[0];
+            }
+            b'[' => {
+                if self.mem[self.dp] == 0 {
+                    self.ip = *self
+                        .loops
+                        .get(&self.ip)
+                        .ok_or("Unable to get matching bracket")?;
                 }
-                '+' => self.mem[self.dp] = self.mem[self.dp].wrapping_add(1),
-                '-' => self.mem[self.dp] = self.mem[self.dp].wrapping_sub(1),
-                '.' => {
-                    let cnt = self.writer.write(&self.mem[self.dp..self.dp + 1])?;
-
-                    if cnt != 1 {
-                        return Err(format!(
-                            "Read {} bytes from configured reader, expected exactly 1",
-                            cnt
-                        )
-                        .into());
-                    }
+            }
+            b']' => {
+                if self.mem[self.dp] != 0 {
+                    self.ip = *self
+                        .loops
+                        .get(&self.ip)
+                        .ok_or("Unable to get matching bracket")?;
                 }
-                ',' => {
-                    let mut buf = [0u8];
-                    let cnt = self.reader.read(&mut buf)?;
-
-                    if cnt != 1 {
-                        return Err(format!(
-                            "Read {} bytes from configured reader, expected exactly 1",
-                            cnt
-                        )
-                        .into());
-                    }
-
-                    self.mem[self.dp] = buf[0];
+            }
+            b'#' => {
+                if let Some(breakpoint) = self.breakpoint.as_mut() {
+                    breakpoint(&self.mem, self.dp);
                 }
-                '[' => {
-                    if self.mem[self.dp] == 0 {
-                        self.ip = *self
-                            .loops
-                            .get(&self.ip)
-                            .ok_or("Unable to get matching bracket")?;
-                    }
+            }
+            b'(' => {
+                let end = self.code[self.ip..]
+                    .iter()
+                    .position(|&c| c == b')')
+                    .ok_or("Unterminated macro-instruction")?
+                    + self.ip;
+                let body: String = self.code[self.ip + 1..end]
+                    .iter()
+                    .map(|&c| c as char)
+                    .collect();
+
+                self.execute_macro(&body)?;
+                self.ip = end;
+            }
+            c => return Err(format!("Unexpected char in code: {}", c as char).into()),
+        };
+
+        self.ip += 1;
+
+        if self.ip >= self.code.len() {
+            // Make sure all output has been written by the time the program finishes
+            self.writer.flush()?;
+        }
+
+        Ok(Some(StepInfo {
+            instr: instr as char,
+            ip: self.ip,
+            dp: self.dp,
+            cell: self.mem[self.dp],
+        }))
+    }
+
+    /// Execute a macro-instruction (as described in OPTIMIZATIONS.md), given the text between
+    /// the parentheses
+    fn execute_macro(&mut self, body: &str) -> InterpreteResult<()> {
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| format!("Invalid number in macro-instruction: ({})", body))
+        };
+
+        if let Some((cnt, op)) = body.split_once('*') {
+            let cnt = parse(cnt)?;
+
+            match op {
+                "p" => self.add_to_cell(cnt),
+                "m" => self.sub_from_cell(cnt),
+                "f" => self.move_right(cnt)?,
+                "b" => {
+                    self.dp = self.dp.checked_sub(cnt).ok_or(format!(
+                        "Data pointer is {}, cannot decrement by {}",
+                        self.dp, cnt
+                    ))?
                 }
-                ']' => {
-                    if self.mem[self.dp] != 0 {
-                        self.ip = *self
-                            .loops
-                            .get(&self.ip)
-                            .ok_or("Unable to get matching bracket")?;
-                    }
+                _ => return Err(format!("Unable to repeat opcode: {}", op).into()),
+            }
+        } else if let Some((max_left, max_right)) = body.split_once(';') {
+            let (max_left, max_right) = (parse(max_left)?, parse(max_right)?);
+
+            if self.dp < max_left || self.dp + max_right > self.max_dp() {
+                return Err(format!(
+                    "Data pointer is {}, moving {} left or {} right is out of bounds",
+                    self.dp, max_left, max_right
+                )
+                .into());
+            }
+        } else {
+            return Err(format!("Unknown macro-instruction: ({})", body).into());
+        }
+
+        Ok(())
+    }
+
+    /// Add `cnt` to the current cell, as if by `cnt` consecutive `+`
+    fn add_to_cell(&mut self, cnt: usize) {
+        let cell = &mut self.mem[self.dp];
+
+        *cell = match self.arithmetic {
+            CellArithmetic::Wrapping => cell.wrapping_add((cnt % 256) as u8),
+            CellArithmetic::Saturating => cell.saturating_add(cnt.min(255) as u8),
+        }
+    }
+
+    /// Subtract `cnt` from the current cell, as if by `cnt` consecutive `-`
+    fn sub_from_cell(&mut self, cnt: usize) {
+        let cell = &mut self.mem[self.dp];
+
+        *cell = match self.arithmetic {
+            CellArithmetic::Wrapping => cell.wrapping_sub((cnt % 256) as u8),
+            CellArithmetic::Saturating => cell.saturating_sub(cnt.min(255) as u8),
+        }
+    }
+
+    /// Largest index the data pointer can reach, taking into account how far the tape can grow
+    fn max_dp(&self) -> usize {
+        if self.tape.dynamic {
+            self.tape
+                .max_cells
+                .map_or(usize::MAX, |max| max.max(self.mem.len()) - 1)
+        } else {
+            self.mem.len() - 1
+        }
+    }
+
+    /// Move the data pointer `cnt` cells to the right, growing a dynamic tape if it moves past the
+    /// end
+    fn move_right(&mut self, cnt: usize) -> InterpreteResult<()> {
+        let max_dp = self.max_dp();
+
+        match self.dp.checked_add(cnt).filter(|&dp| dp <= max_dp) {
+            Some(dp) => self.dp = dp,
+            None if self.tape.dynamic && max_dp < usize::MAX => {
+                return Err(format!(
+                    "Data pointer is {}, cannot increment past the tape limit of {} cells",
+                    self.dp,
+                    max_dp + 1
+                )
+                .into())
+            }
+            None => return Err(format!("Data pointer is {}, cannot increment", self.dp).into()),
+        }
+
+        if self.dp >= self.mem.len() {
+            let len = (self.mem.len() * 2)
+                .max(self.dp + 1)
+                .min(max_dp.saturating_add(1));
+            self.mem.resize(len, 0);
+        }
+
+        Ok(())
+    }
+
+    /// Set how the tape behaves when the data pointer moves past its end, see `TapeOptions`
+    pub fn set_tape_options(&mut self, tape: TapeOptions) {
+        self.tape = tape;
+    }
+
+    /// Set what `+` and `-` do at the limits of a cell, see `CellArithmetic`. Returns an Err when
+    /// switching to saturating arithmetic on a program created with `new_optimized_full`, since
+    /// its code was reduced assuming wrapping arithmetic. Use
+    /// `new_optimized_with_arithmetic_full` for an optimized program with saturating cells
+    pub fn set_cell_arithmetic(&mut self, arithmetic: CellArithmetic) -> InterpreteResult<()> {
+        if self.math_reduced && arithmetic != CellArithmetic::Wrapping {
+            return Err(
+                "The code was optimized for wrapping arithmetic, saturating arithmetic would \
+                 change its behavior"
+                    .into(),
+            );
+        }
+
+        self.arithmetic = arithmetic;
+
+        Ok(())
+    }
+
+    /// Reset the memory, pointers, and profile so the program can be run again. The code and bracket
+    /// pairs are kept as-is, so nothing needs to be reparsed
+    pub fn reset(&mut self) {
+        self.mem = vec![0; TAPE_LEN];
+        self.ip = 0;
+        self.dp = 0;
+        self.profile = Profile::default();
+    }
+
+    /// Save the memory and pointers so execution can be resumed later with `restore`. The
+    /// encoding is the code length, `ip`, and `dp` as little-endian `u64`s followed by the memory
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(24 + self.mem.len());
+
+        for n in [self.code.len(), self.ip, self.dp] {
+            res.extend_from_slice(&(n as u64).to_le_bytes());
+        }
+        res.extend_from_slice(&self.mem);
+
+        res
+    }
+
+    /// Restore the memory and pointers from a `snapshot`. The snapshot must have been taken from
+    /// a program with the same code, which is checked by comparing the code length. A dynamic tape
+    /// takes on the size of the tape in the snapshot, as long as it's within `max_cells`
+    pub fn restore(&mut self, snapshot: &[u8]) -> InterpreteResult<()> {
+        let valid_len = if self.tape.dynamic {
+            snapshot.len() >= SNAPSHOT_LEN && snapshot.len() - 24 <= self.max_dp().saturating_add(1)
+        } else {
+            snapshot.len() == SNAPSHOT_LEN
+        };
+
+        if !valid_len {
+            return Err(format!(
+                "Snapshot has length {}, expected {}",
+                snapshot.len(),
+                SNAPSHOT_LEN
+            )
+            .into());
+        }
+
+        let read_u64 = |i: usize| {
+            u64::from_le_bytes(snapshot[i * 8..(i + 1) * 8].try_into().unwrap()) as usize
+        };
+        let (code_len, ip, dp) = (read_u64(0), read_u64(1), read_u64(2));
+
+        if code_len != self.code.len() {
+            return Err(format!(
+                "Snapshot is for code of length {}, but this program's code has length {}",
+                code_len,
+                self.code.len()
+            )
+            .into());
+        }
+        if ip > self.code.len() || dp >= snapshot.len() - 24 {
+            return Err(format!("Snapshot has invalid pointers, ip: {}, dp: {}", ip, dp).into());
+        }
+
+        self.ip = ip;
+        self.dp = dp;
+        self.mem = snapshot[24..].to_vec();
+
+        Ok(())
+    }
+
+    /// Counts of the instructions executed since the program was created or last reset
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Formats `len` cells of the tape starting at `start` as space-separated values, with the cell
+    /// under the data pointer in brackets, e.g. `1 [2] 255`. The window is cut off at the end of
+    /// the tape
+    pub fn dump_cells(&self, start: usize, len: usize) -> String {
+        self.dump_cells_with(start, len, |c| c.to_string())
+    }
+
+    /// Same as `dump_cells`, but each cell is shown as an `i8` so e.g. `255` is shown as `-1`.
+    /// Useful since wrapping arithmetic is often used to represent small negative numbers
+    pub fn dump_cells_signed(&self, start: usize, len: usize) -> String {
+        self.dump_cells_with(start, len, |c| (c as i8).to_string())
+    }
+
+    fn dump_cells_with(&self, start: usize, len: usize, fmt: fn(u8) -> String) -> String {
+        let end = start.saturating_add(len).min(self.mem.len());
+
+        (start.min(end)..end)
+            .map(|i| {
+                if i == self.dp {
+                    format!("[{}]", fmt(self.mem[i]))
+                } else {
+                    fmt(self.mem[i])
                 }
-                c => return Err(format!("Unexpected char in code: {}", c).into()),
-            };
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Set the callback to run whenever a `#` is executed. It receives the memory block and the
+    /// data pointer, and otherwise has no effect on execution
+    pub fn set_breakpoint<F>(&mut self, breakpoint: F)
+    where
+        F: FnMut(&[u8], usize) + 'static,
+    {
+        self.breakpoint = Some(Box::new(breakpoint));
+    }
 
-            self.ip += 1;
+    /// Replace the reader, e.g. to rerun the program with different input after a `reset`
+    pub fn set_reader(&mut self, reader: R) {
+        self.reader = reader;
+    }
+}
+
+/// Pairs up the brackets in the code, mapping the position of each bracket to the position of
+/// its match (so every pair shows up twice, once in each direction). Returns an Err if the
+/// brackets are unbalanced. Works on both chars and the bytes `BrainfuckProgram` stores its code
+/// as
+///
+/// # Examples
+/// ```
+/// use interprete_rs::brainfuck::match_brackets;
+///
+/// let code: Vec<char> = "+[->[-]<]".chars().collect();
+/// let loops = match_brackets(&code).unwrap();
+/// assert_eq!(loops[&1], 8);
+/// assert_eq!(loops[&6], 4);
+/// ```
+pub fn match_brackets<T: Copy + Into<char>>(code: &[T]) -> InterpreteResult<HashMap<usize, usize>> {
+    let mut stack = Vec::new();
+    let mut loops = HashMap::new();
+
+    for (i, &c) in code.iter().enumerate() {
+        match c.into() {
+            '[' => stack.push(i),
+            ']' => {
+                let matching = stack
+                    .pop()
+                    .ok_or("Detected mismatched brackets, too many ]")?;
+                loops.insert(matching, i);
+                loops.insert(i, matching);
+            }
+            _ => (),
         }
+    }
 
-        Ok(self.mem)
+    if !stack.is_empty() {
+        Err("Detected mismatched brackets, too many [".into())
+    } else {
+        Ok(loops)
+    }
+}
+
+/// Keeps only the characters of the input that mean something to the interpreter. In strict mode
+/// any other character besides whitespace is an error giving its position
+fn filter_code(input: &str, strict: bool) -> InterpreteResult<Vec<char>> {
+    let mut code = Vec::new();
+
+    for (i, c) in input.chars().enumerate() {
+        if c.is_ascii() && (VALID_CHARS.contains(&c) || DEBUG_CHARS.contains(&c)) {
+            code.push(c);
+        } else if strict && !c.is_whitespace() {
+            return Err(format!("Unexpected character '{}' at position {}", c, i).into());
+        }
     }
+
+    Ok(code)
+}
+
+/// Same as `filter_code` in non-strict mode, but working on raw bytes. Multi-byte UTF-8 sequences
+/// never contain ASCII bytes, so they're dropped like any other unknown character
+fn filter_bytes(input: &[u8]) -> Vec<u8> {
+    input
+        .iter()
+        .copied()
+        .filter(|&b| VALID_CHARS.contains(&(b as char)) || DEBUG_CHARS.contains(&(b as char)))
+        .collect()
+}
+
+/// Converts filtered code to bytes, every instruction (including the macro-instructions) is ASCII
+fn to_bytes(code: Vec<char>) -> Vec<u8> {
+    code.into_iter().map(|c| c as u8).collect()
 }
 
 impl<R> BrainfuckProgram<R, Stdout>
@@ -170,11 +651,17 @@ impl BrainfuckProgram<Stdin, Stdout> {
     pub fn new(input: String) -> InterpreteResult<Self> {
         BrainfuckProgram::new_full(input, stdout(), stdin())
     }
+
+    /// Create a new BrainfuckProgram from the raw bytes of the code, with stdin and stdout as the
+    /// reader and writer. See `new_from_bytes_full`
+    pub fn new_from_bytes(input: &[u8]) -> InterpreteResult<Self> {
+        BrainfuckProgram::new_from_bytes_full(input, stdout(), stdin())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{array, io::Cursor, iter::repeat};
+    use std::{cell::RefCell, io::Cursor, rc::Rc};
 
     use crate::{arr, error::InterpreTestResult};
 
@@ -203,6 +690,113 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn match_brackets_test() -> InterpreTestResult {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+
+        assert_eq!(
+            match_brackets(&chars("[[]][")).unwrap_err().to_string(),
+            "Detected mismatched brackets, too many ["
+        );
+        assert_eq!(
+            match_brackets(&chars("[]]")).unwrap_err().to_string(),
+            "Detected mismatched brackets, too many ]"
+        );
+        assert_eq!(
+            match_brackets(&chars("][")).unwrap_err().to_string(),
+            "Detected mismatched brackets, too many ]"
+        );
+        assert!(match_brackets(&chars("+-.,"))?.is_empty());
+
+        let expected = HashMap::from([(0, 7), (7, 0), (2, 5), (5, 2), (3, 4), (4, 3)]);
+        assert_eq!(match_brackets(&chars("[-[[]]+]"))?, expected);
+        assert_eq!(match_brackets("[-[[]]+]".as_bytes())?, expected);
+
+        // The optimizations mustn't hide an unmatched bracket
+        for input in ["[+", "[[]"] {
+            assert!(BrainfuckProgram::new_optimized_full(
+                input.to_string(),
+                Vec::new(),
+                Cursor::new(vec![])
+            )
+            .is_err());
+            assert!(transpile::transpile_to_rust(input).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn brainfuck_macro() -> InterpreTestResult {
+        let code = brainfuck!("++[->+++<]>.");
+        let mut output = Vec::new();
+
+        BrainfuckProgram::new_full(code.to_string(), &mut output, Cursor::new(vec![]))?
+            .interpret_naive()?;
+
+        assert_eq!(code, "++[->+++<]>.");
+        assert_eq!(output, vec![6]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_bytes() -> InterpreTestResult {
+        let input = "++++++[>++++++++<-]>+. é[-]#";
+
+        let from_str = BrainfuckProgram::new_full(input.to_string(), vec![], Cursor::new(vec![]))?;
+        let from_bytes =
+            BrainfuckProgram::new_from_bytes_full(input.as_bytes(), vec![], Cursor::new(vec![]))?;
+
+        assert_eq!(from_str.code, from_bytes.code);
+        assert_eq!(from_str.loops, from_bytes.loops);
+
+        let (mut out_str, mut out_bytes) = (Vec::new(), Vec::new());
+        let mem_str =
+            BrainfuckProgram::new_full(input.to_string(), &mut out_str, Cursor::new(vec![]))?
+                .interpret_naive()?;
+        let mem_bytes = BrainfuckProgram::new_from_bytes_full(
+            input.as_bytes(),
+            &mut out_bytes,
+            Cursor::new(vec![]),
+        )?
+        .interpret_naive()?;
+
+        assert_eq!(mem_str, mem_bytes);
+        assert_eq!(out_str, b"1");
+        assert_eq!(out_str, out_bytes);
+
+        // Bytes that aren't valid UTF-8 are ignored like any other non-instruction
+        let prog = BrainfuckProgram::new_from_bytes_full(
+            &[b'+', 0xff, b'-'],
+            vec![],
+            Cursor::new(vec![]),
+        )?;
+        assert_eq!(prog.code, b"+-");
+
+        Ok(())
+    }
+
+    #[test]
+    fn strict_mode() -> InterpreTestResult {
+        let input = "++ hello ++";
+
+        let prog = BrainfuckProgram::new_full(input.to_string(), vec![], Cursor::new(vec![]))?;
+        assert_eq!(prog.code, vec![b'+'; 4]);
+
+        let err = BrainfuckProgram::new_strict_full(input.to_string(), vec![], Cursor::new(vec![]))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Unexpected character 'h' at position 3");
+
+        // Whitespace and breakpoints are still fine
+        let prog =
+            BrainfuckProgram::new_strict_full("+ #\n-".to_string(), vec![], Cursor::new(vec![]))?;
+        assert_eq!(prog.code, b"+#-");
+
+        Ok(())
+    }
+
     #[should_panic]
     #[test]
     fn mismatched_brackets() {
@@ -413,4 +1007,408 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn reset() -> InterpreTestResult {
+        // Reads a byte n, prints every number from n down to 1, then sets the next cell
+        let input = String::from(",[.-]>+");
+
+        let mut stdout_buf = Vec::new();
+        let writer = Cursor::new(&mut stdout_buf);
+
+        let mut prog = BrainfuckProgram::new_full(input, writer, Cursor::new(vec![3]))?;
+        let loops = prog.loops.clone();
+
+        assert_eq!(*prog.interpret()?, arr!([0; 30000], (0), (1)));
+
+        prog.reset();
+        assert_eq!((prog.ip, prog.dp), (0, 0));
+        assert_eq!(prog.mem, [0; 30000]);
+
+        prog.set_reader(Cursor::new(vec![2]));
+        assert_eq!(*prog.interpret()?, arr!([0; 30000], (0), (1)));
+        assert_eq!(prog.loops, loops);
+
+        drop(prog);
+        assert_eq!(stdout_buf, vec![3, 2, 1, 2, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn interpret_steps() -> InterpreTestResult {
+        let code = String::from("++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>+.");
+
+        let exp = BrainfuckProgram::new_with_writer(code.clone(), Vec::new())?.interpret_naive()?;
+
+        let mut prog = BrainfuckProgram::new_with_writer(code, Vec::new())?;
+        let mut chunks = 0;
+
+        while !prog.interpret_steps(100)? {
+            chunks += 1;
+        }
+
+        assert!(chunks > 1);
+        assert_eq!(prog.mem, exp);
+        assert!(prog.interpret_steps(100)?);
+
+        Ok(())
+    }
+
+    /// Writer that needs a retry for every other write and only makes its output visible once
+    /// it's flushed
+    struct TrickleWriter {
+        buf: Vec<u8>,
+        flushed: Rc<RefCell<Vec<u8>>>,
+        interrupt: bool,
+        fail_flush: bool,
+    }
+
+    impl Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+
+            if self.interrupt {
+                Err(std::io::ErrorKind::Interrupted.into())
+            } else {
+                self.buf.push(buf[0]);
+                Ok(1)
+            }
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            if self.fail_flush {
+                return Err(std::io::Error::other("flush failed"));
+            }
+
+            self.flushed.borrow_mut().append(&mut self.buf);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writer_flush() -> InterpreTestResult {
+        let flushed = Rc::new(RefCell::new(Vec::new()));
+        let writer = TrickleWriter {
+            buf: Vec::new(),
+            flushed: flushed.clone(),
+            interrupt: false,
+            fail_flush: false,
+        };
+
+        BrainfuckProgram::new_with_writer(String::from("+++.+.+."), writer)?.interpret_naive()?;
+        assert_eq!(*flushed.borrow(), vec![3, 4, 5]);
+
+        let writer = TrickleWriter {
+            buf: Vec::new(),
+            flushed: flushed.clone(),
+            interrupt: false,
+            fail_flush: true,
+        };
+        assert!(
+            BrainfuckProgram::new_with_writer(String::from("+."), writer)?
+                .interpret_naive()
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_restore() -> InterpreTestResult {
+        let code = String::from("++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>+.");
+
+        let mut prog = BrainfuckProgram::new_with_writer(code.clone(), Vec::new())?;
+        prog.interpret_steps(200)?;
+
+        let snapshot = prog.snapshot();
+        let exp = prog.interpret()?.to_vec();
+
+        // Restoring into a fresh program with the same code resumes from the same point
+        let mut restored = BrainfuckProgram::new_with_writer(code, Vec::new())?;
+        restored.restore(&snapshot)?;
+
+        assert_eq!(restored.interpret()?, exp);
+        assert_eq!(restored.writer, prog.writer);
+
+        let mut other = BrainfuckProgram::new_with_writer(String::from("+."), Vec::new())?;
+        assert!(other.restore(&snapshot).is_err());
+        assert!(other.restore(&snapshot[1..]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn dynamic_tape() -> InterpreTestResult {
+        let code = format!("{}+.", ">".repeat(100_000));
+        let new_prog = || BrainfuckProgram::new_full(code.clone(), Vec::new(), Cursor::new(vec![]));
+
+        // A fixed tape stops at the last cell
+        let err = new_prog()?.interpret_naive().err().unwrap();
+        assert_eq!(err.to_string(), "Data pointer is 29999, cannot increment");
+
+        let mut prog = new_prog()?;
+        prog.set_tape_options(TapeOptions {
+            dynamic: true,
+            max_cells: None,
+        });
+        prog.interpret()?;
+
+        assert_eq!(prog.dp, 100_000);
+        assert_eq!(prog.mem.len(), 120_000);
+        assert_eq!(prog.mem[100_000], 1);
+        assert_eq!(prog.writer, vec![1]);
+
+        // Restoring the grown tape into another dynamic program keeps its size
+        let snapshot = prog.snapshot();
+        let mut restored = new_prog()?;
+        assert!(restored.restore(&snapshot).is_err());
+        restored.set_tape_options(TapeOptions {
+            dynamic: true,
+            max_cells: None,
+        });
+        restored.restore(&snapshot)?;
+        assert_eq!(restored.mem, prog.mem);
+
+        // The tape never grows past the cap, even when doubling would take it further
+        let mut prog = new_prog()?;
+        prog.set_tape_options(TapeOptions {
+            dynamic: true,
+            max_cells: Some(50_000),
+        });
+        let err = prog.interpret().err().unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Data pointer is 49999, cannot increment past the tape limit of 50000 cells"
+        );
+        assert_eq!(prog.mem.len(), 50_000);
+
+        // Repeated moves from the optimizations grow the tape all at once
+        let mut prog =
+            BrainfuckProgram::new_optimized_full(code.clone(), Vec::new(), Cursor::new(vec![]))?;
+        prog.set_tape_options(TapeOptions {
+            dynamic: true,
+            max_cells: Some(100_001),
+        });
+        prog.interpret()?;
+        assert_eq!(prog.mem.len(), 100_001);
+        assert_eq!(prog.writer, vec![1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cell_arithmetic() -> InterpreTestResult {
+        let run = |code: String, arithmetic: CellArithmetic| -> InterpreteResult<u8> {
+            let mut prog = BrainfuckProgram::new_full(code, Vec::new(), Cursor::new(vec![]))?;
+            prog.set_cell_arithmetic(arithmetic)?;
+
+            Ok(prog.interpret()?[0])
+        };
+        let run_optimized = |code: String, arithmetic: CellArithmetic| -> InterpreteResult<u8> {
+            let mut prog = BrainfuckProgram::new_optimized_with_arithmetic_full(
+                code,
+                Vec::new(),
+                Cursor::new(vec![]),
+                arithmetic,
+            )?;
+
+            Ok(prog.interpret()?[0])
+        };
+
+        let up = |n| "+".repeat(n);
+
+        assert_eq!(run("-".to_string(), CellArithmetic::Wrapping)?, 255);
+        assert_eq!(run(up(256), CellArithmetic::Wrapping)?, 0);
+        assert_eq!(run(up(300), CellArithmetic::Wrapping)?, 44);
+
+        assert_eq!(run("-".to_string(), CellArithmetic::Saturating)?, 0);
+        assert_eq!(run(up(256), CellArithmetic::Saturating)?, 255);
+        assert_eq!(run(up(255) + "-", CellArithmetic::Saturating)?, 254);
+        assert_eq!(run("-+".to_string(), CellArithmetic::Saturating)?, 1);
+
+        // The optimized code agrees, with `-+` no longer reduced to nothing when saturating
+        for (code, wrapping, saturating) in [
+            ("-+".to_string(), 0, 1),
+            (up(300), 44, 255),
+            (up(255) + "+-", 255, 254),
+        ] {
+            assert_eq!(
+                run_optimized(code.clone(), CellArithmetic::Wrapping)?,
+                wrapping
+            );
+            assert_eq!(
+                run_optimized(code.clone(), CellArithmetic::Saturating)?,
+                saturating
+            );
+            assert_eq!(run(code.clone(), CellArithmetic::Wrapping)?, wrapping);
+            assert_eq!(run(code, CellArithmetic::Saturating)?, saturating);
+        }
+
+        let mut prog = BrainfuckProgram::new_optimized_full(
+            "-+".to_string(),
+            Vec::new(),
+            Cursor::new(vec![]),
+        )?;
+        assert!(prog
+            .set_cell_arithmetic(CellArithmetic::Saturating)
+            .is_err());
+        prog.set_cell_arithmetic(CellArithmetic::Wrapping)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn step() -> InterpreTestResult {
+        let mut prog = BrainfuckProgram::new(String::from("+>+"))?;
+
+        let exp = [
+            StepInfo {
+                instr: '+',
+                ip: 1,
+                dp: 0,
+                cell: 1,
+            },
+            StepInfo {
+                instr: '>',
+                ip: 2,
+                dp: 1,
+                cell: 0,
+            },
+            StepInfo {
+                instr: '+',
+                ip: 3,
+                dp: 1,
+                cell: 1,
+            },
+        ];
+
+        for info in exp {
+            assert_eq!(prog.step()?, Some(info));
+        }
+
+        assert_eq!(prog.step()?, None);
+        assert_eq!(prog.step()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn breakpoints() -> InterpreTestResult {
+        let input = String::from("++#>+++#<-#");
+
+        let snapshots = Rc::new(RefCell::new(Vec::new()));
+        let captured = Rc::clone(&snapshots);
+
+        let mut prog = BrainfuckProgram::new(input)?;
+        prog.set_breakpoint(move |mem, dp| captured.borrow_mut().push((mem[0..3].to_vec(), dp)));
+
+        let output = prog.interpret_naive()?;
+
+        assert_eq!(output, arr!([0; 30000], (1), (3)));
+        assert_eq!(
+            *snapshots.borrow(),
+            vec![(vec![2, 0, 0], 0), (vec![2, 3, 0], 1), (vec![1, 3, 0], 0)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn breakpoints_unset() -> InterpreTestResult {
+        let prog = BrainfuckProgram::new(String::from("#+#"))?;
+
+        assert_eq!(prog.code, b"#+#");
+        assert_eq!(prog.interpret_naive()?, arr!([0; 30000], (1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn profile() -> InterpreTestResult {
+        let mut prog = BrainfuckProgram::new(String::from("+++[-]>+<"))?;
+        prog.interpret()?;
+
+        let exp = Profile {
+            left: 1,
+            right: 1,
+            inc: 4,
+            dec: 3,
+            output: 0,
+            input: 0,
+            loop_start: 1,
+            loop_end: 3,
+            total: 13,
+        };
+
+        assert_eq!(*prog.profile(), exp);
+
+        prog.reset();
+        assert_eq!(*prog.profile(), Profile::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn dump_cells() -> InterpreTestResult {
+        let code = format!("+>++>->{}<<", "+".repeat(130));
+        let mut prog = BrainfuckProgram::new(code)?;
+        prog.interpret()?;
+
+        assert_eq!(prog.dump_cells(0, 5), "1 [2] 255 130 0");
+        assert_eq!(prog.dump_cells_signed(0, 5), "1 [2] -1 -126 0");
+        assert_eq!(prog.dump_cells(2, 2), "255 130");
+        assert_eq!(prog.dump_cells(29998, 10), "0 0");
+        assert_eq!(prog.dump_cells(30005, 10), "");
+
+        Ok(())
+    }
+
+    /// Run the program both naively and after the optimization passes, asserting that the two
+    /// produce the same memory and output (or both fail)
+    fn assert_optimization_preserves(input: &str, stdin: &[u8]) {
+        let run = |optimized: bool| {
+            let mut stdout_buf = Vec::new();
+            let writer = Cursor::new(&mut stdout_buf);
+            let reader = Cursor::new(stdin);
+
+            let prog = if optimized {
+                BrainfuckProgram::new_optimized_full(input.to_string(), writer, reader)
+            } else {
+                BrainfuckProgram::new_full(input.to_string(), writer, reader)
+            };
+
+            let mem = prog.and_then(|p| p.interpret_naive()).ok();
+
+            (mem, stdout_buf)
+        };
+
+        let (naive_mem, naive_out) = run(false);
+        let (opt_mem, opt_out) = run(true);
+
+        assert_eq!(naive_mem, opt_mem, "Memory differs for program {}", input);
+        assert_eq!(naive_out, opt_out, "Output differs for program {}", input);
+    }
+
+    #[test]
+    fn optimization_differential() {
+        // Multiply 4 by 12 into the second cell
+        assert_optimization_preserves("++++[>++++++++++++<-]>.", &[]);
+        // Clear loops, with some canceling math around them
+        assert_optimization_preserves("+++++[-]++-+-[+]+++>+-<-.", &[]);
+        // Cat program, echoes input until it reads a 0
+        assert_optimization_preserves(",[.,]", &[b'a', b'b', b'c', 0]);
+        // Hello World, with some back and forth pointer movement
+        assert_optimization_preserves(
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.\
+             ------.--------.>>+.>++.",
+            &[],
+        );
+        // Moving the pointer back and forth should still catch going out of bounds
+        assert_optimization_preserves("+<>+", &[]);
+        assert_optimization_preserves(">>><<<<>>>>+", &[]);
+        assert_optimization_preserves(&">".repeat(29999), &[]);
+        assert_optimization_preserves(&(">".repeat(29999) + "><"), &[]);
+    }
 }
@@ -2,6 +2,8 @@
 
 use std::cmp::Ordering;
 
+use super::CellArithmetic;
+
 //const MACROINSTRUCTION_CHARS: [char; 22] = [
 //    '(', ')', ';', '*', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'p', 'm', 'f', 'b', 'i',
 //    'o', '{', '}',
@@ -21,6 +23,20 @@ fn to_alt_opcode(c: char) -> char {
     }
 }
 
+fn from_alt_opcode(c: char) -> Option<char> {
+    match c {
+        'p' => Some('+'),
+        'm' => Some('-'),
+        'b' => Some('<'),
+        'f' => Some('>'),
+        'o' => Some('.'),
+        'i' => Some(','),
+        '{' => Some('['),
+        '}' => Some(']'),
+        _ => None,
+    }
+}
+
 /// Macro to allow simpler implementation of full pair reductions.
 ///
 /// # Examples
@@ -67,7 +83,8 @@ macro_rules! max_reduction {
 }
 
 /// Since we use wrapping arithmetic we can reduce any contiguous sequence of math operations which
-/// contain at least one `-` and one `+`
+/// contain at least one `-` and one `+`. With saturating arithmetic this isn't correct, e.g. `+-`
+/// on a cell at 255 leaves it at 254
 pub fn math_reduction(input: Vec<char>) -> Vec<char> {
     max_reduction!('+', '-', input)
 }
@@ -87,6 +104,10 @@ pub fn full_dp_reduction(input: Vec<char>) -> Vec<char> {
 /// 5 cells forward from the starting location, and 1 cell backward. That way the interpreter can
 /// check if the operation would have caused an overflow. Then afterwards we insert `<` or `>` as
 /// in a full reduction and allow the interpreter to handle them normally
+///
+/// Any other character ends a sequence and is copied as-is, which includes the bounds checks this
+/// emits and the repeat macro-instructions from `compress_seq`. A check is always followed by the
+/// moves it belongs to, so running this on its own output leaves it unchanged
 pub fn safe_dp_reduction(input: Vec<char>) -> Vec<char> {
     let mut res = Vec::new();
 
@@ -95,7 +116,11 @@ pub fn safe_dp_reduction(input: Vec<char>) -> Vec<char> {
     let mut max_right = 0u32;
 
     let handle_seq = |cd: i32, ml: u32, mr: u32, r: &mut Vec<char>| {
-        if ml == 0 && cd >= mr as i32 || mr == 0 && cd <= -(ml as i32) {
+        // The bounds check can only be skipped if the pointer only ever moved in one direction,
+        // since then the final position is the most extreme point reached and the interpreter
+        // checks it anyway. If the pointer strayed past its net displacement at any point (e.g.
+        // `><` or `>><<`) we need the check
+        if (ml == 0 && cd == mr as i32) || (mr == 0 && cd == -(ml as i32)) {
             // No need for bounds check since current position is the most extreme point reached
         } else {
             // Add macro-instruction to output
@@ -167,6 +192,190 @@ pub fn compress_seq(input: Vec<char>) -> Vec<char> {
     res
 }
 
+/// The inverse of `compress_seq`, expands each repeat macro-instruction back into the
+/// instructions it stands for, e.g. `(8*p)` becomes `++++++++`. Bounds checks like `(5;0)` are
+/// only hints for the interpreter so they're dropped, and anything else (including macros that
+/// aren't understood) is kept as-is. Useful for diffing optimized code against the original
+pub fn decompress(input: Vec<char>) -> Vec<char> {
+    let mut res = Vec::new();
+    let mut iter = input.into_iter();
+
+    while let Some(c) = iter.next() {
+        if c != '(' {
+            res.push(c);
+            continue;
+        }
+
+        let body: String = iter.by_ref().take_while(|&c| c != ')').collect();
+
+        if let Some((op, cnt)) = parse_repeat(&body) {
+            res.append(&mut vec![op; cnt]);
+            continue;
+        } else if !body.contains('*') && body.contains(';') {
+            continue;
+        }
+
+        res.push('(');
+        res.extend(body.chars());
+        res.push(')');
+    }
+
+    res
+}
+
+/// The instruction and count of a repeat macro-instruction, given the text between the
+/// parentheses, e.g. `8*p` is `('+', 8)`. Returns None for anything else
+fn parse_repeat(body: &str) -> Option<(char, usize)> {
+    let (cnt, op) = body.split_once('*')?;
+    let mut op_chars = op.chars();
+
+    match (
+        cnt.parse::<usize>(),
+        op_chars.next().and_then(from_alt_opcode),
+        op_chars.next(),
+    ) {
+        (Ok(cnt), Some(op), None) => Some((op, cnt)),
+        _ => None,
+    }
+}
+
+/// Merges consecutive repeats of the same instruction into one macro-instruction, whether they're
+/// written as macro-instructions or single instructions. So `(3*p)(2*p)` becomes `(5*p)` and
+/// `(3*p)+` becomes `(4*p)`. `compress_seq` never leaves these behind itself, but they can show
+/// up in hand-written IR or when passes interact. Anything else, including bounds checks, ends a
+/// run and is copied as-is, and so does a count that would overflow a `usize`
+pub fn merge_adjacent(input: Vec<char>) -> Vec<char> {
+    let compressable = ['+', '-', '<', '>'];
+
+    let mut res = Vec::new();
+
+    // The instruction being repeated and how many times so far
+    let mut curr: Option<(char, usize)> = None;
+
+    let handle_seq = |curr: Option<(char, usize)>, r: &mut Vec<char>| match curr {
+        Some((c, 1)) => r.push(c),
+        Some((c, cnt)) => {
+            r.append(&mut format!("({}*{})", cnt, to_alt_opcode(c)).chars().collect())
+        }
+        None => (),
+    };
+
+    let mut iter = input.into_iter();
+
+    while let Some(c) = iter.next() {
+        // The run this is part of if it can be merged, otherwise the text to copy
+        let (item, text) = if c == '(' {
+            let body: String = iter.by_ref().take_while(|&c| c != ')').collect();
+
+            (
+                parse_repeat(&body).filter(|(op, _)| compressable.contains(op)),
+                format!("({})", body),
+            )
+        } else if compressable.contains(&c) {
+            (Some((c, 1)), c.to_string())
+        } else {
+            (None, c.to_string())
+        };
+
+        match (curr, item) {
+            // A count that would overflow falls through to the next arm, starting a new run
+            (Some((c, cnt)), Some((op, n))) if c == op && cnt.checked_add(n).is_some() => {
+                curr = Some((c, cnt + n))
+            }
+            (_, Some(item)) => {
+                handle_seq(curr, &mut res);
+                curr = Some(item);
+            }
+            (_, None) => {
+                handle_seq(curr, &mut res);
+                curr = None;
+                res.extend(text.chars());
+            }
+        }
+    }
+
+    handle_seq(curr, &mut res);
+
+    res
+}
+
+/// A loop that starts when the current cell is known to be 0 is never entered, so it can be
+/// removed entirely. This is the case at the start of the program and right after a loop exits
+/// (e.g. the second loop in `[-][+]`). Output and breakpoints don't change the cell so they
+/// don't affect this
+pub fn dead_loop_elimination(input: Vec<char>) -> Vec<char> {
+    let mut res = Vec::new();
+
+    let mut known_zero = true;
+    let mut iter = input.into_iter();
+
+    while let Some(c) = iter.next() {
+        match c {
+            '[' if known_zero => {
+                // Skip to the matching bracket, accounting for nested loops
+                let mut depth = 1;
+                let mut skipped = vec![c];
+
+                for c in iter.by_ref() {
+                    match c {
+                        '[' => depth += 1,
+                        ']' => depth -= 1,
+                        _ => (),
+                    }
+
+                    if depth == 0 {
+                        break;
+                    }
+
+                    skipped.push(c);
+                }
+
+                // Without a matching bracket the rest is left alone so the mismatch is still
+                // reported when the code is run
+                if depth != 0 {
+                    res.append(&mut skipped);
+                }
+            }
+            ']' => {
+                known_zero = true;
+                res.push(c);
+            }
+            '.' | '#' => res.push(c),
+            _ => {
+                known_zero = false;
+                res.push(c);
+            }
+        }
+    }
+
+    res
+}
+
+/// Runs all of the always-correct passes in order. The result can be run directly with
+/// `BrainfuckProgram::new_optimized_full`
+pub fn optimize(input: Vec<char>) -> Vec<char> {
+    optimize_for(input, CellArithmetic::Wrapping)
+}
+
+/// Same as `optimize`, but only runs the passes that are correct for the given cell arithmetic.
+/// `math_reduction` is skipped for saturating arithmetic, a run of `+` or `-` is still compressed
+/// since `(n*p)` saturates the same way
+///
+/// Each pass is idempotent, and they're ordered so the result is a fixed point of the whole
+/// pipeline. `dead_loop_elimination` comes after `math_reduction` since reducing e.g. `+-[.]`
+/// leaves a loop at the start of the program, and `compress_seq` and `merge_adjacent` go last
+/// since the other passes only understand single instructions
+pub fn optimize_for(input: Vec<char>, arithmetic: CellArithmetic) -> Vec<char> {
+    let input = match arithmetic {
+        CellArithmetic::Wrapping => math_reduction(input),
+        CellArithmetic::Saturating => input,
+    };
+
+    merge_adjacent(compress_seq(dead_loop_elimination(safe_dp_reduction(
+        input,
+    ))))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -242,6 +451,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn safe_dp_reduction_return_to_start_test() {
+        // Any sequence that strays past its net displacement needs a bounds check, even when
+        // it returns exactly to the start
+        mk_test!(
+            ["><", "<>", ">><<", "<<>>", "><<", "<>>", ">>", "<<", "+"],
+            ["(0;1)", "(1;0)", "(0;2)", "(2;0)", "(1;1)<", "(1;1)>", ">>", "<<", "+"],
+            safe_dp_reduction
+        );
+    }
+
+    #[test]
+    fn safe_dp_reduction_rerun_test() {
+        // Earlier bounds checks and compressed moves pass through untouched
+        mk_test!(
+            ("(3;1)<", "(3;1)<")("[(2;2)<](0;4)>>>", "[(2;2)<](0;4)>>>")(
+                "(4;0)(3*b)+(0;1)",
+                "(4;0)(3*b)+(0;1)"
+            ),
+            safe_dp_reduction
+        );
+
+        for input in ["><><><<<<><><>><>", "[><>><<<<>]>><>>><", "><+<>>>-<<<<"] {
+            let once = safe_dp_reduction(input.chars().collect());
+
+            assert_eq!(safe_dp_reduction(once.clone()), once);
+            assert_eq!(
+                safe_dp_reduction(compress_seq(once.clone())),
+                compress_seq(once)
+            );
+        }
+    }
+
+    #[test]
+    fn dead_loop_elimination_test() {
+        mk_test!(
+            [
+                "[+++]+",
+                "[-][+]",
+                "+[-][+]",
+                "[[-]+[+]]>",
+                "+[+++]",
+                "+[-].[+]>[+]",
+                "++[>[-]<-][>+<-]",
+                "[+",
+                "[[]"
+            ],
+            [
+                "+",
+                "",
+                "+[-]",
+                ">",
+                "+[+++]",
+                "+[-].>[+]",
+                "++[>[-]<-]",
+                "[+",
+                "[[]"
+            ],
+            dead_loop_elimination
+        );
+    }
+
     #[test]
     fn compress_seq_test() {
         mk_test!(
@@ -260,4 +531,118 @@ mod tests {
             compress_seq
         );
     }
+
+    #[test]
+    fn decompress_test() {
+        let inputs = [
+            "++++++++<++++",
+            "<<<<<<<->>>>>>",
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.",
+            "#,[.,]#",
+            "",
+        ];
+
+        for input in inputs {
+            let code: Vec<char> = input.chars().collect();
+            assert_eq!(decompress(compress_seq(code.clone())), code);
+        }
+
+        // Bounds checks are dropped, unknown macros are left alone
+        mk_test!(
+            ("(13;2)(12*b)", "<<<<<<<<<<<<")("[(13;2)(5*b)]+(2*f)", "[<<<<<]+>>")(
+                "(h)(3*x)(2*p)",
+                "(h)(3*x)++"
+            ),
+            decompress
+        );
+    }
+
+    #[test]
+    fn merge_adjacent_test() {
+        mk_test!(
+            [
+                "(3*p)(2*p)",
+                "(2*f)(3*f)(4*f)",
+                "(3*p)+",
+                "-(2*m)-",
+                "<(2*b)",
+                "++",
+                "(3*p)(2*m)",
+                "+-",
+                "(3*p).(2*p)",
+                "(3*p)(1;0)(2*p)",
+                "[(2*b)<]>(h)(3*o)",
+                "(18446744073709551615*p)+"
+            ],
+            [
+                "(5*p)",
+                "(9*f)",
+                "(4*p)",
+                "(4*m)",
+                "(3*b)",
+                "(2*p)",
+                "(3*p)(2*m)",
+                "+-",
+                "(3*p).(2*p)",
+                "(3*p)(1;0)(2*p)",
+                "[(3*b)]>(h)(3*o)",
+                "(18446744073709551615*p)+"
+            ],
+            merge_adjacent
+        );
+
+        // Merging doesn't change what the code does
+        for input in ["(3*p)(2*p)>(2*f)+", "(3*p)+[-(2*m)]"] {
+            let code: Vec<char> = input.chars().collect();
+
+            assert_eq!(decompress(merge_adjacent(code.clone())), decompress(code));
+        }
+    }
+
+    #[test]
+    fn idempotence_test() {
+        let inputs = [
+            "",
+            "+-[.]",
+            "><",
+            ">><<<",
+            "<<>>>><",
+            "[-][+]",
+            "+[-][+][.]>",
+            "+++[->+<]>.",
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.",
+            "#,[.,]#",
+            "+>-<<+>>>-<-+",
+        ];
+        type Pass = fn(Vec<char>) -> Vec<char>;
+
+        let passes: [(&str, Pass); 9] = [
+            ("math_reduction", math_reduction),
+            ("full_dp_reduction", full_dp_reduction),
+            ("safe_dp_reduction", safe_dp_reduction),
+            ("compress_seq", compress_seq),
+            ("decompress", decompress),
+            ("merge_adjacent", merge_adjacent),
+            ("dead_loop_elimination", dead_loop_elimination),
+            ("optimize", optimize),
+            ("optimize_for saturating", |input| {
+                optimize_for(input, CellArithmetic::Saturating)
+            }),
+        ];
+
+        for input in inputs {
+            for (name, pass) in passes {
+                let once = pass(input.chars().collect());
+                let twice = pass(once.clone());
+
+                assert_eq!(
+                    twice.iter().collect::<String>(),
+                    once.iter().collect::<String>(),
+                    "{} isn't idempotent on {}",
+                    name,
+                    input
+                );
+            }
+        }
+    }
 }
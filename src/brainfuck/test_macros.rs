@@ -66,6 +66,29 @@ macro_rules! arr {
             arr
         }
     };
+    ( [$default:expr; $size:literal]; $( $iter:expr ),* ) => {
+        match $crate::try_arr!([$default; $size]; $( $iter ),*) {
+            Ok(arr) => arr,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+/// The iterator form of `arr`, but returning an `Err` instead of panicking when the iterators
+/// produce more elements than fit in the array
+///
+/// # Examples
+/// ```
+/// use interprete_rs::try_arr;
+///
+/// let arr: Result<[i32; 5], _> = try_arr!([0; 5]; (1..3), [7]);
+/// assert_eq!(arr, Ok([1, 2, 7, 0, 0]));
+///
+/// let arr: Result<[i32; 3], _> = try_arr!([0; 3]; (1..3), [7, 8]);
+/// assert_eq!(arr, Err("Specified size not large enough to hold all data"));
+/// ```
+#[macro_export]
+macro_rules! try_arr {
     ( [$default:expr; $size:literal]; $( $iter:expr ),* ) => {
         {
             let mut sum = 0;
@@ -80,18 +103,18 @@ macro_rules! arr {
             )*
 
             if sum > $size {
-                panic!("Specified size not large enough to hold all data");
-            }
+                Err("Specified size not large enough to hold all data")
+            } else {
+                let mut arr = [$default; $size];
 
-            let mut arr = [$default; $size];
+                for (i, v) in vec.into_iter().enumerate() {
+                    arr[i] = v;
+                }
 
-            for (i, v) in vec.into_iter().enumerate() {
-                arr[i] = v;
+                Ok(arr)
             }
-
-            arr
         }
-    }
+    };
 }
 
 // Helper macros below
@@ -124,6 +147,7 @@ macro_rules! arr {
 /// This is an attempt at a nicer-looking `arr` macro that uses recursion. Macro recursion is not
 /// optimized, so this may increase compile time vs. the other macro. This is specifically set up
 /// to support literals and ranges. E.g. `arr_tt!([default; cnt], 1, (4; 3), 5)`.
+#[allow(unused_macros)]
 macro_rules! arr_tt {
     () => {};
     ( [ $default:expr; $cnt:literal ], $( $tail:tt )* ) => {
@@ -145,6 +169,7 @@ macro_rules! arr_tt {
     };
 }
 
+#[allow(unused_macros)]
 macro_rules! add_one {
     ( [$vec: ident, $sum:ident], $elem:expr) => {
         $sum += 1;
@@ -152,14 +177,16 @@ macro_rules! add_one {
     };
 }
 
+#[allow(unused_macros)]
 macro_rules! add_block {
     ( [$vec: ident, $sum:ident], $elem:expr, $cnt:expr) => {
-        for i in 0..$cnt {
+        for _ in 0..$cnt {
             add_one!( [$vec, $sum], $elem );
         }
     };
 }
 
+#[allow(unused_macros)]
 macro_rules! arr_helper {
     ( [$vec:ident, $sum:ident] => ($elem:expr; $cnt:expr)) => {
         $(add_block!([$vec, $sum], $elem, $cnt);)?
@@ -199,9 +226,20 @@ macro_rules! arr_helper {
 /// assert_eq!(map.get(&4), Some(&3));
 /// assert_eq!(map.get(&5), Some(&2));
 /// assert_eq!(map.get(&3), None);
+///
+/// // A map can also be collected from an iterator of pairs
+/// let squares = map!(from (0..3).map(|i| (i, i * i)));
+///
+/// assert_eq!(squares.len(), 3);
+/// assert_eq!(squares.get(&0), Some(&0));
+/// assert_eq!(squares.get(&1), Some(&1));
+/// assert_eq!(squares.get(&2), Some(&4));
 /// ```
 #[macro_export]
 macro_rules! map {
+    { from $iter:expr } => {
+        $iter.collect::<std::collections::HashMap<_, _>>()
+    };
     { $( ( $key:expr, $val:expr ) ),+ } => {
         {
             let mut map = std::collections::HashMap::new();
@@ -215,7 +253,9 @@ macro_rules! map {
     };
 }
 
-/// Haskell-inspired list comprehension
+/// Haskell-inspired list comprehension. Any number of `list => var` generators can be given, in
+/// which case later generators are iterated once for each element of the earlier ones (like
+/// nested loops), followed by any number of conditions
 ///
 /// # Examples
 /// ```
@@ -230,22 +270,37 @@ macro_rules! map {
 /// let l3 = list_comp!(a * 2; 0..1000 => a);
 /// let l4 = list_comp!(a; 0..2000 => a; a % 2 == 0);
 /// assert_eq!(l3, l4);
+///
+/// // Equivalent to `[(a, b) | a <- [1, 2], b <- "xy"]`
+/// let l5 = list_comp!((a, b); [1, 2] => a; "xy".chars() => b);
+/// assert_eq!(l5, [(1, 'x'), (1, 'y'), (2, 'x'), (2, 'y')]);
+///
+/// // Later generators and the conditions can use the earlier variables
+/// let l6 = list_comp!(a * b; 1..5 => a; a..5 => b; a != b; (a + b) % 2 == 1);
+/// assert_eq!(l6, [2, 4, 6, 12]);
 /// ```
 #[macro_export]
 macro_rules! list_comp {
-    [ $func:expr; $lst:expr => $var:ident $( ;$cond:expr )? ] => {
+    // Helper arms, these need to come first since `@` can't start an expression
+    (@gen $vec:ident, $func:expr; $lst:expr => $var:ident $( ;$($tail:tt)+ )?) => {
+        for $var in $lst {
+            $crate::list_comp!(@gen $vec, $func $( ;$($tail)+ )?);
+        }
+    };
+    (@gen $vec:ident, $func:expr $( ;$cond:expr )*) => {
+        $(if !$cond {continue;})*
+
+        $vec.push($func);
+    };
+    [ $func:expr; $($tail:tt)+ ] => {
         {
             let mut vec = Vec::new();
 
-            for $var in $lst {
-                $(if !$cond {continue;})?
-
-                vec.push($func);
-            }
+            $crate::list_comp!(@gen vec, $func; $($tail)+);
 
             vec
         }
-    }
+    };
 }
 
 #[cfg(test)]
@@ -290,7 +345,7 @@ mod tests {
             panic!("abc")
         }
         vec.into_iter().enumerate().for_each(|(i, v)| arr[i] = v);
-        arr;
+        let _ = arr;
     }
 
     #[test]
@@ -345,6 +400,18 @@ mod tests {
         assert_eq!(map, expected);
     }
 
+    #[test]
+    fn map_macro_from_iter() {
+        let map = map!(from ["a", "bc", "def"].into_iter().map(|s| (s, s.len())));
+
+        let mut expected = HashMap::new();
+        expected.insert("a", 1);
+        expected.insert("bc", 2);
+        expected.insert("def", 3);
+
+        assert_eq!(map, expected);
+    }
+
     #[test]
     fn list_comp() {
         let comp1 = list_comp!(a * 2; [1, 2, 3] => a);
@@ -361,6 +428,12 @@ mod tests {
             arr!([0; 50], (0; 5), (1; 5), (2; 5), (3; 5), (4; 5), (5; 5), (6; 5), (7; 5), (8; 5), (9; 5))
         );
         
+        let comp5 = list_comp!(a * 10 + b; [1, 2, 3] => a; [4, 5] => b);
+        let comp6 = list_comp!((a, b, c); 0..3 => a; 0..3 => b; 0..3 => c; a < b; b < c);
+
+        assert_eq!(comp5, vec![14, 15, 24, 25, 34, 35]);
+        assert_eq!(comp6, vec![(0, 1, 2)]);
+
         // let mut a = vec![1,2,3];
         // a.append(vec![1,5]);
     }
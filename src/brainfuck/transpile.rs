@@ -0,0 +1,160 @@
+//! Turns Brainfuck programs into equivalent Rust source code, so hot programs can be compiled
+//! instead of interpreted
+
+use crate::error::InterpreteResult;
+
+use super::{filter_code, match_brackets, optimizations::optimize};
+
+/// Generates a Rust function `run` that executes the program on a `[u8; 30000]` tape, reading
+/// from `input` and writing to `output`. The code goes through the optimization passes first and
+/// each macro-instruction becomes a single statement, e.g. `(8*p)` is
+/// `mem[dp] = mem[dp].wrapping_add(8);`. Breakpoints are dropped
+///
+/// # Examples
+/// ```
+/// use interprete_rs::brainfuck::transpile::transpile_to_rust;
+///
+/// let code = transpile_to_rust("+[-]").unwrap();
+/// assert!(code.contains("while mem[dp] != 0 {"));
+/// ```
+pub fn transpile_to_rust(input: &str) -> InterpreteResult<String> {
+    let code = filter_code(input, false)?;
+    // Reported here since dead loop elimination could otherwise drop an unmatched bracket
+    match_brackets(&code)?;
+
+    let code = optimize(code);
+
+    let mut res = String::from(
+        "pub fn run(input: &mut impl std::io::Read, output: &mut impl std::io::Write) -> \
+         std::io::Result<[u8; 30000]> {\n    let mut mem = [0u8; 30000];\n    let mut dp = 0usize;\n",
+    );
+
+    // Nesting level of the body, the function itself is one level
+    let mut depth = 1;
+    let mut iter = code.into_iter();
+
+    while let Some(c) = iter.next() {
+        let stmt = match c {
+            '+' => "mem[dp] = mem[dp].wrapping_add(1);".to_string(),
+            '-' => "mem[dp] = mem[dp].wrapping_sub(1);".to_string(),
+            '>' => "dp += 1;".to_string(),
+            '<' => "dp -= 1;".to_string(),
+            '.' => "output.write_all(&mem[dp..dp + 1])?;".to_string(),
+            ',' => "input.read_exact(&mut mem[dp..dp + 1])?;".to_string(),
+            '[' => {
+                push_line(&mut res, depth, "while mem[dp] != 0 {");
+                depth += 1;
+                continue;
+            }
+            ']' => {
+                depth = depth
+                    .checked_sub(1)
+                    .filter(|&d| d > 0)
+                    .ok_or("Detected mismatched brackets, too many ]")?;
+                "}".to_string()
+            }
+            '#' => continue,
+            '(' => {
+                let body: String = iter.by_ref().take_while(|&c| c != ')').collect();
+                macro_to_rust(&body)?
+            }
+            c => return Err(format!("Unexpected char in code: {}", c).into()),
+        };
+
+        push_line(&mut res, depth, &stmt);
+    }
+
+    if depth != 1 {
+        return Err("Detected mismatched brackets, too many [".into());
+    }
+
+    res.push_str("    Ok(mem)\n}\n");
+
+    Ok(res)
+}
+
+fn push_line(res: &mut String, depth: usize, line: &str) {
+    res.push_str(&"    ".repeat(depth));
+    res.push_str(line);
+    res.push('\n');
+}
+
+/// Statement for a macro-instruction, given the text between the parentheses. See
+/// `BrainfuckProgram::execute_macro` for what each one does
+fn macro_to_rust(body: &str) -> InterpreteResult<String> {
+    let parse = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| format!("Invalid number in macro-instruction: ({})", body))
+    };
+
+    if let Some((cnt, op)) = body.split_once('*') {
+        let cnt = parse(cnt)?;
+
+        match op {
+            "p" => Ok(format!("mem[dp] = mem[dp].wrapping_add({});", cnt % 256)),
+            "m" => Ok(format!("mem[dp] = mem[dp].wrapping_sub({});", cnt % 256)),
+            "f" => Ok(format!("dp += {};", cnt)),
+            "b" => Ok(format!("dp -= {};", cnt)),
+            _ => Err(format!("Unable to repeat opcode: {}", op).into()),
+        }
+    } else if let Some((max_left, max_right)) = body.split_once(';') {
+        let (max_left, max_right) = (parse(max_left)?, parse(max_right)?);
+
+        // `dp` is a usize, so `dp >= 0` would always hold and trip `unused_comparisons`
+        let right = format!("dp + {} <= 29999", max_right);
+        let cond = match max_left {
+            0 => right,
+            _ => format!("dp >= {} && {}", max_left, right),
+        };
+
+        Ok(format!(
+            "assert!({}, \"Data pointer out of bounds\");",
+            cond
+        ))
+    } else {
+        Err(format!("Unknown macro-instruction: ({})", body).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transpile_test() -> crate::error::InterpreTestResult {
+        let code = transpile_to_rust("++++++++[>+++<-]>.")?;
+
+        let expected = [
+            "    let mut mem = [0u8; 30000];",
+            "    mem[dp] = mem[dp].wrapping_add(8);",
+            "    while mem[dp] != 0 {",
+            "        dp += 1;",
+            "        mem[dp] = mem[dp].wrapping_add(3);",
+            "        dp -= 1;",
+            "        mem[dp] = mem[dp].wrapping_sub(1);",
+            "    }",
+            "    output.write_all(&mem[dp..dp + 1])?;",
+            "    Ok(mem)",
+        ];
+
+        for line in expected {
+            assert!(
+                code.lines().any(|l| l == line),
+                "Missing `{}` in:\n{}",
+                line,
+                code
+            );
+        }
+
+        // Moves that stray past their net displacement keep their bounds check
+        assert!(transpile_to_rust("+><")?
+            .contains("assert!(dp + 1 <= 29999, \"Data pointer out of bounds\");"));
+        assert!(transpile_to_rust("+<>")?
+            .contains("assert!(dp >= 1 && dp + 0 <= 29999, \"Data pointer out of bounds\");"));
+
+        assert!(transpile_to_rust("+[").is_err());
+        assert!(transpile_to_rust("+]").is_err());
+
+        Ok(())
+    }
+}
@@ -1,8 +1,8 @@
 use std::{
-    error::{self, Error},
+    error::Error,
     fmt::{self, Display, Formatter},
     io,
-    result::{self, Result},
+    result::Result,
 };
 
 #[derive(PartialEq, Eq, Debug)]
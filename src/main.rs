@@ -1,4 +1,2 @@
-use interprete_rs::list_comp;
-
 fn main() {
 }
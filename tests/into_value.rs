@@ -0,0 +1,120 @@
+use interprete_rs::blisp::{
+    interpreter::{AbstractType, BLispType, IntoValue, Value, ValueData},
+    lexer::Type,
+};
+
+#[derive(IntoValue)]
+struct Point {
+    x: i64,
+    y: i64,
+}
+
+#[derive(IntoValue)]
+struct Record {
+    id: u64,
+    score: f64,
+    grade: u8,
+    active: bool,
+    name: String,
+    tags: Vec<String>,
+    origin: Point,
+}
+
+#[derive(IntoValue)]
+struct Pair(u64, Vec<i64>);
+
+fn string_val(s: &str) -> Value {
+    Value::new(
+        Type::List(Box::new(Type::Char)).into(),
+        ValueData::List(s.bytes().map(Value::from).collect()),
+    )
+}
+
+#[test]
+fn simple_struct_test() {
+    let val = Value::from(Point { x: 1, y: -2 });
+
+    assert_eq!(
+        val,
+        Value::new(
+            Type::Tuple(vec![Type::Int, Type::Int]).into(),
+            ValueData::Tuple(vec![
+                Value::new(Type::Int.into(), ValueData::Int(1)),
+                Value::new(Type::Int.into(), ValueData::Int(-2)),
+            ])
+        )
+    );
+}
+
+#[test]
+fn nested_struct_test() {
+    let record = Record {
+        id: 7,
+        score: 1.5,
+        grade: b'A',
+        active: true,
+        name: "ab".to_string(),
+        tags: vec!["x".to_string()],
+        origin: Point { x: 0, y: 3 },
+    };
+
+    let string_ty = Type::List(Box::new(Type::Char));
+    let point_ty = Type::Tuple(vec![Type::Int, Type::Int]);
+
+    assert_eq!(
+        Value::from(record),
+        Value::new(
+            AbstractType::ConcreteType(Type::Tuple(vec![
+                Type::UInt,
+                Type::Float,
+                Type::Char,
+                Type::Bool,
+                string_ty.clone(),
+                Type::List(Box::new(string_ty.clone())),
+                point_ty.clone(),
+            ])),
+            ValueData::Tuple(vec![
+                Value::new(Type::UInt.into(), ValueData::UInt(7)),
+                Value::new(Type::Float.into(), ValueData::Float(1.5)),
+                Value::new(Type::Char.into(), ValueData::Char(b'A')),
+                Value::new(Type::Bool.into(), ValueData::Bool(true)),
+                string_val("ab"),
+                Value::new(
+                    Type::List(Box::new(string_ty)).into(),
+                    ValueData::List(vec![string_val("x")])
+                ),
+                Value::new(
+                    point_ty.into(),
+                    ValueData::Tuple(vec![
+                        Value::new(Type::Int.into(), ValueData::Int(0)),
+                        Value::new(Type::Int.into(), ValueData::Int(3)),
+                    ])
+                ),
+            ])
+        )
+    );
+}
+
+#[test]
+fn tuple_struct_test() {
+    // The element type of an empty Vec still comes from the Rust type
+    let val = Value::from(Pair(2, vec![]));
+
+    assert_eq!(
+        val,
+        Value::new(
+            Type::Tuple(vec![Type::UInt, Type::List(Box::new(Type::Int))]).into(),
+            ValueData::Tuple(vec![
+                Value::new(Type::UInt.into(), ValueData::UInt(2)),
+                Value::new(
+                    Type::List(Box::new(Type::Int)).into(),
+                    ValueData::List(vec![])
+                ),
+            ])
+        )
+    );
+    assert_eq!(
+        Pair::blisp_type(),
+        Type::Tuple(vec![Type::UInt, Type::List(Box::new(Type::Int))])
+    );
+}